@@ -0,0 +1,147 @@
+use crate::Result;
+use crate::bail;
+use crate::generation::{GeneratedSequence, Model, StopReason, TextGeneration, Usage};
+use crate::tokenizers::Tokenizer;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// 稽核紀錄的一筆，為 JSONL 的一行
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+    /// unix time，單位 ms
+    pub timestamp: u64,
+    pub model_id: Option<String>,
+    pub prompt: String,
+    pub response: String,
+    pub usage: Usage,
+    pub latency_ms: u64,
+    pub stop_reason: Option<StopReason>,
+}
+
+type Redact = Box<dyn Fn(&str) -> String + Send + Sync>;
+
+/// 將 prompt 與生成結果寫成 JSONL 稽核紀錄，寫入前先經過 redaction，eg: 遮蔽 email、電話
+pub struct AuditLog<W: Write> {
+    writer: W,
+    model_id: Option<String>,
+    redact: Option<Redact>,
+    enabled: bool,
+    records: usize,
+}
+
+impl AuditLog<BufWriter<File>> {
+    /// 以 append 開啟檔案，不會覆蓋之前的紀錄
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write> AuditLog<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            model_id: None,
+            redact: None,
+            enabled: true,
+            records: 0,
+        }
+    }
+
+    pub fn with_model_id(mut self, model_id: &str) -> Self {
+        self.model_id = Some(model_id.to_string());
+        self
+    }
+
+    /// prompt 與 response 寫入前會先經過 redact
+    pub fn with_redaction<F: Fn(&str) -> String + Send + Sync + 'static>(
+        mut self,
+        redact: F,
+    ) -> Self {
+        self.redact = Some(Box::new(redact));
+        self
+    }
+
+    /// 關閉時 log 與 generate 都不會寫入紀錄
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn log(
+        &mut self,
+        prompt: &str,
+        response: &str,
+        usage: Usage,
+        latency: Duration,
+        stop_reason: Option<StopReason>,
+    ) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let redact = |text: &str| match &self.redact {
+            Some(redact) => redact(text),
+            None => text.to_string(),
+        };
+        let record = AuditRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            model_id: self.model_id.clone(),
+            prompt: redact(prompt),
+            response: redact(response),
+            usage,
+            latency_ms: latency.as_millis() as u64,
+            stop_reason,
+        };
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// 以 prompt 生成一次並寫入紀錄，回傳 decode 後的文字與生成結果
+    pub fn generate<M: Model>(
+        &mut self,
+        generation: &mut TextGeneration<M>,
+        tokenizer: &Tokenizer,
+        prompt: &str,
+        max_new_tokens: usize,
+    ) -> Result<(String, GeneratedSequence)> {
+        let ids = match tokenizer.tokenizer().encode(prompt, true) {
+            Ok(encoding) => encoding.get_ids().to_vec(),
+            Err(err) => bail!("cannot encode: {err}"),
+        };
+        let start = Instant::now();
+        let sequence = generation.generate_n(&ids, max_new_tokens, 1)?.remove(0);
+        let latency = start.elapsed();
+        let response = tokenizer.decode(&sequence.tokens)?;
+        self.log(
+            prompt,
+            &response,
+            Usage::new(ids.len(), sequence.tokens.len()),
+            latency,
+            Some(sequence.stop_reason),
+        )?;
+        Ok((response, sequence))
+    }
+
+    /// 已寫入的紀錄數
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
@@ -0,0 +1,124 @@
+use crate::{Result, bail, generation::Model};
+use candle_core::{Device, Tensor};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// 測試的 batch size、prompt 長度與 decode token 數
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchConfig {
+    pub batch_sizes: Vec<usize>,
+    pub prompt_lens: Vec<usize>,
+    pub decode_tokens: usize,
+    /// 每組設定重複次數，取平均
+    pub repeats: usize,
+    /// 正式量測前的暖身次數
+    pub warmup: usize,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            batch_sizes: vec![1],
+            prompt_lens: vec![128, 512],
+            decode_tokens: 128,
+            repeats: 3,
+            warmup: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchResult {
+    pub batch_size: usize,
+    pub prompt_len: usize,
+    pub decode_tokens: usize,
+    pub prefill_secs: f64,
+    pub decode_secs: f64,
+    pub prefill_tokens_per_sec: f64,
+    pub decode_tokens_per_sec: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchReport {
+    pub device: String,
+    pub results: Vec<BenchResult>,
+}
+
+impl BenchReport {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// 量測模型 prefill 與 decode 的 tokens/sec
+pub fn run<M: Model>(model: &mut M, device: &Device, config: &BenchConfig) -> Result<BenchReport> {
+    if config.prompt_lens.contains(&0) {
+        bail!("prompt_lens must be greater than 0");
+    }
+    let mut results = vec![];
+    for &batch_size in &config.batch_sizes {
+        for &prompt_len in &config.prompt_lens {
+            for _ in 0..config.warmup {
+                measure(model, device, batch_size, prompt_len, config.decode_tokens)?;
+            }
+
+            let repeats = config.repeats.max(1);
+            let (mut prefill_secs, mut decode_secs) = (0., 0.);
+            for _ in 0..repeats {
+                let (prefill, decode) =
+                    measure(model, device, batch_size, prompt_len, config.decode_tokens)?;
+                prefill_secs += prefill;
+                decode_secs += decode;
+            }
+            let prefill_secs = prefill_secs / repeats as f64;
+            let decode_secs = decode_secs / repeats as f64;
+
+            results.push(BenchResult {
+                batch_size,
+                prompt_len,
+                decode_tokens: config.decode_tokens,
+                prefill_secs,
+                decode_secs,
+                prefill_tokens_per_sec: per_sec(batch_size * prompt_len, prefill_secs),
+                decode_tokens_per_sec: per_sec(batch_size * config.decode_tokens, decode_secs),
+            });
+        }
+    }
+
+    Ok(BenchReport {
+        device: format!("{:?}", device.location()),
+        results,
+    })
+}
+
+fn per_sec(tokens: usize, secs: f64) -> f64 {
+    if secs > 0. { tokens as f64 / secs } else { 0. }
+}
+
+// 回傳 (prefill 秒數, decode 秒數)
+fn measure<M: Model>(
+    model: &mut M,
+    device: &Device,
+    batch_size: usize,
+    prompt_len: usize,
+    decode_tokens: usize,
+) -> Result<(f64, f64)> {
+    model.reset();
+    let prompt = Tensor::zeros((batch_size, prompt_len), candle_core::DType::U32, device)?;
+    let step = Tensor::zeros((batch_size, 1), candle_core::DType::U32, device)?;
+
+    let start = Instant::now();
+    model.forward(&prompt, 0)?;
+    device.synchronize()?;
+    let prefill = start.elapsed().as_secs_f64();
+
+    let start = Instant::now();
+    for pos in prompt_len..prompt_len + decode_tokens {
+        model.forward(&step, pos)?;
+    }
+    device.synchronize()?;
+    let decode = start.elapsed().as_secs_f64();
+
+    model.reset();
+    Ok((prefill, decode))
+}
@@ -0,0 +1,250 @@
+use crate::Result;
+use crate::chat_template::ChatTemplate;
+use crate::generation::{Checkpoint, Model, Step, TextGeneration};
+use crate::tokenizers::Tokenizer;
+use candle_core::Device;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::fs::File;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// `/save` 寫入的對話，可以用 `ChatRepl::resume` 繼續
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatSession {
+    pub system: Option<String>,
+    /// role/content 的訊息，不包含 system 訊息
+    pub messages: Vec<Value>,
+    pub checkpoint: Checkpoint,
+}
+
+impl ChatSession {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = File::create(path)?;
+        Ok(serde_json::to_writer(file, self)?)
+    }
+}
+
+/// 多輪對話的 REPL，用於快速測試模型。
+///
+/// 每一輪以 chat template render 完整的對話，由 `apply_incremental` 重複使用 kv cache 中
+/// 相同的前綴，回覆逐 token 串流輸出。支援的指令:
+/// - `/reset`: 清除對話，保留 system prompt
+/// - `/system <prompt>`: 設定 system prompt，沒有 prompt 時清除
+/// - `/save <path>`: 將對話與生成狀態寫入檔案
+/// - `/exit`: 結束，讀到 EOF 也會結束
+pub struct ChatRepl<M: Model> {
+    generation: TextGeneration<M>,
+    tokenizer: Tokenizer,
+    template: ChatTemplate,
+    system: Option<String>,
+    messages: Vec<Value>,
+    max_new_tokens: usize,
+    prompt: String,
+}
+
+impl<M: Model> ChatRepl<M> {
+    /// 預設每輪最多生成 512 個 token
+    pub fn new(
+        generation: TextGeneration<M>,
+        tokenizer: Tokenizer,
+        template: ChatTemplate,
+    ) -> Self {
+        Self {
+            generation,
+            tokenizer,
+            template,
+            system: None,
+            messages: vec![],
+            max_new_tokens: 512,
+            prompt: "> ".to_string(),
+        }
+    }
+
+    /// 從 `/save` 的檔案繼續對話。logits transform 與 stopping criteria 需要重新加入
+    pub fn resume<P: AsRef<Path>>(
+        path: P,
+        model: M,
+        device: Device,
+        tokenizer: Tokenizer,
+        template: ChatTemplate,
+    ) -> Result<Self> {
+        let session = ChatSession::from_file(path)?;
+        let max_new_tokens = session.checkpoint.max_new_tokens;
+        let generation = TextGeneration::from_checkpoint(session.checkpoint, model, device)?;
+        let mut repl = Self::new(generation, tokenizer, template);
+        repl.system = session.system;
+        repl.messages = session.messages;
+        repl.max_new_tokens = max_new_tokens;
+        Ok(repl)
+    }
+
+    pub fn with_system_prompt(mut self, system: &str) -> Self {
+        self.system = Some(system.to_string());
+        self
+    }
+
+    pub fn with_max_new_tokens(mut self, max_new_tokens: usize) -> Self {
+        self.max_new_tokens = max_new_tokens;
+        self
+    }
+
+    /// 每次讀取輸入前輸出的提示，預設為 "> "
+    pub fn with_prompt(mut self, prompt: &str) -> Self {
+        self.prompt = prompt.to_string();
+        self
+    }
+
+    pub fn system(&self) -> Option<&str> {
+        self.system.as_deref()
+    }
+
+    pub fn set_system(&mut self, system: Option<&str>) {
+        self.system = system.map(str::to_string);
+    }
+
+    /// 目前的對話，不包含 system 訊息
+    pub fn messages(&self) -> &[Value] {
+        &self.messages
+    }
+
+    pub fn generation(&self) -> &TextGeneration<M> {
+        &self.generation
+    }
+
+    pub fn generation_mut(&mut self) -> &mut TextGeneration<M> {
+        &mut self.generation
+    }
+
+    /// 清除對話，保留 system prompt。kv cache 不需要清除，下一輪會依前綴判斷
+    pub fn reset(&mut self) {
+        self.messages.clear();
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        ChatSession {
+            system: self.system.clone(),
+            messages: self.messages.clone(),
+            checkpoint: self.generation.snapshot(),
+        }
+        .save(path)
+    }
+
+    // system 訊息加上目前的對話
+    fn render(&self) -> Result<String> {
+        let mut messages = vec![];
+        if let Some(system) = &self.system {
+            messages.push(json!({ "role": "system", "content": system }));
+        }
+        messages.extend(self.messages.iter().cloned());
+        self.template.apply(json!({
+            "messages": messages,
+            "add_generation_prompt": true,
+        }))
+    }
+
+    /// 送出一則 user 訊息，回覆串流寫入 output，回傳完整的回覆
+    pub fn send<W: Write>(&mut self, content: &str, output: &mut W) -> Result<String> {
+        self.messages
+            .push(json!({ "role": "user", "content": content }));
+        let reply = match self.reply(output) {
+            Ok(reply) => reply,
+            Err(err) => {
+                // 生成失敗時移除這則訊息，可以重新輸入
+                self.messages.pop();
+                return Err(err);
+            }
+        };
+        self.messages
+            .push(json!({ "role": "assistant", "content": reply }));
+        Ok(reply)
+    }
+
+    fn reply<W: Write>(&mut self, output: &mut W) -> Result<String> {
+        let prompt = self.render()?;
+        let ids = self.tokenizer.encode_untruncated(&prompt, false)?;
+        self.tokenizer.clear();
+        let mut step = self
+            .generation
+            .apply_incremental(ids.get_ids(), self.max_new_tokens)?;
+        while let Step::Token(token) = step {
+            if let Some(text) = self.tokenizer.next_token(token)? {
+                output.write_all(text.as_bytes())?;
+                output.flush()?;
+            }
+            step = self.generation.next()?;
+        }
+        if let Some(rest) = self.tokenizer.decode_rest()? {
+            output.write_all(rest.as_bytes())?;
+        }
+        writeln!(output)?;
+        self.tokenizer.decode_all()
+    }
+
+    /// 讀取 input 的每一行，直到 `/exit` 或 EOF
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> Result<()> {
+        let mut lines = input.lines();
+        loop {
+            write!(output, "{}", self.prompt)?;
+            output.flush()?;
+            let Some(line) = lines.next() else {
+                return Ok(());
+            };
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if !self.command(line, &mut output)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // 處理指令或送出訊息，回傳 false 代表結束
+    fn command<W: Write>(&mut self, line: &str, output: &mut W) -> Result<bool> {
+        let Some(command) = line.strip_prefix('/') else {
+            self.send(line, output)?;
+            return Ok(true);
+        };
+        let (name, arg) = match command.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (command, ""),
+        };
+        match name {
+            "exit" | "quit" => return Ok(false),
+            "reset" => {
+                self.reset();
+                writeln!(output, "conversation cleared")?;
+            }
+            "system" if arg.is_empty() => {
+                self.set_system(None);
+                writeln!(output, "system prompt cleared")?;
+            }
+            "system" => {
+                self.set_system(Some(arg));
+                writeln!(output, "system prompt set")?;
+            }
+            "save" if arg.is_empty() => writeln!(output, "usage: /save <path>")?,
+            "save" => {
+                self.save(arg)?;
+                writeln!(output, "saved to {arg}")?;
+            }
+            _ => writeln!(
+                output,
+                "unknown command /{name}, available: /reset, /system, /save, /exit"
+            )?,
+        }
+        Ok(true)
+    }
+}
+
+/// 同 `ChatRepl::run`，使用 stdin 與 stdout
+pub fn chat_loop<M: Model>(repl: &mut ChatRepl<M>) -> Result<()> {
+    repl.run(std::io::stdin().lock(), std::io::stdout())
+}
@@ -1,13 +1,53 @@
 use std::fs::File;
 
-use crate::repo::Repo;
-use crate::{Result, error};
-use minijinja::{Environment, Template};
+use crate::repo::{Repo, found};
+use crate::tokenizers::SpecialTokens;
+use crate::{Result, bail, error};
+use candle_core::quantized::gguf_file;
+use minijinja::value::{Value, merge_maps};
+use minijinja::{Environment, ErrorKind, Template};
 use minijinja_contrib::pycompat;
 
 #[derive(Clone)]
 pub struct ChatTemplate {
     template: Template<'static, 'static>,
+    globals: Value,
+    // 每次 render 都會加入的變數，eg: enable_thinking、date_string
+    defaults: Value,
+    system_prompt: Option<String>,
+    system_role: SystemRole,
+    // 錯誤訊息中的名稱，eg: model id
+    name: String,
+}
+
+/// system 訊息的處理方式，Gemma、舊版 Mistral 等 template 遇到 system role 會 raise_exception
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SystemRole {
+    /// 直接交給 template
+    #[default]
+    Native,
+    /// 合併到下一則 user 訊息的開頭，以空行分隔；後面沒有 user 訊息時改為 user 訊息
+    MergeIntoUser,
+    /// 移除 system 訊息
+    Drop,
+}
+
+impl SystemRole {
+    /// template 不接受 system role 時回傳 MergeIntoUser
+    pub fn detect(template: &str) -> Self {
+        let rejects_system = template.contains("raise_exception")
+            && [
+                "System role not supported",
+                "Only user and assistant roles are supported",
+            ]
+            .iter()
+            .any(|msg| template.contains(msg));
+        if rejects_system {
+            Self::MergeIntoUser
+        } else {
+            Self::Native
+        }
+    }
 }
 
 impl ChatTemplate {
@@ -15,6 +55,13 @@ impl ChatTemplate {
         let mut env = Environment::new();
         // 加入 python 相容的 function, like str.startswith, str.endswith
         env.set_unknown_method_callback(pycompat::unknown_method_callback);
+        // transformers 提供給 template 的 function
+        env.add_function(
+            "raise_exception",
+            |msg: String| -> std::result::Result<Value, minijinja::Error> {
+                Err(minijinja::Error::new(ErrorKind::InvalidOperation, msg))
+            },
+        );
         env
     }
 
@@ -23,24 +70,448 @@ impl ChatTemplate {
 
         let template_str = template.as_ref().to_string().into_boxed_str();
         Ok(ChatTemplate {
+            system_role: SystemRole::detect(&template_str),
             template: Box::leak(env).template_from_str(Box::leak(template_str))?,
+            globals: Value::from(()),
+            defaults: Value::from(()),
+            system_prompt: None,
+            name: "chat_template".to_string(),
         })
     }
 
+    /// render 失敗時錯誤訊息中的名稱，eg: model id
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 將 bos_token、eos_token 等加入 template 變數，呼叫 apply 時傳入的值優先
+    pub fn with_special_tokens(mut self, special_tokens: &SpecialTokens) -> Self {
+        self.globals = Value::from_serialize(special_tokens.template_globals());
+        self
+    }
+
+    /// 加入 template 變數的預設值，eg: Qwen3 的 `enable_thinking=false`。
+    /// 可以呼叫多次，相同的變數以後面的為準；呼叫 apply 時傳入的值優先
+    pub fn with_context<S: serde::Serialize>(mut self, ctx: S) -> Self {
+        self.defaults = merge_maps([self.defaults, Value::from_serialize(ctx)]);
+        self
+    }
+
+    /// 加入今天的日期 `date_string`，eg: "16 Oct 2026"，同 Llama 3.1 的 template
+    pub fn with_current_date(self) -> Self {
+        let days = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() / 86400);
+        self.with_context(serde_json::json!({ "date_string": date_string(days as i64) }))
+    }
+
+    /// messages 的第一則不是 system 訊息時，在最前面加入 system prompt
+    pub fn with_system_prompt(mut self, system_prompt: &str) -> Self {
+        self.system_prompt = Some(system_prompt.to_string());
+        self
+    }
+
+    /// 覆寫 system 訊息的處理方式，預設依 template 自動判斷
+    pub fn with_system_role(mut self, system_role: SystemRole) -> Self {
+        self.system_role = system_role;
+        self
+    }
+
+    pub fn system_role(&self) -> SystemRole {
+        self.system_role
+    }
+
+    /// 使用內建的 template
+    pub fn from_kind(kind: ChatTemplateKind) -> Result<Self> {
+        Ok(Self::new(kind.template())?
+            .with_special_tokens(&kind.special_tokens())
+            .with_name(&format!("{kind:?}")))
+    }
+
+    /// 以範例對話 (system、user、assistant、user) 檢查 template 可以 render，
+    /// 建議載入時呼叫，避免在處理 request 時才發現錯誤
+    pub fn lint(&self) -> Result<()> {
+        let messages = [
+            ("system", "You are a helpful assistant."),
+            ("user", "Hello"),
+            ("assistant", "Hi! How can I help?"),
+            ("user", "Tell me a joke."),
+        ];
+        self.test_render(&messages)?;
+        self.test_render(&messages[1..])?;
+        Ok(())
+    }
+
+    /// 以 (role, content) render 並加上 generation prompt，方便與 transformers 的
+    /// `apply_chat_template(messages, tokenize=False, add_generation_prompt=True)` 比對
+    pub fn test_render(&self, messages: &[(&str, &str)]) -> Result<String> {
+        let messages = messages
+            .iter()
+            .map(|(role, content)| serde_json::json!({ "role": role, "content": content }))
+            .collect::<Vec<_>>();
+        self.apply(serde_json::json!({
+            "messages": messages,
+            "add_generation_prompt": true,
+        }))
+    }
+
     pub fn apply<S: serde::Serialize>(&self, msg: S) -> Result<String> {
-        Ok(self.template.render(msg)?)
+        let msg = if self.system_prompt.is_some() || self.system_role != SystemRole::Native {
+            let mut msg = serde_json::to_value(msg)?;
+            if let Some(system_prompt) = &self.system_prompt {
+                insert_system_prompt(&mut msg, system_prompt);
+            }
+            remap_system_role(&mut msg, self.system_role);
+            Value::from_serialize(msg)
+        } else {
+            Value::from_serialize(msg)
+        };
+        let ctx = merge_maps([self.globals.clone(), self.defaults.clone(), msg.clone()]);
+        self.template
+            .render(ctx)
+            .map_err(|err| self.render_error(&err, &msg))
+    }
+
+    // 加上位置與出錯的訊息
+    fn render_error(&self, err: &minijinja::Error, msg: &Value) -> error::Error {
+        let column = err.range().map(|range| {
+            let source = self.template.source();
+            let start = range.start.min(source.len());
+            let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+            source[line_start..start].chars().count() + 1
+        });
+        let message = match err.detail() {
+            Some(detail) => format!("{}: {detail}", err.kind()),
+            None => err.kind().to_string(),
+        };
+        error::Error::TemplateRender {
+            name: self.name.clone(),
+            line: err.line(),
+            column,
+            message_index: self.failing_message(msg),
+            message,
+        }
+    }
+
+    // 逐一加入訊息 render，回傳第一個失敗的訊息
+    fn failing_message(&self, msg: &Value) -> Option<usize> {
+        let mut msg = serde_json::to_value(msg).ok()?;
+        let messages = msg.get("messages")?.as_array()?.clone();
+        msg["add_generation_prompt"] = false.into();
+        (0..messages.len()).find(|&i| {
+            msg["messages"] = messages[..=i].to_vec().into();
+            let ctx = merge_maps([
+                self.globals.clone(),
+                self.defaults.clone(),
+                Value::from_serialize(&msg),
+            ]);
+            self.template.render(ctx).is_err()
+        })
+    }
+
+    /// 指定 assistant 回覆的開頭，eg: "Sure, here is the JSON:"，模型會接著 prefill 繼續生成。
+    ///
+    /// 作法同 transformers 的 continue_final_message: 把 prefill 當成最後一則 assistant
+    /// 訊息 render，再把 prefill 之後的結尾 (eg: `<|im_end|>`) 切掉
+    pub fn apply_with_prefill<S: serde::Serialize>(&self, msg: S, prefill: &str) -> Result<String> {
+        let mut ctx = serde_json::to_value(msg)?;
+        let Some(obj) = ctx.as_object_mut() else {
+            bail!("chat template context must be a map")
+        };
+
+        let message = serde_json::json!({ "role": "assistant", "content": prefill });
+        match obj.get_mut("messages").and_then(|m| m.as_array_mut()) {
+            Some(messages) => messages.push(message),
+            None => bail!("chat template context without messages"),
+        }
+
+        if !prefill.trim().is_empty() {
+            obj.insert("add_generation_prompt".to_string(), false.into());
+            let prompt = self.apply(&ctx)?;
+            // 有些 template 會 trim 訊息內容
+            let found = prompt
+                .rfind(prefill)
+                .map(|i| i + prefill.len())
+                .or_else(|| {
+                    prompt
+                        .rfind(prefill.trim())
+                        .map(|i| i + prefill.trim().len())
+                });
+            if let Some(end) = found {
+                return Ok(prompt[..end].to_string());
+            }
+        }
+
+        // 找不到 prefill 時，改用 generation prompt 後直接接上 prefill
+        let obj = ctx.as_object_mut().unwrap();
+        if let Some(messages) = obj.get_mut("messages").and_then(|m| m.as_array_mut()) {
+            messages.pop();
+        }
+        obj.insert("add_generation_prompt".to_string(), true.into());
+        Ok(self.apply(&ctx)? + prefill)
     }
 }
 
+fn insert_system_prompt(msg: &mut serde_json::Value, system_prompt: &str) {
+    let Some(messages) = msg.get_mut("messages").and_then(|m| m.as_array_mut()) else {
+        return;
+    };
+    let has_system = messages
+        .first()
+        .and_then(|m| m.get("role"))
+        .is_some_and(|role| role == "system");
+    if !has_system {
+        messages.insert(
+            0,
+            serde_json::json!({ "role": "system", "content": system_prompt }),
+        );
+    }
+}
+
+fn remap_system_role(msg: &mut serde_json::Value, system_role: SystemRole) {
+    if system_role == SystemRole::Native {
+        return;
+    }
+    let Some(messages) = msg.get_mut("messages").and_then(|m| m.as_array_mut()) else {
+        return;
+    };
+    let is_system = |m: &serde_json::Value| m.get("role").is_some_and(|role| role == "system");
+    if system_role == SystemRole::Drop {
+        messages.retain(|m| !is_system(m));
+        return;
+    }
+
+    let mut remapped = Vec::with_capacity(messages.len());
+    let mut pending: Vec<String> = vec![];
+    for mut message in messages.drain(..) {
+        if is_system(&message) {
+            // 只合併文字內容，其他格式 (eg: 多模態的 list) 交給 template 處理
+            if let Some(content) = message.get("content").and_then(|c| c.as_str()) {
+                pending.push(content.to_string());
+                continue;
+            }
+        } else if !pending.is_empty()
+            && message.get("role").is_some_and(|role| role == "user")
+            && let Some(content) = message.get("content").and_then(|c| c.as_str())
+        {
+            pending.push(content.to_string());
+            message["content"] = pending.join("\n\n").into();
+            pending.clear();
+        }
+        remapped.push(message);
+    }
+    if !pending.is_empty() {
+        remapped.push(serde_json::json!({ "role": "user", "content": pending.join("\n\n") }));
+    }
+    *messages = remapped;
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// 1970-01-01 起的天數轉成 "%d %b %Y"
+// https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn date_string(days: i64) -> String {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{day:02} {} {year}", MONTHS[month as usize - 1])
+}
+
 pub fn from_pretrained<R: Repo>(repo: &R) -> Result<ChatTemplate> {
-    let tokenizer_config = repo.tokenizer_config_file()?;
+    let missing = || error::Error::ChatTemplateMissing {
+        model_id: repo.model_id().to_string(),
+    };
+    let tokenizer_config = match found(repo.tokenizer_config_file())? {
+        Some(file) if file.exists() => file,
+        _ => return Err(missing()),
+    };
     let tokenizer_config: serde_json::Value =
         serde_json::from_reader(File::open(tokenizer_config)?)?;
 
     let chat_template = tokenizer_config
         .get("chat_template")
         .and_then(|v| v.as_str())
-        .ok_or(error::Error::msg("chat_template not found"))?;
+        .ok_or_else(missing)?;
+
+    Ok(ChatTemplate::new(chat_template)?
+        .with_special_tokens(&repo.special_tokens()?)
+        .with_name(repo.model_id()))
+}
 
-    ChatTemplate::new(chat_template)
+/// repo 沒有 chat_template 時，改用 fallback；其他錯誤 (eg: 網路、gated repo、template 語法) 直接回傳
+pub fn from_pretrained_or<R: Repo>(repo: &R, fallback: ChatTemplateKind) -> Result<ChatTemplate> {
+    match from_pretrained(repo) {
+        Err(error::Error::ChatTemplateMissing { .. }) => {
+            let special_tokens = repo.special_tokens()?;
+            let mut template = ChatTemplate::from_kind(fallback)?;
+            if special_tokens.bos_token.is_some() || special_tokens.eos_token.is_some() {
+                template = template.with_special_tokens(&special_tokens);
+            }
+            Ok(template)
+        }
+        result => result,
+    }
+}
+
+/// 使用 GGUF 中的 `tokenizer.chat_template`。沒有時依序使用 fallback、
+/// 由 `general.name` 與 `general.architecture` 推測的內建 template
+pub fn from_gguf(
+    content: &gguf_file::Content,
+    fallback: Option<ChatTemplateKind>,
+) -> Result<ChatTemplate> {
+    let string = |key: &str| {
+        content
+            .metadata
+            .get(key)
+            .and_then(|v| v.to_string().ok())
+            .map(String::as_str)
+    };
+    let special_tokens = gguf_special_tokens(content);
+    let name = string("general.name");
+    if let Some(template) = string("tokenizer.chat_template") {
+        return Ok(ChatTemplate::new(template)?
+            .with_special_tokens(&special_tokens)
+            .with_name(name.unwrap_or("gguf")));
+    }
+
+    let kind = fallback.or_else(|| {
+        [name, string("general.architecture")]
+            .into_iter()
+            .flatten()
+            .find_map(ChatTemplateKind::guess)
+    });
+    let Some(kind) = kind else {
+        return Err(error::Error::ChatTemplateMissing {
+            model_id: name.unwrap_or("gguf").to_string(),
+        });
+    };
+    let mut template = ChatTemplate::from_kind(kind)?;
+    if special_tokens.bos_token.is_some() || special_tokens.eos_token.is_some() {
+        template = template.with_special_tokens(&special_tokens);
+    }
+    Ok(template)
+}
+
+// tokenizer.ggml.tokens 中 bos、eos 的文字
+fn gguf_special_tokens(content: &gguf_file::Content) -> SpecialTokens {
+    let token = |key: &str| {
+        let id = content.metadata.get(key)?.to_u32().ok()?;
+        let tokens = content
+            .metadata
+            .get("tokenizer.ggml.tokens")?
+            .to_vec()
+            .ok()?;
+        tokens.get(id as usize)?.to_string().ok().cloned()
+    };
+    SpecialTokens {
+        bos_token: token("tokenizer.ggml.bos_token_id"),
+        eos_token: token("tokenizer.ggml.eos_token_id"),
+        ..Default::default()
+    }
+}
+
+/// 內建的 chat template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatTemplateKind {
+    Llama3,
+    /// Qwen2.5，沒有 system 訊息時會加上預設的 system prompt
+    Qwen,
+    ChatML,
+    /// Mistral Instruct v0.1/v0.2，只支援 user/assistant 交替
+    Mistral,
+    /// Gemma，不支援 system role，system 訊息會合併到 user 訊息
+    Gemma,
+}
+
+impl ChatTemplateKind {
+    pub fn template(&self) -> &'static str {
+        match self {
+            Self::Llama3 => include_str!("chat_templates/llama3.jinja"),
+            Self::Qwen => include_str!("chat_templates/qwen.jinja"),
+            Self::ChatML => include_str!("chat_templates/chatml.jinja"),
+            Self::Mistral => include_str!("chat_templates/mistral.jinja"),
+            Self::Gemma => include_str!("chat_templates/gemma.jinja"),
+        }
+    }
+
+    /// template 預設使用的 bos_token、eos_token
+    pub fn special_tokens(&self) -> SpecialTokens {
+        let (bos, eos) = match self {
+            Self::Llama3 => (Some("<|begin_of_text|>"), "<|eot_id|>"),
+            Self::Qwen | Self::ChatML => (None, "<|im_end|>"),
+            Self::Mistral => (Some("<s>"), "</s>"),
+            Self::Gemma => (Some("<bos>"), "<eos>"),
+        };
+        SpecialTokens {
+            bos_token: bos.map(String::from),
+            eos_token: Some(eos.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// 依名稱選擇，不分大小寫，eg: "chatml"、"llama3"、"mistral-instruct"
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase().replace(['-', '_', ' ', '.'], "");
+        match name.as_str() {
+            "llama3" | "llama31" | "llama32" | "llama33" => Some(Self::Llama3),
+            "qwen" | "qwen2" | "qwen25" => Some(Self::Qwen),
+            "chatml" => Some(Self::ChatML),
+            "mistral" | "mistralinstruct" => Some(Self::Mistral),
+            "gemma" | "gemma2" => Some(Self::Gemma),
+            _ => None,
+        }
+    }
+
+    /// 由模型名稱或架構推測，eg: "Meta-Llama-3.1-8B-Instruct"、"qwen2"
+    pub fn guess(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase().replace(['-', '_', ' ', '.'], "");
+        if name.contains("llama3") {
+            Some(Self::Llama3)
+        } else if name.contains("qwen") {
+            Some(Self::Qwen)
+        } else if name.contains("mistral") || name.contains("mixtral") {
+            Some(Self::Mistral)
+        } else if name.contains("gemma") {
+            Some(Self::Gemma)
+        } else if ["chatml", "hermes", "openhermes", "dolphin"]
+            .iter()
+            .any(|n| name.contains(n))
+        {
+            Some(Self::ChatML)
+        } else {
+            None
+        }
+    }
+
+    /// 依 template 中的特殊標記判斷格式
+    pub fn detect(template: &str) -> Option<Self> {
+        if template.contains("<|start_header_id|>") {
+            Some(Self::Llama3)
+        } else if template.contains("<start_of_turn>") {
+            Some(Self::Gemma)
+        } else if template.contains("[INST]") {
+            Some(Self::Mistral)
+        } else if template.contains("<|im_start|>") {
+            if template.contains("Qwen") {
+                Some(Self::Qwen)
+            } else {
+                Some(Self::ChatML)
+            }
+        } else {
+            None
+        }
+    }
 }
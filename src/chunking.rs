@@ -0,0 +1,156 @@
+use crate::tokenizers::Tokenizer;
+use crate::{Result, bail};
+
+/// 切割後的文字片段，start、end 為在原文中的 byte 位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+    /// token 數
+    pub tokens: usize,
+}
+
+impl Chunk {
+    fn new(text: &str, start: usize, end: usize, tokens: usize) -> Self {
+        Self {
+            text: text[start..end].to_string(),
+            start,
+            end,
+            tokens,
+        }
+    }
+}
+
+fn offsets(tokenizer: &Tokenizer, text: &str) -> Result<Vec<(usize, usize)>> {
+    // 整份文件都要 encode，不能被 model_max_length 截斷
+    let encoding = tokenizer.encode_untruncated(text, false)?;
+    Ok(encoding.get_offsets().to_vec())
+}
+
+/// 依 token 數切割，每段最多 max_tokens 個 token，相鄰兩段重疊 overlap 個 token。
+/// 以 tokenizer 的 offset 對應回原文，不會因為 decode 而改變文字
+pub fn split_by_tokens(
+    text: &str,
+    tokenizer: &Tokenizer,
+    max_tokens: usize,
+    overlap: usize,
+) -> Result<Vec<Chunk>> {
+    if overlap >= max_tokens {
+        bail!("overlap ({overlap}) must be less than max_tokens ({max_tokens})");
+    }
+    let offsets = offsets(tokenizer, text)?;
+    let mut chunks = vec![];
+    let mut start = 0;
+    while start < offsets.len() {
+        let end = (start + max_tokens).min(offsets.len());
+        // byte-level BPE 的 token 可能只包含半個字，調整到字元邊界
+        let byte_start = floor_char_boundary(text, offsets[start].0);
+        let byte_end = ceil_char_boundary(text, offsets[end - 1].1);
+        chunks.push(Chunk::new(text, byte_start, byte_end, end - start));
+        if end == offsets.len() {
+            break;
+        }
+        start += max_tokens - overlap;
+    }
+    Ok(chunks)
+}
+
+/// 以句尾標點 (含中日文的。！？) 與換行切句，每句包含結尾的標點與空白，
+/// 所有句子接起來等於原文
+pub fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = vec![];
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        if !matches!(c, '.' | '!' | '?' | '。' | '！' | '？' | '\n') {
+            continue;
+        }
+        // 連續的標點與之後的空白都算在同一句
+        while let Some(&(_, next)) = chars.peek() {
+            if matches!(next, '.' | '!' | '?' | '。' | '！' | '？') || next.is_whitespace() {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let end = chars.peek().map_or(text.len(), |&(i, _)| i);
+        sentences.push(&text[start..end]);
+        start = end;
+    }
+    if start < text.len() {
+        sentences.push(&text[start..]);
+    }
+    sentences
+}
+
+/// 以句子為單位組成不超過 max_tokens 的片段，單句超過時再依 token 切割
+pub fn split_by_sentences(
+    text: &str,
+    tokenizer: &Tokenizer,
+    max_tokens: usize,
+) -> Result<Vec<Chunk>> {
+    if max_tokens == 0 {
+        bail!("max_tokens must be greater than 0");
+    }
+    let mut chunks = vec![];
+    // 目前累積的範圍與 token 數
+    let mut current: Option<(usize, usize, usize)> = None;
+    let mut pos = 0;
+    for sentence in split_sentences(text) {
+        let start = pos;
+        pos += sentence.len();
+        let tokens = offsets(tokenizer, sentence)?.len();
+        if tokens == 0 {
+            continue;
+        }
+
+        if let Some((chunk_start, _, count)) = current {
+            if count + tokens <= max_tokens {
+                current = Some((chunk_start, pos, count + tokens));
+                continue;
+            }
+            push_trimmed(&mut chunks, text, current.take().unwrap());
+        }
+
+        if tokens <= max_tokens {
+            current = Some((start, pos, tokens));
+        } else {
+            for chunk in split_by_tokens(sentence, tokenizer, max_tokens, 0)? {
+                chunks.push(Chunk::new(
+                    text,
+                    start + chunk.start,
+                    start + chunk.end,
+                    chunk.tokens,
+                ));
+            }
+        }
+    }
+    if let Some(current) = current {
+        push_trimmed(&mut chunks, text, current);
+    }
+    Ok(chunks)
+}
+
+fn push_trimmed(chunks: &mut Vec<Chunk>, text: &str, (start, end, tokens): (usize, usize, usize)) {
+    let s = &text[start..end];
+    let start = start + (s.len() - s.trim_start().len());
+    let end = end - (s.len() - s.trim_end().len());
+    chunks.push(Chunk::new(text, start, end.max(start), tokens));
+}
+
+pub(crate) fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+pub(crate) fn ceil_char_boundary(text: &str, mut index: usize) -> usize {
+    index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
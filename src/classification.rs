@@ -0,0 +1,53 @@
+use crate::Result;
+use crate::config::{ModelConfig, ProblemType};
+use candle_core::{DType, Tensor};
+
+/// label 與分數
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelScore {
+    pub label: String,
+    pub score: f32,
+}
+
+/// 將分類模型的 logits 依 problem_type 轉成每個 label 的分數，由高到低。
+/// single label 使用 softmax，multi label 使用 sigmoid，regression 為原本的 logits
+pub fn scores(
+    logits: &Tensor,
+    problem_type: ProblemType,
+    labels: &[String],
+) -> Result<Vec<LabelScore>> {
+    let logits = logits.flatten_all()?.to_dtype(DType::F32)?;
+    let scores = match problem_type {
+        ProblemType::SingleLabel => candle_nn::ops::softmax_last_dim(&logits)?,
+        ProblemType::MultiLabel => candle_nn::ops::sigmoid(&logits)?,
+        ProblemType::Regression => logits,
+    };
+    let mut scores: Vec<LabelScore> = scores
+        .to_vec1::<f32>()?
+        .into_iter()
+        .enumerate()
+        .map(|(i, score)| LabelScore {
+            label: labels
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| format!("LABEL_{i}")),
+            score,
+        })
+        .collect();
+    scores.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(scores)
+}
+
+/// 使用 config.json 的 problem_type 與 id2label
+pub fn scores_from_config(logits: &Tensor, config: &ModelConfig) -> Result<Vec<LabelScore>> {
+    scores(logits, config.problem_type(), &config.id2label())
+}
+
+/// multi label 時分數不低於 threshold 的 label，transformers 預設為 0.5
+pub fn labels_above(scores: &[LabelScore], threshold: f32) -> Vec<&str> {
+    scores
+        .iter()
+        .filter(|s| s.score >= threshold)
+        .map(|s| s.label.as_str())
+        .collect()
+}
@@ -0,0 +1,367 @@
+use crate::{Result, bail};
+use serde_json::Value;
+use std::{fs::File, path::Path};
+
+/// config.json 的寬鬆封裝，只取常用欄位，其餘透過 `raw()` 取得
+#[derive(Debug, Clone)]
+pub struct ModelConfig {
+    raw: Value,
+}
+
+impl ModelConfig {
+    pub fn new(raw: Value) -> Self {
+        Self { raw }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        Ok(Self::new(serde_json::from_reader(file)?))
+    }
+
+    /// 原始 JSON
+    pub fn raw(&self) -> &Value {
+        &self.raw
+    }
+
+    pub fn into_raw(self) -> Value {
+        self.raw
+    }
+
+    /// 取得欄位，找不到時再找 `text_config` (多模態模型會把語言模型設定放在這裡)
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.raw.get(key).filter(|v| !v.is_null()).or_else(|| {
+            self.raw
+                .get("text_config")
+                .and_then(|c| c.get(key))
+                .filter(|v| !v.is_null())
+        })
+    }
+
+    fn get_any(&self, keys: &[&str]) -> Option<&Value> {
+        keys.iter().find_map(|k| self.get(k))
+    }
+
+    fn usize_of(&self, keys: &[&str]) -> Option<usize> {
+        self.get_any(keys).and_then(as_usize)
+    }
+
+    fn f64_of(&self, keys: &[&str]) -> Option<f64> {
+        self.get_any(keys).and_then(as_f64)
+    }
+
+    pub fn model_type(&self) -> Option<&str> {
+        self.get("model_type").and_then(Value::as_str)
+    }
+
+    pub fn architectures(&self) -> Vec<&str> {
+        match self.raw.get("architectures") {
+            Some(Value::Array(list)) => list.iter().filter_map(Value::as_str).collect(),
+            Some(Value::String(s)) => vec![s.as_str()],
+            _ => vec![],
+        }
+    }
+
+    pub fn hidden_size(&self) -> Option<usize> {
+        self.usize_of(&["hidden_size", "n_embd", "d_model", "dim"])
+    }
+
+    pub fn num_layers(&self) -> Option<usize> {
+        self.usize_of(&["num_hidden_layers", "n_layer", "num_layers", "n_layers"])
+    }
+
+    pub fn num_attention_heads(&self) -> Option<usize> {
+        self.usize_of(&["num_attention_heads", "n_head", "num_heads", "n_heads"])
+    }
+
+    pub fn num_key_value_heads(&self) -> Option<usize> {
+        self.usize_of(&["num_key_value_heads", "n_kv_heads", "num_kv_heads"])
+            .or_else(|| self.num_attention_heads())
+    }
+
+    pub fn head_dim(&self) -> Option<usize> {
+        self.usize_of(&["head_dim"]).or_else(|| {
+            match (self.hidden_size(), self.num_attention_heads()) {
+                (Some(h), Some(n)) if n > 0 => Some(h / n),
+                _ => None,
+            }
+        })
+    }
+
+    pub fn max_position_embeddings(&self) -> Option<usize> {
+        self.usize_of(&[
+            "max_position_embeddings",
+            "n_positions",
+            "max_seq_len",
+            "seq_length",
+            "n_ctx",
+        ])
+    }
+
+    pub fn vocab_size(&self) -> Option<usize> {
+        self.usize_of(&["vocab_size", "padded_vocab_size"])
+    }
+
+    pub fn rope_theta(&self) -> Option<f64> {
+        self.f64_of(&["rope_theta", "rotary_emb_base", "rope_base"])
+    }
+
+    /// 解析 `rope_scaling`，未設定或 `default` 時回傳 None
+    pub fn rope_scaling(&self) -> Result<Option<RopeScaling>> {
+        let Some(scaling) = self.get("rope_scaling").and_then(Value::as_object) else {
+            return Ok(None);
+        };
+
+        let rope_type = scaling
+            .get("rope_type")
+            .or_else(|| scaling.get("type"))
+            .and_then(Value::as_str)
+            .unwrap_or("default");
+        let f = |key: &str| scaling.get(key).and_then(as_f64);
+        let original_max_position_embeddings = scaling
+            .get("original_max_position_embeddings")
+            .and_then(as_usize)
+            .or_else(|| self.usize_of(&["original_max_position_embeddings"]))
+            .or_else(|| self.max_position_embeddings());
+        let factor = f("factor");
+
+        let scaling = match (rope_type, factor) {
+            ("default", _) => return Ok(None),
+            (_, None) => bail!("rope_scaling {rope_type} without factor"),
+            ("linear", Some(factor)) => RopeScaling::Linear { factor },
+            ("dynamic", Some(factor)) => RopeScaling::Dynamic {
+                factor,
+                original_max_position_embeddings,
+            },
+            ("yarn", Some(factor)) => RopeScaling::Yarn {
+                factor,
+                original_max_position_embeddings,
+                beta_fast: f("beta_fast").unwrap_or(32.),
+                beta_slow: f("beta_slow").unwrap_or(1.),
+                attention_factor: f("attention_factor"),
+            },
+            ("llama3", Some(factor)) => RopeScaling::Llama3 {
+                factor,
+                low_freq_factor: f("low_freq_factor").unwrap_or(1.),
+                high_freq_factor: f("high_freq_factor").unwrap_or(4.),
+                original_max_position_embeddings: original_max_position_embeddings.unwrap_or(8192),
+            },
+            (other, _) => bail!("unsupported rope_scaling type {other}"),
+        };
+        Ok(Some(scaling))
+    }
+
+    /// 依 head_dim、rope_theta 與 rope_scaling 計算 rotary embedding 的 inv_freq
+    pub fn rope_inv_freq(&self, seq_len: usize) -> Result<Vec<f64>> {
+        let Some(head_dim) = self.head_dim() else {
+            bail!("cannot determine head_dim from config")
+        };
+        let theta = self.rope_theta().unwrap_or(10000.);
+        Ok(match self.rope_scaling()? {
+            Some(scaling) => scaling.inv_freq(head_dim, theta, seq_len),
+            None => rope_inv_freq(head_dim, theta),
+        })
+    }
+
+    pub fn tie_word_embeddings(&self) -> Option<bool> {
+        self.get("tie_word_embeddings").and_then(Value::as_bool)
+    }
+
+    /// `id2label` 依 id 排序，沒有設定時依 num_labels 產生 `LABEL_0`、`LABEL_1`...，同 transformers
+    pub fn id2label(&self) -> Vec<String> {
+        let mut labels: Vec<(usize, String)> = match self.get("id2label").and_then(Value::as_object)
+        {
+            Some(map) => map
+                .iter()
+                .filter_map(|(id, label)| Some((id.parse().ok()?, label.as_str()?.to_string())))
+                .collect(),
+            None => vec![],
+        };
+        if labels.is_empty() {
+            let num_labels = self.usize_of(&["num_labels"]).unwrap_or(0);
+            return (0..num_labels).map(|i| format!("LABEL_{i}")).collect();
+        }
+        labels.sort();
+        labels.into_iter().map(|(_, label)| label).collect()
+    }
+
+    /// 分類模型的 `problem_type`，沒有設定時只有一個 label 為 regression，其餘為 single label
+    pub fn problem_type(&self) -> ProblemType {
+        match self.get("problem_type").and_then(Value::as_str) {
+            Some("multi_label_classification") => ProblemType::MultiLabel,
+            Some("regression") => ProblemType::Regression,
+            Some(_) => ProblemType::SingleLabel,
+            None if self.id2label().len() == 1 => ProblemType::Regression,
+            None => ProblemType::SingleLabel,
+        }
+    }
+
+    /// 轉成指定的設定檔 struct
+    pub fn parse<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        Ok(T::deserialize(&self.raw)?)
+    }
+}
+
+/// 分類模型的輸出方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProblemType {
+    /// 只有一個答案，以 softmax 計算分數
+    SingleLabel,
+    /// 每個 label 各自判斷，以 sigmoid 計算分數
+    MultiLabel,
+    /// 直接輸出數值
+    Regression,
+}
+
+/// config.json 中的 `rope_scaling` 設定
+#[derive(Debug, Clone, PartialEq)]
+pub enum RopeScaling {
+    Linear {
+        factor: f64,
+    },
+    /// Dynamic NTK，長度超過 original_max_position_embeddings 才會調整 base
+    Dynamic {
+        factor: f64,
+        original_max_position_embeddings: Option<usize>,
+    },
+    Yarn {
+        factor: f64,
+        original_max_position_embeddings: Option<usize>,
+        beta_fast: f64,
+        beta_slow: f64,
+        attention_factor: Option<f64>,
+    },
+    Llama3 {
+        factor: f64,
+        low_freq_factor: f64,
+        high_freq_factor: f64,
+        original_max_position_embeddings: usize,
+    },
+}
+
+impl RopeScaling {
+    // 對照 transformers 的 modeling_rope_utils.py
+    pub fn inv_freq(&self, head_dim: usize, theta: f64, seq_len: usize) -> Vec<f64> {
+        match self {
+            Self::Linear { factor } => rope_inv_freq(head_dim, theta)
+                .into_iter()
+                .map(|f| f / factor)
+                .collect(),
+            Self::Dynamic {
+                factor,
+                original_max_position_embeddings,
+            } => {
+                let original = original_max_position_embeddings.unwrap_or(seq_len);
+                if seq_len <= original || head_dim <= 2 {
+                    return rope_inv_freq(head_dim, theta);
+                }
+                let d = head_dim as f64;
+                let base = theta
+                    * ((factor * seq_len as f64 / original as f64) - (factor - 1.))
+                        .powf(d / (d - 2.));
+                rope_inv_freq(head_dim, base)
+            }
+            Self::Yarn {
+                factor,
+                original_max_position_embeddings,
+                beta_fast,
+                beta_slow,
+                ..
+            } => {
+                let d = head_dim as f64;
+                let max_pos = original_max_position_embeddings.unwrap_or(seq_len) as f64;
+                let correction_dim = |num_rotations: f64| {
+                    d * (max_pos / (num_rotations * 2. * std::f64::consts::PI)).ln()
+                        / (2. * theta.ln())
+                };
+                let low = correction_dim(*beta_fast).floor().max(0.);
+                let high = correction_dim(*beta_slow).ceil().min(d - 1.);
+                let high = if low == high { high + 0.001 } else { high };
+
+                rope_inv_freq(head_dim, theta)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, extrapolation)| {
+                        let ramp = ((i as f64 - low) / (high - low)).clamp(0., 1.);
+                        let extrapolation_factor = 1. - ramp;
+                        let interpolation = extrapolation / factor;
+                        interpolation * (1. - extrapolation_factor)
+                            + extrapolation * extrapolation_factor
+                    })
+                    .collect()
+            }
+            Self::Llama3 {
+                factor,
+                low_freq_factor,
+                high_freq_factor,
+                original_max_position_embeddings,
+            } => {
+                let original = *original_max_position_embeddings as f64;
+                let low_freq_wavelen = original / low_freq_factor;
+                let high_freq_wavelen = original / high_freq_factor;
+
+                rope_inv_freq(head_dim, theta)
+                    .into_iter()
+                    .map(|freq| {
+                        let wavelen = 2. * std::f64::consts::PI / freq;
+                        if wavelen < high_freq_wavelen {
+                            freq
+                        } else if wavelen > low_freq_wavelen {
+                            freq / factor
+                        } else {
+                            let smooth = (original / wavelen - low_freq_factor)
+                                / (high_freq_factor - low_freq_factor);
+                            (1. - smooth) * freq / factor + smooth * freq
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// cos/sin 需要額外乘上的係數，只有 YaRN 會用到
+    pub fn attention_factor(&self) -> f64 {
+        match self {
+            Self::Yarn {
+                factor,
+                attention_factor,
+                ..
+            } => attention_factor.unwrap_or_else(|| {
+                if *factor <= 1. {
+                    1.
+                } else {
+                    0.1 * factor.ln() + 1.
+                }
+            }),
+            _ => 1.,
+        }
+    }
+}
+
+/// 未縮放的 inv_freq: 1 / theta^(2i/d)
+pub fn rope_inv_freq(head_dim: usize, theta: f64) -> Vec<f64> {
+    (0..head_dim)
+        .step_by(2)
+        .map(|i| 1. / theta.powf(i as f64 / head_dim as f64))
+        .collect()
+}
+
+// 有些 config 會把數字寫成浮點數或字串，eg: 4096.0, "4096"
+fn as_usize(v: &Value) -> Option<usize> {
+    match v {
+        Value::Number(n) => n.as_u64().map(|v| v as usize).or_else(|| {
+            n.as_f64()
+                .filter(|f| *f >= 0. && f.fract() == 0.)
+                .map(|f| f as usize)
+        }),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_f64(v: &Value) -> Option<f64> {
+    match v {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{Result, repo::Repo};
+use candle_core::Device;
+use candle_core::quantized::{GgmlDType, QTensor, gguf_file};
+use std::fs::File;
+use std::path::Path;
+
+/// 匯出 GGUF 的設定
+#[derive(Debug, Clone)]
+pub struct GgufOptions {
+    /// 權重的量化格式，eg: Q4K、Q8_0。1D 或最後一維無法整除 block size 的 tensor 保留 F32
+    pub dtype: GgmlDType,
+    /// general.architecture，eg: llama、qwen2
+    pub architecture: Option<String>,
+    /// tokenizer.huggingface.json
+    pub tokenizer_json: Option<String>,
+    /// tokenizer.chat_template
+    pub chat_template: Option<String>,
+    /// 其他 metadata
+    pub metadata: Vec<(String, gguf_file::Value)>,
+}
+
+impl GgufOptions {
+    pub fn new(dtype: GgmlDType) -> Self {
+        Self {
+            dtype,
+            architecture: None,
+            tokenizer_json: None,
+            chat_template: None,
+            metadata: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GgufSummary {
+    pub tensors: usize,
+    pub quantized: usize,
+}
+
+/// 將 safetensors 量化後寫成 GGUF。
+///
+/// rename 用來把 tensor 名稱轉成 GGUF loader 預期的名稱 (eg: llama.cpp 的 blk.0.attn_q.weight)，
+/// 回傳 None 表示略過該 tensor
+pub fn safetensors_to_gguf<P, Q, F>(
+    inputs: &[P],
+    output: Q,
+    options: &GgufOptions,
+    rename: F,
+) -> Result<GgufSummary>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    F: Fn(&str) -> Option<String>,
+{
+    let block_size = options.dtype.block_size();
+    let mut tensors = vec![];
+    let mut quantized = 0;
+    for input in inputs {
+        let mut loaded: Vec<_> = candle_core::safetensors::load(input, &Device::Cpu)?
+            .into_iter()
+            .collect();
+        loaded.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (name, tensor) in loaded {
+            let Some(name) = rename(&name) else {
+                continue;
+            };
+            let dims = tensor.dims();
+            let dtype = if dims.len() >= 2 && dims[dims.len() - 1] % block_size == 0 {
+                quantized += 1;
+                options.dtype
+            } else {
+                GgmlDType::F32
+            };
+            tensors.push((name, QTensor::quantize(&tensor, dtype)?));
+        }
+    }
+
+    let mut metadata = vec![(
+        "general.quantization_version".to_string(),
+        gguf_file::Value::U32(2),
+    )];
+    let strings = [
+        ("general.architecture", &options.architecture),
+        ("tokenizer.huggingface.json", &options.tokenizer_json),
+        ("tokenizer.chat_template", &options.chat_template),
+    ];
+    for (key, value) in strings {
+        if let Some(value) = value {
+            metadata.push((key.to_string(), gguf_file::Value::String(value.clone())));
+        }
+    }
+    metadata.extend(options.metadata.iter().cloned());
+
+    let metadata: Vec<_> = metadata.iter().map(|(k, v)| (k.as_str(), v)).collect();
+    let refs: Vec<_> = tensors.iter().map(|(k, v)| (k.as_str(), v)).collect();
+    let mut file = File::create(output)?;
+    gguf_file::write(&mut file, &metadata, &refs)?;
+
+    Ok(GgufSummary {
+        tensors: tensors.len(),
+        quantized,
+    })
+}
+
+/// 將 repo 中的 safetensors 模型轉成 GGUF，並嵌入 tokenizer.json 與 chat template
+pub fn repo_to_gguf<R, Q, F>(
+    repo: &R,
+    output: Q,
+    dtype: GgmlDType,
+    rename: F,
+) -> Result<GgufSummary>
+where
+    R: Repo,
+    Q: AsRef<Path>,
+    F: Fn(&str) -> Option<String>,
+{
+    let mut options = GgufOptions::new(dtype);
+    options.architecture = repo
+        .model_config()
+        .ok()
+        .and_then(|c| c.model_type().map(str::to_string));
+    options.tokenizer_json = repo
+        .tokenizer_file()
+        .ok()
+        .and_then(|p| std::fs::read_to_string(p).ok());
+    options.chat_template = repo
+        .tokenizer_config_file()
+        .ok()
+        .and_then(|p| File::open(p).ok())
+        .and_then(|f| serde_json::from_reader::<_, serde_json::Value>(f).ok())
+        .and_then(|v| v.get("chat_template")?.as_str().map(str::to_string));
+    options.metadata.push((
+        "general.name".to_string(),
+        gguf_file::Value::String(repo.model_id().to_string()),
+    ));
+
+    safetensors_to_gguf(&repo.safetensors_files()?, output, &options, rename)
+}
@@ -0,0 +1,526 @@
+use crate::{Result, bail};
+use candle_core::quantized::gguf_file;
+use candle_core::{DType, Device, Shape, Tensor};
+use candle_nn::VarBuilder;
+use candle_nn::var_builder::SimpleBackend;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// 印出 tensor 的設定，預設值同 PyTorch
+#[derive(Debug, Clone, Copy)]
+pub struct PrintOptions {
+    /// 小數位數
+    pub precision: usize,
+    /// 元素個數超過 threshold 時只印出每個維度前後 edge_items 個
+    pub threshold: usize,
+    pub edge_items: usize,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        Self {
+            precision: 4,
+            threshold: 1000,
+            edge_items: 3,
+        }
+    }
+}
+
+pub fn print_tensor(tensor: &Tensor) -> Result<()> {
+    println!("{}", format_tensor(tensor, &PrintOptions::default())?);
+    Ok(())
+}
+
+pub fn print_tensor_with(tensor: &Tensor, options: &PrintOptions) -> Result<()> {
+    println!("{}", format_tensor(tensor, options)?);
+    Ok(())
+}
+
+/// 依 tensor.dtype() 格式化，支援任意 rank
+pub fn format_tensor(tensor: &Tensor, options: &PrintOptions) -> Result<String> {
+    let dims = tensor.dims().to_vec();
+    let values = tensor
+        .flatten_all()?
+        .to_dtype(DType::F64)?
+        .to_vec1::<f64>()?;
+    let is_int = tensor.dtype().is_int();
+    let summarize = values.len() > options.threshold;
+
+    let format = |v: f64| {
+        if is_int {
+            format!("{v}")
+        } else {
+            format!("{v:.prec$}", prec = options.precision)
+        }
+    };
+
+    let mut strides = vec![1; dims.len()];
+    for i in (0..dims.len().saturating_sub(1)).rev() {
+        strides[i] = strides[i + 1] * dims[i + 1];
+    }
+
+    let printer = Printer {
+        dims: &dims,
+        strides: &strides,
+        values: &values,
+        edge_items: options.edge_items,
+        summarize,
+        width: values.iter().map(|v| format(*v).len()).max().unwrap_or(0),
+        format: &format,
+    };
+
+    let mut out = String::from("tensor(");
+    if dims.is_empty() {
+        out.push_str(&format(values[0]));
+    } else {
+        printer.write(0, 0, &mut out);
+    }
+    out.push_str(&format!(", dtype={:?}, shape={dims:?})", tensor.dtype()));
+    Ok(out)
+}
+
+struct Printer<'a> {
+    dims: &'a [usize],
+    strides: &'a [usize],
+    values: &'a [f64],
+    edge_items: usize,
+    summarize: bool,
+    width: usize,
+    format: &'a dyn Fn(f64) -> String,
+}
+
+impl Printer<'_> {
+    // 每個維度要印出的 index，None 代表省略號
+    fn indexes(&self, n: usize) -> Vec<Option<usize>> {
+        if self.summarize && n > 2 * self.edge_items {
+            (0..self.edge_items)
+                .map(Some)
+                .chain(std::iter::once(None))
+                .chain((n - self.edge_items..n).map(Some))
+                .collect()
+        } else {
+            (0..n).map(Some).collect()
+        }
+    }
+
+    fn write(&self, dim: usize, offset: usize, out: &mut String) {
+        let rank = self.dims.len();
+        let last = dim + 1 == rank;
+        out.push('[');
+        for (k, index) in self.indexes(self.dims[dim]).into_iter().enumerate() {
+            if k > 0 {
+                if last {
+                    out.push_str(", ");
+                } else {
+                    out.push(',');
+                    out.push_str(&"\n".repeat(rank - dim - 1));
+                    // "tensor(" 的長度加上外層的 '['
+                    out.push_str(&" ".repeat(7 + dim + 1));
+                }
+            }
+            match index {
+                None => out.push_str("..."),
+                Some(i) if last => {
+                    let v = (self.format)(self.values[offset + i]);
+                    out.push_str(&format!("{v:>width$}", width = self.width));
+                }
+                Some(i) => self.write(dim + 1, offset + i * self.strides[dim], out),
+            }
+        }
+        out.push(']');
+    }
+}
+
+/// 兩個 tensor 的差異統計
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffSummary {
+    pub shape: Vec<usize>,
+    pub max_abs: f64,
+    pub mean_abs: f64,
+    pub max_rel: f64,
+    /// 超出容許誤差的元素個數
+    pub mismatches: usize,
+    /// 前幾個超出容許誤差的元素: (index, a, b)
+    pub first_mismatches: Vec<(Vec<usize>, f64, f64)>,
+}
+
+impl std::fmt::Display for DiffSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total: usize = self.shape.iter().product();
+        writeln!(
+            f,
+            "shape {:?}: {} / {} mismatched, max abs {:e}, mean abs {:e}, max rel {:e}",
+            self.shape, self.mismatches, total, self.max_abs, self.mean_abs, self.max_rel
+        )?;
+        for (index, a, b) in &self.first_mismatches {
+            writeln!(f, "  at {index:?}: {a} vs {b}")?;
+        }
+        Ok(())
+    }
+}
+
+const MAX_REPORTED_MISMATCHES: usize = 10;
+
+/// 比較 a 與 b，超出 atol + rtol * |b| 視為不符 (同 torch.allclose)
+pub fn diff_summary(a: &Tensor, b: &Tensor, rtol: f64, atol: f64) -> Result<DiffSummary> {
+    if a.dims() != b.dims() {
+        bail!("shape mismatch: {:?} vs {:?}", a.dims(), b.dims());
+    }
+    let shape = a.dims().to_vec();
+    let to_vec = |t: &Tensor| -> Result<Vec<f64>> {
+        Ok(t.flatten_all()?.to_dtype(DType::F64)?.to_vec1::<f64>()?)
+    };
+    let (va, vb) = (to_vec(a)?, to_vec(b)?);
+
+    let mut summary = DiffSummary {
+        shape,
+        max_abs: 0.,
+        mean_abs: 0.,
+        max_rel: 0.,
+        mismatches: 0,
+        first_mismatches: vec![],
+    };
+    for (i, (&x, &y)) in va.iter().zip(vb.iter()).enumerate() {
+        let abs = (x - y).abs();
+        let rel = if y != 0. { abs / y.abs() } else { abs };
+        // NaN 的比較結果都是 false，要另外處理
+        if abs.is_nan() || abs > summary.max_abs {
+            summary.max_abs = abs;
+        }
+        if rel.is_nan() || rel > summary.max_rel {
+            summary.max_rel = rel;
+        }
+        summary.mean_abs += abs;
+
+        if abs.is_nan() || abs > atol + rtol * y.abs() {
+            summary.mismatches += 1;
+            if summary.first_mismatches.len() < MAX_REPORTED_MISMATCHES {
+                summary
+                    .first_mismatches
+                    .push((unravel(i, &summary.shape), x, y));
+            }
+        }
+    }
+    if !va.is_empty() {
+        summary.mean_abs /= va.len() as f64;
+    }
+    Ok(summary)
+}
+
+/// a 與 b 在容許誤差內時回傳 Ok，否則回傳包含差異統計的錯誤
+pub fn assert_close(a: &Tensor, b: &Tensor, rtol: f64, atol: f64) -> Result<()> {
+    let summary = diff_summary(a, b, rtol, atol)?;
+    if summary.mismatches > 0 {
+        bail!("tensors are not close (rtol={rtol}, atol={atol})\n{summary}");
+    }
+    Ok(())
+}
+
+fn unravel(mut index: usize, shape: &[usize]) -> Vec<usize> {
+    let mut result = vec![0; shape.len()];
+    for (i, &dim) in shape.iter().enumerate().rev() {
+        if dim > 0 {
+            result[i] = index % dim;
+            index /= dim;
+        }
+    }
+    result
+}
+
+/// 權重檔中的 tensor 資訊
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TensorInfo {
+    pub name: String,
+    pub shape: Vec<usize>,
+    pub dtype: String,
+}
+
+/// 列出 safetensors 或 gguf 檔案中的 tensor，只讀取檔頭，不會載入權重
+pub fn list_tensors<P: AsRef<Path>>(path: P) -> Result<Vec<TensorInfo>> {
+    let path = path.as_ref();
+    let mut file = File::open(path)?;
+    let mut tensors = if path.extension().is_some_and(|ext| ext == "gguf") {
+        let content = gguf_file::Content::read(&mut file)?;
+        content
+            .tensor_infos
+            .into_iter()
+            .map(|(name, info)| TensorInfo {
+                name,
+                shape: info.shape.dims().to_vec(),
+                dtype: format!("{:?}", info.ggml_dtype),
+            })
+            .collect()
+    } else {
+        read_safetensors_header(&mut file)?
+    };
+    tensors.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(tensors)
+}
+
+// safetensors 格式: 8 bytes little-endian 的 header 長度，接著是 JSON header
+fn read_safetensors_header<R: Read>(reader: &mut R) -> Result<Vec<TensorInfo>> {
+    let mut len = [0u8; 8];
+    reader.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len) as usize;
+    let mut header = vec![0u8; len];
+    reader.read_exact(&mut header)?;
+    let header: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(&header)?;
+
+    header
+        .into_iter()
+        .filter(|(name, _)| name != "__metadata__")
+        .map(|(name, info)| {
+            let dtype = info.get("dtype").and_then(|v| v.as_str());
+            let shape = info.get("shape").and_then(|v| v.as_array());
+            let (Some(dtype), Some(shape)) = (dtype, shape) else {
+                bail!("invalid safetensors header for {name}")
+            };
+            Ok(TensorInfo {
+                shape: shape
+                    .iter()
+                    .map(|v| v.as_u64().unwrap_or_default() as usize)
+                    .collect(),
+                dtype: dtype.to_string(),
+                name,
+            })
+        })
+        .collect()
+}
+
+// 記錄模型載入時要求的 tensor，回傳不佔記憶體的 broadcast tensor
+struct Recorder(Arc<Mutex<Vec<TensorInfo>>>);
+
+impl SimpleBackend for Recorder {
+    fn get(
+        &self,
+        s: Shape,
+        name: &str,
+        _h: candle_nn::Init,
+        dtype: DType,
+        dev: &Device,
+    ) -> candle_core::Result<Tensor> {
+        if let Ok(mut tensors) = self.0.lock() {
+            tensors.push(TensorInfo {
+                name: name.to_string(),
+                shape: s.dims().to_vec(),
+                dtype: format!("{dtype:?}"),
+            });
+        }
+        Tensor::zeros(1, dtype, dev)?.broadcast_as(s)
+    }
+
+    fn contains_tensor(&self, _name: &str) -> bool {
+        true
+    }
+}
+
+/// 執行模型的載入函式，回傳模型預期的 tensor (不需要權重檔)
+pub fn expected_tensors<C, M, F>(
+    config: &C,
+    dtype: DType,
+    device: &Device,
+    load: F,
+) -> Result<Vec<TensorInfo>>
+where
+    F: Fn(&C, VarBuilder) -> candle_core::Result<M>,
+{
+    let recorded = Arc::new(Mutex::new(vec![]));
+    let vb = VarBuilder::from_backend(Box::new(Recorder(recorded.clone())), dtype, device.clone());
+    load(config, vb)?;
+
+    let mut tensors = recorded
+        .lock()
+        .map_err(|_| crate::Error::msg("recorder mutex poisoned"))?
+        .clone();
+    tensors.sort_by(|a, b| a.name.cmp(&b.name));
+    tensors.dedup_by(|a, b| a.name == b.name);
+    Ok(tensors)
+}
+
+/// 權重檔與模型預期 tensor 的差異
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TensorDiff {
+    /// 模型需要但權重檔中沒有
+    pub missing: Vec<String>,
+    /// 權重檔中有但模型沒有用到
+    pub unexpected: Vec<String>,
+    /// (name, 權重檔的 shape, 模型預期的 shape)
+    pub shape_mismatches: Vec<(String, Vec<usize>, Vec<usize>)>,
+}
+
+impl TensorDiff {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty() && self.shape_mismatches.is_empty()
+    }
+}
+
+pub fn diff_tensors(checkpoint: &[TensorInfo], expected: &[TensorInfo]) -> TensorDiff {
+    let actual: HashMap<&str, &TensorInfo> =
+        checkpoint.iter().map(|t| (t.name.as_str(), t)).collect();
+    let wanted: HashMap<&str, &TensorInfo> =
+        expected.iter().map(|t| (t.name.as_str(), t)).collect();
+
+    let mut diff = TensorDiff::default();
+    for t in expected {
+        match actual.get(t.name.as_str()) {
+            None => diff.missing.push(t.name.clone()),
+            Some(a) if a.shape != t.shape => {
+                diff.shape_mismatches
+                    .push((t.name.clone(), a.shape.clone(), t.shape.clone()))
+            }
+            Some(_) => {}
+        }
+    }
+    diff.unexpected = checkpoint
+        .iter()
+        .filter(|t| !wanted.contains_key(t.name.as_str()))
+        .map(|t| t.name.clone())
+        .collect();
+    diff
+}
+
+/// conv_pth_to_safetensors 的設定
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// 轉換時順便轉型，eg: F16、BF16
+    pub dtype: Option<DType>,
+    /// 每個 safetensors 檔案的最大 bytes，超過時分檔並產生 model.safetensors.index.json
+    pub max_shard_size: Option<usize>,
+    /// state dict 在 pickle 中的 key，eg: "state_dict"
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConvertSummary {
+    pub tensors: usize,
+    pub total_size: usize,
+    pub files: Vec<PathBuf>,
+}
+
+/// 將一或多個 pytorch .bin 檔轉成 safetensors
+pub fn conv_pth_to_safetensors<P: AsRef<Path>, Q: AsRef<Path>>(
+    inputs: &[P],
+    output_dir: Q,
+    options: &ConvertOptions,
+) -> Result<ConvertSummary> {
+    let output_dir = output_dir.as_ref();
+    std::fs::create_dir_all(output_dir)?;
+    let max_shard_size = options.max_shard_size.unwrap_or(usize::MAX);
+
+    let mut shards: Vec<Vec<String>> = vec![];
+    let mut current: HashMap<String, Tensor> = HashMap::new();
+    let mut current_size = 0;
+    let mut total_size = 0;
+    let mut tensors = 0;
+
+    let shard_file =
+        |index: usize| output_dir.join(format!("model-{:05}.safetensors.tmp", index + 1));
+    let flush =
+        |current: &mut HashMap<String, Tensor>, shards: &mut Vec<Vec<String>>| -> Result<()> {
+            if current.is_empty() {
+                return Ok(());
+            }
+            candle_core::safetensors::save(current, shard_file(shards.len()))?;
+            let mut names: Vec<String> = current.drain().map(|(name, _)| name).collect();
+            names.sort();
+            shards.push(names);
+            Ok(())
+        };
+
+    for input in inputs {
+        let mut loaded = candle_core::pickle::read_all_with_key(input, options.key.as_deref())?;
+        loaded.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, tensor) in loaded {
+            let tensor = match options.dtype {
+                Some(dtype) if tensor.dtype().is_float() => tensor.to_dtype(dtype)?,
+                _ => tensor,
+            };
+            let size = tensor.elem_count() * tensor.dtype().size_in_bytes();
+            if current_size + size > max_shard_size && !current.is_empty() {
+                flush(&mut current, &mut shards)?;
+                current_size = 0;
+            }
+            current_size += size;
+            total_size += size;
+            tensors += 1;
+            current.insert(name, tensor);
+        }
+    }
+    flush(&mut current, &mut shards)?;
+
+    // 知道總檔案數後再命名
+    let mut files = vec![];
+    let mut weight_map = serde_json::Map::new();
+    for (index, names) in shards.iter().enumerate() {
+        let filename = if shards.len() == 1 {
+            "model.safetensors".to_string()
+        } else {
+            format!("model-{:05}-of-{:05}.safetensors", index + 1, shards.len())
+        };
+        let file = output_dir.join(&filename);
+        std::fs::rename(shard_file(index), &file)?;
+        for name in names {
+            weight_map.insert(name.clone(), filename.clone().into());
+        }
+        files.push(file);
+    }
+
+    if shards.len() > 1 {
+        let index = serde_json::json!({
+            "metadata": { "total_size": total_size },
+            "weight_map": weight_map,
+        });
+        let index_file = output_dir.join("model.safetensors.index.json");
+        serde_json::to_writer_pretty(File::create(&index_file)?, &index)?;
+        files.push(index_file);
+    }
+
+    Ok(ConvertSummary {
+        tensors,
+        total_size,
+        files,
+    })
+}
+
+/// 記憶體用量 (bytes)，backend 沒有提供時為 None
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MemoryStats {
+    pub allocated: Option<u64>,
+    pub peak: Option<u64>,
+}
+
+/// 目前 device 的記憶體用量。
+///
+/// CPU 在 Linux 上讀取 /proc/self/status 的 VmRSS 與 VmHWM (整個 process)；
+/// candle 沒有公開 CUDA/Metal allocator 的統計，GPU 目前回傳空值
+pub fn memory_stats(device: &Device) -> Result<MemoryStats> {
+    match device {
+        Device::Cpu => process_memory(),
+        _ => Ok(MemoryStats::default()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn process_memory() -> Result<MemoryStats> {
+    let status = std::fs::read_to_string("/proc/self/status")?;
+    // eg: "VmRSS:     12345 kB"
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|v| v.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+            .map(|kb| kb * 1024)
+    };
+    Ok(MemoryStats {
+        allocated: field("VmRSS:"),
+        peak: field("VmHWM:"),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_memory() -> Result<MemoryStats> {
+    Ok(MemoryStats::default())
+}
@@ -0,0 +1,48 @@
+use crate::generation::{Model, TextGeneration};
+use crate::{Result, bail};
+use candle_core::Device;
+
+/// 開啟 deterministic 模式，讓相同的輸入在同一個環境得到相同的輸出，適合做 regression test。
+///
+/// - 關閉 CUDA gemm 的 reduced precision reduction
+/// - 以 seed 固定 device 的亂數 (CUDA、Metal)
+/// - CPU 的 matmul 依 thread 數切分，reduction 的順序會隨 thread 數改變，
+///   需要以 `RAYON_NUM_THREADS=1` 啟動，否則回傳錯誤
+///
+/// sampling 的亂數見 `TextGeneration::set_deterministic`
+pub fn set_deterministic(device: &Device, seed: u64) -> Result<()> {
+    if candle_core::utils::get_num_threads() != 1 {
+        bail!("deterministic mode requires RAYON_NUM_THREADS=1")
+    }
+    candle_core::cuda::set_gemm_reduced_precision_f16(false);
+    candle_core::cuda::set_gemm_reduced_precision_bf16(false);
+    candle_core::cuda::set_gemm_reduced_precision_f32(false);
+    if !device.is_cpu() {
+        device.set_seed(seed)?;
+    }
+    Ok(())
+}
+
+/// 以相同的 prompt 生成兩次並比較 token，相同時回傳生成的 token。
+/// 每次生成前都會以 seed 重設 sampling 與 logits transform 的亂數
+pub fn verify_determinism<M: Model>(
+    generation: &mut TextGeneration<M>,
+    ids: &[u32],
+    max_new_tokens: usize,
+) -> Result<Vec<u32>> {
+    generation.reseed();
+    let first = generation.generate_n(ids, max_new_tokens, 1)?.remove(0);
+    generation.reseed();
+    let second = generation.generate_n(ids, max_new_tokens, 1)?.remove(0);
+
+    if let Some(i) = (0..first.tokens.len().max(second.tokens.len()))
+        .find(|&i| first.tokens.get(i) != second.tokens.get(i))
+    {
+        bail!(
+            "generation is not deterministic: token {i} differs ({:?} vs {:?})",
+            first.tokens.get(i),
+            second.tokens.get(i)
+        )
+    }
+    Ok(first.tokens)
+}
@@ -0,0 +1,444 @@
+use crate::repo::Repo;
+use crate::tokenizers::Tokenizer;
+use crate::{Result, bail};
+use candle_core::{D, DType, Tensor};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 對最後一維做 L2 normalize
+pub fn normalize(embeddings: &Tensor) -> Result<Tensor> {
+    let embeddings = embeddings.to_dtype(DType::F32)?;
+    let norm = embeddings
+        .sqr()?
+        .sum_keepdim(D::Minus1)?
+        .sqrt()?
+        .clamp(1e-12, f32::MAX)?;
+    Ok(embeddings.broadcast_div(&norm)?)
+}
+
+/// Matryoshka 截斷：保留最後一維的前 dims 維後重新 normalize，dims 需為模型支援的維度
+pub fn truncate(embeddings: &Tensor, dims: usize) -> Result<Tensor> {
+    let size = embeddings.dim(D::Minus1)?;
+    if dims == 0 || dims > size {
+        bail!("cannot truncate {size} dims embedding to {dims}");
+    }
+    normalize(&embeddings.narrow(D::Minus1, 0, dims)?)
+}
+
+/// 向量的量化格式
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantization {
+    /// 每個向量以最大絕對值對稱量化成 i8
+    Int8,
+    /// 大於 0 為 1，每 8 維壓成一個 u8，高位在前，同 numpy.packbits
+    Binary,
+}
+
+/// 量化後的向量
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum QuantizedEmbedding {
+    Int8 { values: Vec<i8>, scale: f32 },
+    Binary { bits: Vec<u8>, dims: usize },
+}
+
+impl QuantizedEmbedding {
+    pub fn quantize(vector: &[f32], quantization: Quantization) -> Self {
+        match quantization {
+            Quantization::Int8 => {
+                let max = vector.iter().fold(0f32, |max, v| max.max(v.abs()));
+                let scale = if max == 0. { 1. } else { max / 127. };
+                let values = vector
+                    .iter()
+                    .map(|v| (v / scale).round().clamp(-127., 127.) as i8)
+                    .collect();
+                Self::Int8 { values, scale }
+            }
+            Quantization::Binary => {
+                let mut bits = vec![0u8; vector.len().div_ceil(8)];
+                for (i, v) in vector.iter().enumerate() {
+                    if *v > 0. {
+                        bits[i / 8] |= 0x80 >> (i % 8);
+                    }
+                }
+                Self::Binary {
+                    bits,
+                    dims: vector.len(),
+                }
+            }
+        }
+    }
+
+    /// 還原成 f32，binary 還原為 1 與 -1
+    pub fn dequantize(&self) -> Vec<f32> {
+        match self {
+            Self::Int8 { values, scale } => values.iter().map(|v| *v as f32 * scale).collect(),
+            Self::Binary { bits, dims } => (0..*dims)
+                .map(|i| {
+                    if bits[i / 8] & (0x80 >> (i % 8)) != 0 {
+                        1.
+                    } else {
+                        -1.
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn dims(&self) -> usize {
+        match self {
+            Self::Int8 { values, .. } => values.len(),
+            Self::Binary { dims, .. } => *dims,
+        }
+    }
+}
+
+/// 將 (batch, dims) 的 embeddings 逐筆量化
+pub fn quantize(
+    embeddings: &Tensor,
+    quantization: Quantization,
+) -> Result<Vec<QuantizedEmbedding>> {
+    Ok(embeddings
+        .to_dtype(DType::F32)?
+        .to_vec2::<f32>()?
+        .iter()
+        .map(|v| QuantizedEmbedding::quantize(v, quantization))
+        .collect())
+}
+
+/// binary 向量的 hamming distance，越小越相似
+pub fn hamming_distance(a: &[u8], b: &[u8]) -> u32 {
+    a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+}
+
+/// Qwen3-Embedding 預設的 task
+pub const DEFAULT_TASK: &str =
+    "Given a web search query, retrieve relevant passages that answer the query";
+
+/// 檢索時 query 與 document 需要加上的前綴，eg: E5 的 "query: " 與 "passage: "
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct EmbeddingPrompts {
+    pub query: String,
+    pub document: String,
+}
+
+impl EmbeddingPrompts {
+    pub fn new(query: &str, document: &str) -> Self {
+        Self {
+            query: query.to_string(),
+            document: document.to_string(),
+        }
+    }
+
+    /// Qwen3-Embedding、gte-Qwen2 等的 instruction 格式，只有 query 需要
+    pub fn instruct(task: &str) -> Self {
+        Self::new(&format!("Instruct: {task}\nQuery:"), "")
+    }
+
+    /// 依模型名稱取得已知的前綴，eg: "intfloat/multilingual-e5-large"、"BAAI/bge-large-zh-v1.5"
+    pub fn guess(model_id: &str) -> Option<Self> {
+        let name = model_id.to_ascii_lowercase();
+        let name = name.rsplit('/').next().unwrap_or_default();
+        if name.contains("e5") && !name.contains("instruct") {
+            Some(Self::new("query: ", "passage: "))
+        } else if name.starts_with("bge") && name.contains("zh") {
+            Some(Self::new("为这个句子生成表示以用于检索相关文章：", ""))
+        } else if name.starts_with("bge") && !name.starts_with("bge-m3") {
+            Some(Self::new(
+                "Represent this sentence for searching relevant passages: ",
+                "",
+            ))
+        } else if name.contains("nomic-embed") {
+            Some(Self::new("search_query: ", "search_document: "))
+        } else if name.contains("qwen") || name.contains("e5-mistral") || name.contains("instruct")
+        {
+            Some(Self::instruct(DEFAULT_TASK))
+        } else {
+            None
+        }
+    }
+
+    /// 優先讀取 config_sentence_transformers.json 的 prompts，沒有時依模型名稱推測，都沒有則不加前綴
+    pub fn from_repo<R: Repo>(repo: &R) -> Result<Self> {
+        if let Some(file) = repo
+            .get("config_sentence_transformers.json")
+            .ok()
+            .filter(|p| p.exists())
+        {
+            let config: serde_json::Value = serde_json::from_reader(std::fs::File::open(file)?)?;
+            let prompt = |key: &str| config["prompts"][key].as_str().map(str::to_string);
+            if let Some(query) = prompt("query") {
+                return Ok(Self {
+                    query,
+                    document: prompt("document")
+                        .or_else(|| prompt("passage"))
+                        .unwrap_or_default(),
+                });
+            }
+        }
+        Ok(Self::guess(repo.model_id()).unwrap_or_default())
+    }
+
+    pub fn query(&self, text: &str) -> String {
+        format!("{}{text}", self.query)
+    }
+
+    pub fn document(&self, text: &str) -> String {
+        format!("{}{text}", self.document)
+    }
+
+    pub fn queries<S: AsRef<str>>(&self, texts: &[S]) -> Vec<String> {
+        texts.iter().map(|t| self.query(t.as_ref())).collect()
+    }
+
+    pub fn documents<S: AsRef<str>>(&self, texts: &[S]) -> Vec<String> {
+        texts.iter().map(|t| self.document(t.as_ref())).collect()
+    }
+}
+
+/// 稀疏向量，indices 為 token id，由小到大排序
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SparseEmbedding {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+}
+
+impl SparseEmbedding {
+    /// 由 (token id, weight) 建立，忽略 weight 為 0 的項目
+    pub fn new(mut pairs: Vec<(u32, f32)>) -> Self {
+        pairs.retain(|(_, w)| *w != 0.);
+        pairs.sort_by_key(|(id, _)| *id);
+        let (indices, values) = pairs.into_iter().unzip();
+        Self { indices, values }
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// (token id, weight)
+    pub fn iter(&self) -> impl Iterator<Item = (u32, f32)> + '_ {
+        self.indices
+            .iter()
+            .copied()
+            .zip(self.values.iter().copied())
+    }
+
+    /// 只保留 weight 最大的 k 個
+    pub fn top_k(&self, k: usize) -> Self {
+        let mut pairs: Vec<_> = self.iter().collect();
+        pairs.sort_by(|a, b| b.1.total_cmp(&a.1));
+        pairs.truncate(k);
+        Self::new(pairs)
+    }
+
+    pub fn dot(&self, other: &Self) -> f32 {
+        let (mut i, mut j, mut sum) = (0, 0, 0.);
+        while i < self.indices.len() && j < other.indices.len() {
+            match self.indices[i].cmp(&other.indices[j]) {
+                std::cmp::Ordering::Less => i += 1,
+                std::cmp::Ordering::Greater => j += 1,
+                std::cmp::Ordering::Equal => {
+                    sum += self.values[i] * other.values[j];
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        sum
+    }
+}
+
+/// SPLADE pooling：將 MLM 的 logits (batch, seq, vocab) 轉成 max(log(1 + relu(logits)))。
+/// attention_mask (batch, seq) 為 0 的位置不列入
+pub fn splade(logits: &Tensor, attention_mask: Option<&Tensor>) -> Result<Vec<SparseEmbedding>> {
+    let weights = (logits.to_dtype(DType::F32)?.relu()? + 1.)?.log()?;
+    let weights = match attention_mask {
+        Some(mask) => weights.broadcast_mul(&mask.to_dtype(DType::F32)?.unsqueeze(D::Minus1)?)?,
+        None => weights,
+    };
+    Ok(weights
+        .max(1)?
+        .to_vec2::<f32>()?
+        .into_iter()
+        .map(|row| {
+            SparseEmbedding::new(
+                row.into_iter()
+                    .enumerate()
+                    .map(|(id, w)| (id as u32, w))
+                    .collect(),
+            )
+        })
+        .collect())
+}
+
+/// 稀疏向量的倒排索引，以 dot product 搜尋
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SparseIndex {
+    postings: HashMap<u32, Vec<(usize, f32)>>,
+    len: usize,
+}
+
+impl SparseIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 加入文件，回傳 id
+    pub fn add(&mut self, embedding: &SparseEmbedding) -> usize {
+        let id = self.len;
+        for (token, weight) in embedding.iter() {
+            self.postings.entry(token).or_default().push((id, weight));
+        }
+        self.len += 1;
+        id
+    }
+
+    /// 回傳分數最高的 k 筆 (id, score)，由高到低，只有與 query 有共同 token 的文件
+    pub fn search(&self, query: &SparseEmbedding, k: usize) -> Vec<(usize, f32)> {
+        let mut scores: HashMap<usize, f32> = HashMap::new();
+        for (token, weight) in query.iter() {
+            for (id, w) in self.postings.get(&token).into_iter().flatten() {
+                *scores.entry(*id).or_default() += weight * w;
+            }
+        }
+        let mut scores: Vec<_> = scores.into_iter().collect();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scores.truncate(k);
+        scores
+    }
+}
+
+/// late interaction (ColBERT) 的每個 token 向量：normalize 後去掉 attention_mask 為 0 的 token。
+/// hidden 為 (batch, seq, dims)，回傳每筆 (tokens, dims)
+pub fn multi_vector(hidden: &Tensor, attention_mask: Option<&Tensor>) -> Result<Vec<Tensor>> {
+    let hidden = normalize(hidden)?;
+    let mut vectors = Vec::with_capacity(hidden.dim(0)?);
+    for i in 0..hidden.dim(0)? {
+        let tokens = hidden.get(i)?;
+        let tokens = match attention_mask {
+            Some(mask) => {
+                let keep: Vec<u32> = mask
+                    .get(i)?
+                    .to_dtype(DType::U32)?
+                    .to_vec1::<u32>()?
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, m)| *m != 0)
+                    .map(|(i, _)| i as u32)
+                    .collect();
+                let keep = Tensor::new(keep.as_slice(), tokens.device())?;
+                tokens.index_select(&keep, 0)?
+            }
+            None => tokens,
+        };
+        vectors.push(tokens);
+    }
+    Ok(vectors)
+}
+
+/// MaxSim：每個 query token 與文件 token 的最大相似度總和。query 為 (q, dims)，doc 為 (d, dims)
+pub fn maxsim(query: &Tensor, doc: &Tensor) -> Result<f32> {
+    Ok(maxsim_batch(query, std::slice::from_ref(doc))?[0])
+}
+
+/// 以一次 matmul 計算 query 與多份文件的 MaxSim
+pub fn maxsim_batch(query: &Tensor, docs: &[Tensor]) -> Result<Vec<f32>> {
+    if docs.is_empty() {
+        return Ok(vec![]);
+    }
+    if let Some(doc) = docs.iter().find(|d| d.dim(0).is_ok_and(|n| n == 0)) {
+        bail!("document without tokens: {:?}", doc.shape());
+    }
+    let query = query.to_dtype(DType::F32)?;
+    let docs = docs
+        .iter()
+        .map(|d| d.to_dtype(DType::F32))
+        .collect::<candle_core::Result<Vec<_>>>()?;
+    // (q, 所有文件的 token 數)
+    let sim = query.matmul(&Tensor::cat(&docs, 0)?.t()?)?;
+    let mut scores = Vec::with_capacity(docs.len());
+    let mut offset = 0;
+    for doc in &docs {
+        let len = doc.dim(0)?;
+        let score = sim.narrow(1, offset, len)?.max(1)?.sum_all()?;
+        scores.push(score.to_scalar::<f32>()?);
+        offset += len;
+    }
+    Ok(scores)
+}
+
+/// 依長度排序後分批，每批 pad 後的 token 數 (筆數 × 最長的長度) 不超過 max_tokens，
+/// 回傳每批在 lengths 中的 index。單筆超過 max_tokens 時自成一批
+pub fn micro_batches(lengths: &[usize], max_tokens: usize) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..lengths.len()).collect();
+    order.sort_by_key(|&i| lengths[i]);
+
+    let mut batches: Vec<Vec<usize>> = vec![];
+    let mut batch = vec![];
+    for i in order {
+        // 由短到長排序，加入的這筆就是最長的
+        if !batch.is_empty() && (batch.len() + 1) * lengths[i] > max_tokens {
+            batches.push(std::mem::take(&mut batch));
+        }
+        batch.push(i);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+/// 大量文字分批 embed：以 tokenizer 計算長度，依 `micro_batches` 分批交給 embed，
+/// 減少 pad 的浪費並限制每批的記憶體用量。回傳的順序與 texts 相同
+pub fn embed_batched<S, F>(
+    texts: &[S],
+    tokenizer: &Tokenizer,
+    max_tokens: usize,
+    mut embed: F,
+) -> Result<Vec<Vec<f32>>>
+where
+    S: AsRef<str> + Sync,
+    F: FnMut(&[String]) -> Result<Vec<Vec<f32>>>,
+{
+    if max_tokens == 0 {
+        bail!("max_tokens must be greater than 0");
+    }
+    // pad 過的 encoding 以 attention_mask 計算實際長度
+    let lengths: Vec<usize> = tokenizer
+        .encode_batch(texts, true)?
+        .iter()
+        .map(|e| e.get_attention_mask().iter().filter(|&&m| m != 0).count())
+        .collect();
+
+    let mut embeddings = vec![vec![]; texts.len()];
+    for batch in micro_batches(&lengths, max_tokens) {
+        let inputs: Vec<String> = batch
+            .iter()
+            .map(|&i| texts[i].as_ref().to_string())
+            .collect();
+        let vectors = embed(&inputs)?;
+        if vectors.len() != batch.len() {
+            bail!(
+                "embed returned {} vectors for {} texts",
+                vectors.len(),
+                batch.len()
+            );
+        }
+        for (i, vector) in batch.into_iter().zip(vectors) {
+            embeddings[i] = vector;
+        }
+    }
+    Ok(embeddings)
+}
@@ -3,11 +3,56 @@ pub enum Error {
     #[error("{0}")]
     Wrapped(Box<dyn std::fmt::Display + Send + Sync>),
 
-    #[error("got eos token {eos_token_id} and {generated} tokens generated")]
-    Eos { eos_token_id: u32, generated: usize },
+    #[error("context length {tokens} exceeds {max_context}")]
+    ContextOverflow { max_context: usize, tokens: usize },
+
+    #[error(
+        "{model_id}: no tokenizer found, expected tokenizer.json, vocab.json + merges.txt or tokenizer.model"
+    )]
+    TokenizerNotFound { model_id: String },
+
+    #[error(
+        "{model_id}: tokenizer_config.json has no chat_template, use a base model prompt or a bundled ChatTemplateKind"
+    )]
+    ChatTemplateMissing { model_id: String },
+
+    #[error(
+        "chat template {name}: {message}{}",
+        render_location(.line, .column, .message_index)
+    )]
+    TemplateRender {
+        name: String,
+        line: Option<usize>,
+        column: Option<usize>,
+        /// 第一個 render 失敗的訊息
+        message_index: Option<usize>,
+        message: String,
+    },
+
+    #[error("{model_id}: no model weights found, tried {}", tried.join(", "))]
+    WeightsNotFound {
+        model_id: String,
+        tried: Vec<String>,
+    },
+
+    #[error("{model_id}: {file} not found")]
+    FileNotFound { model_id: String, file: String },
+
+    #[error(
+        "{model_id}: access denied, the repo may be gated: accept the license on huggingface.co and set a token"
+    )]
+    GatedRepo { model_id: String },
+
+    #[error(
+        "no pad token: set pad_token_id in generation_config.json or pad_token in the tokenizer, or provide an eos token"
+    )]
+    PadTokenNotFound,
+
+    #[error("generation timed out after {0:?}")]
+    Timeout(std::time::Duration),
 
-    #[error("max new tokens {max_new_tokens} exceeded")]
-    MaxNewTokenExceeded { max_new_tokens: usize },
+    #[error("generation panicked: {0}")]
+    Panicked(String),
 
     #[error("{inner}\n{backtrace}")]
     WithBacktrace {
@@ -40,6 +85,23 @@ pub enum Error {
     HfHub(#[from] hf_hub::api::sync::ApiError),
 }
 
+fn render_location(
+    line: &Option<usize>,
+    column: &Option<usize>,
+    message_index: &Option<usize>,
+) -> String {
+    let mut location = String::new();
+    match (line, column) {
+        (Some(line), Some(column)) => location += &format!(" (line {line}, column {column})"),
+        (Some(line), None) => location += &format!(" (line {line})"),
+        _ => {}
+    }
+    if let Some(index) = message_index {
+        location += &format!(" at messages[{index}]");
+    }
+    location
+}
+
 impl std::fmt::Debug for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{self}")
@@ -67,6 +129,16 @@ impl Error {
         Self::Msg(err.to_string()).bt()
     }
 
+    /// 檔案不存在 (HTTP 404 或本地檔案不存在)，其他錯誤如網路或 5xx 不算
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            Self::FileNotFound { .. } => true,
+            Self::Io(err) => err.kind() == std::io::ErrorKind::NotFound,
+            Self::WithBacktrace { inner, .. } => inner.is_not_found(),
+            _ => false,
+        }
+    }
+
     // #[cfg(feature = "hf_hub")]
     // pub fn hub(err: hf_hub::api::sync::ApiError) -> Self {
     //     Self::HfHub(err).bt()
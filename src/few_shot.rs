@@ -0,0 +1,152 @@
+#[cfg(feature = "chat-template")]
+use crate::chat_template::ChatTemplate;
+use crate::tokenizers::Tokenizer;
+use crate::{Result, bail};
+
+/// few-shot prompt: 說明、(input, output) 範例與最後的 query。
+///
+/// 可以 render 成 chat messages 或純文字，設定 token 上限時從最舊的範例開始刪除
+#[derive(Debug, Clone)]
+pub struct FewShot {
+    instruction: Option<String>,
+    examples: Vec<(String, String)>,
+    input_label: String,
+    output_label: String,
+    separator: String,
+    budget: Option<(Tokenizer, usize)>,
+}
+
+impl Default for FewShot {
+    fn default() -> Self {
+        Self {
+            instruction: None,
+            examples: vec![],
+            input_label: "Input:".to_string(),
+            output_label: "Output:".to_string(),
+            separator: "\n\n".to_string(),
+            budget: None,
+        }
+    }
+}
+
+impl FewShot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// chat 時為 system 訊息，純文字時放在最前面
+    pub fn with_instruction(mut self, instruction: &str) -> Self {
+        self.instruction = Some(instruction.to_string());
+        self
+    }
+
+    pub fn with_example(mut self, input: &str, output: &str) -> Self {
+        self.examples.push((input.to_string(), output.to_string()));
+        self
+    }
+
+    pub fn with_examples<I, O>(mut self, examples: impl IntoIterator<Item = (I, O)>) -> Self
+    where
+        I: Into<String>,
+        O: Into<String>,
+    {
+        self.examples
+            .extend(examples.into_iter().map(|(i, o)| (i.into(), o.into())));
+        self
+    }
+
+    /// 純文字格式的標籤，預設為 "Input:" 與 "Output:"
+    pub fn with_labels(mut self, input_label: &str, output_label: &str) -> Self {
+        self.input_label = input_label.to_string();
+        self.output_label = output_label.to_string();
+        self
+    }
+
+    /// 純文字格式中範例之間的分隔，預設為空一行
+    pub fn with_separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_string();
+        self
+    }
+
+    /// render 後的 prompt 最多 max_tokens 個 token，超過時從最舊的範例開始刪除
+    pub fn with_budget(mut self, tokenizer: &Tokenizer, max_tokens: usize) -> Result<Self> {
+        self.budget = Some((tokenizer.clone(), max_tokens));
+        Ok(self)
+    }
+
+    pub fn examples(&self) -> &[(String, String)] {
+        &self.examples
+    }
+
+    /// 以 role/content 表示的 messages，最後一則是 user 的 query
+    pub fn messages(&self, query: &str) -> Vec<serde_json::Value> {
+        self.messages_from(0, query)
+    }
+
+    fn messages_from(&self, skip: usize, query: &str) -> Vec<serde_json::Value> {
+        let message =
+            |role: &str, content: &str| serde_json::json!({ "role": role, "content": content });
+        let mut messages = vec![];
+        if let Some(instruction) = &self.instruction {
+            messages.push(message("system", instruction));
+        }
+        for (input, output) in &self.examples[skip..] {
+            messages.push(message("user", input));
+            messages.push(message("assistant", output));
+        }
+        messages.push(message("user", query));
+        messages
+    }
+
+    fn plain_from(&self, skip: usize, query: &str) -> String {
+        let mut parts = vec![];
+        if let Some(instruction) = &self.instruction {
+            parts.push(instruction.clone());
+        }
+        for (input, output) in &self.examples[skip..] {
+            parts.push(format!(
+                "{} {input}\n{} {output}",
+                self.input_label, self.output_label
+            ));
+        }
+        parts.push(format!(
+            "{} {query}\n{}",
+            self.input_label, self.output_label
+        ));
+        parts.join(&self.separator)
+    }
+
+    /// 以純文字 render，適合 base model
+    pub fn render(&self, query: &str) -> Result<String> {
+        self.fit(|skip| Ok(self.plain_from(skip, query)))
+    }
+
+    /// 以 chat template render 並加上 generation prompt
+    #[cfg(feature = "chat-template")]
+    pub fn apply(&self, template: &ChatTemplate, query: &str) -> Result<String> {
+        self.fit(|skip| {
+            template.apply(serde_json::json!({
+                "messages": self.messages_from(skip, query),
+                "add_generation_prompt": true,
+            }))
+        })
+    }
+
+    // 從最舊的範例開始刪除，直到 prompt 不超過 token 上限
+    fn fit<F>(&self, render: F) -> Result<String>
+    where
+        F: Fn(usize) -> Result<String>,
+    {
+        let Some((tokenizer, max_tokens)) = &self.budget else {
+            return render(0);
+        };
+        for skip in 0..=self.examples.len() {
+            let prompt = render(skip)?;
+            let tokens = tokenizer.encode_untruncated(&prompt, false)?.len();
+            if tokens <= *max_tokens {
+                return Ok(prompt);
+            }
+        }
+        bail!("few-shot prompt exceeds {max_tokens} tokens without examples")
+    }
+}
@@ -1,7 +1,21 @@
-use crate::{Result, repo::Repo};
-use candle_core::{DType, Device, Tensor};
+use crate::debug::{MemoryStats, memory_stats};
+use crate::logits::{
+    BeginSuppressTokens, ExponentialDecayLengthPenalty, ForceTokens, ForcedBos, ForcedEos,
+    FrequencyPresencePenalty, LogitBias, LogitsContext, LogitsTransform, MinNewTokens,
+    SuppressTokens, device_top_k, native_top_k, top_logprobs,
+};
+use crate::sampler::SamplerChain;
+use crate::stopping::{StoppingContext, StoppingCriteria};
+use crate::trace::{Trace, TraceStep};
+use crate::{Result, bail, repo::Repo};
+use candle_core::{D, DType, Device, Tensor};
 use candle_transformers::generation::{LogitsProcessor, Sampling};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread::JoinHandle;
+use std::time::Duration;
 use std::{fs::File, path::Path};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -11,14 +25,68 @@ pub enum Eos {
     Multi(Vec<u32>),
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+// stop_strings 可以是字串或字串陣列
+fn one_or_many<'de, D>(deserializer: D) -> std::result::Result<Option<Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(s)) => Some(vec![s]),
+        Some(OneOrMany::Many(v)) => Some(v),
+        None => None,
+    })
+}
+
+/// generation_config.json，未知的欄位會被忽略
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct GenerationConfig {
+    pub bos_token_id: Option<u32>,
+    pub pad_token_id: Option<u32>,
     pub eos_token_id: Option<Eos>,
+    /// false 時使用 greedy，忽略 temperature 等設定
+    pub do_sample: Option<bool>,
     pub temperature: Option<f64>,
     pub repetition_penalty: Option<f32>,
+    /// repetition penalty 與 frequency/presence penalty 只看最後 n 個 token，
+    /// 沒有設定時 repetition penalty 使用 TextGeneration::new 的參數，frequency/presence penalty 看所有生成的 token
+    pub repeat_last_n: Option<usize>,
+    /// OpenAI 的 frequency_penalty，依生成 token 的出現次數降低 logits
+    pub frequency_penalty: Option<f32>,
+    /// OpenAI 的 presence_penalty，已生成過的 token 降低 logits
+    pub presence_penalty: Option<f32>,
     pub top_p: Option<f64>,
     pub top_k: Option<usize>,
     pub max_new_tokens: Option<usize>,
+    /// 生成至少 min_new_tokens 個 token 前不會生成 eos
+    pub min_new_tokens: Option<usize>,
+    /// prompt + 生成的長度上限
+    pub max_length: Option<usize>,
+    pub min_length: Option<usize>,
+    pub num_return_sequences: Option<usize>,
+    /// 不支援 beam search，只用於讀取設定
+    pub num_beams: Option<usize>,
+    pub no_repeat_ngram_size: Option<usize>,
+    pub typical_p: Option<f64>,
+    pub min_p: Option<f64>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub stop_strings: Option<Vec<String>>,
+    pub use_cache: Option<bool>,
+    pub suppress_tokens: Option<Vec<u32>>,
+    pub begin_suppress_tokens: Option<Vec<u32>>,
+    /// [[index, token_id], ...]
+    pub forced_decoder_ids: Option<Vec<(usize, u32)>>,
+    pub forced_bos_token_id: Option<u32>,
+    pub forced_eos_token_id: Option<Eos>,
+    /// 只用於排序多個結果，見 `GeneratedSequence::score`
+    pub length_penalty: Option<f64>,
+    /// (start_index, decay_factor)
+    pub exponential_decay_length_penalty: Option<(usize, f32)>,
 }
 
 impl GenerationConfig {
@@ -31,6 +99,62 @@ impl GenerationConfig {
         Ok(serde_json::from_reader(file)?)
     }
 
+    /// other 中有設定的欄位覆寫 self，eg: 每個 request 的設定覆寫 repo 的預設值
+    pub fn merge(&mut self, other: &GenerationConfig) {
+        macro_rules! merge {
+            ($($field:ident),* $(,)?) => {
+                $(
+                    if other.$field.is_some() {
+                        self.$field = other.$field.clone();
+                    }
+                )*
+            };
+        }
+        merge!(
+            bos_token_id,
+            pad_token_id,
+            eos_token_id,
+            do_sample,
+            temperature,
+            repetition_penalty,
+            repeat_last_n,
+            frequency_penalty,
+            presence_penalty,
+            top_p,
+            top_k,
+            max_new_tokens,
+            min_new_tokens,
+            max_length,
+            min_length,
+            num_return_sequences,
+            num_beams,
+            no_repeat_ngram_size,
+            typical_p,
+            min_p,
+            stop_strings,
+            use_cache,
+            suppress_tokens,
+            begin_suppress_tokens,
+            forced_decoder_ids,
+            forced_bos_token_id,
+            forced_eos_token_id,
+            length_penalty,
+            exponential_decay_length_penalty,
+        );
+    }
+
+    pub fn set_do_sample(&mut self, do_sample: bool) {
+        self.do_sample = Some(do_sample);
+    }
+
+    pub fn set_min_new_tokens(&mut self, min_new_tokens: usize) {
+        self.min_new_tokens = Some(min_new_tokens);
+    }
+
+    pub fn set_stop_strings(&mut self, stop_strings: Vec<String>) {
+        self.stop_strings = Some(stop_strings);
+    }
+
     pub fn set_eos_token_id(&mut self, eos_token_id: Eos) {
         self.eos_token_id = Some(eos_token_id);
     }
@@ -43,6 +167,18 @@ impl GenerationConfig {
         self.repetition_penalty = Some(repetition_penalty);
     }
 
+    pub fn set_repeat_last_n(&mut self, repeat_last_n: usize) {
+        self.repeat_last_n = Some(repeat_last_n);
+    }
+
+    pub fn set_frequency_penalty(&mut self, frequency_penalty: f32) {
+        self.frequency_penalty = Some(frequency_penalty);
+    }
+
+    pub fn set_presence_penalty(&mut self, presence_penalty: f32) {
+        self.presence_penalty = Some(presence_penalty);
+    }
+
     pub fn set_top_p(&mut self, top_p: f64) {
         self.top_p = Some(top_p);
     }
@@ -51,6 +187,38 @@ impl GenerationConfig {
         self.top_k = Some(top_k);
     }
 
+    pub fn set_suppress_tokens(&mut self, suppress_tokens: Vec<u32>) {
+        self.suppress_tokens = Some(suppress_tokens);
+    }
+
+    pub fn set_begin_suppress_tokens(&mut self, begin_suppress_tokens: Vec<u32>) {
+        self.begin_suppress_tokens = Some(begin_suppress_tokens);
+    }
+
+    pub fn set_forced_decoder_ids(&mut self, forced_decoder_ids: Vec<(usize, u32)>) {
+        self.forced_decoder_ids = Some(forced_decoder_ids);
+    }
+
+    pub fn set_forced_bos_token_id(&mut self, forced_bos_token_id: u32) {
+        self.forced_bos_token_id = Some(forced_bos_token_id);
+    }
+
+    pub fn set_forced_eos_token_id(&mut self, forced_eos_token_id: Eos) {
+        self.forced_eos_token_id = Some(forced_eos_token_id);
+    }
+
+    pub fn set_length_penalty(&mut self, length_penalty: f64) {
+        self.length_penalty = Some(length_penalty);
+    }
+
+    pub fn set_exponential_decay_length_penalty(&mut self, start_index: usize, decay_factor: f32) {
+        self.exponential_decay_length_penalty = Some((start_index, decay_factor));
+    }
+
+    pub fn get_length_penalty_or(&self, default: f64) -> f64 {
+        self.length_penalty.unwrap_or(default)
+    }
+
     pub fn get_eos_token_id(&self) -> Option<Vec<u32>> {
         match &self.eos_token_id {
             Some(Eos::Single(id)) => Some(vec![*id]),
@@ -63,13 +231,22 @@ impl GenerationConfig {
         self.repetition_penalty.unwrap_or(default)
     }
 
+    pub fn get_repeat_last_n_or(&self, default: usize) -> usize {
+        self.repeat_last_n.unwrap_or(default)
+    }
+
     pub fn get_max_new_tokens_or(&self, default: usize) -> usize {
         self.max_new_tokens.unwrap_or(default)
     }
 
+    pub fn get_num_return_sequences_or(&self, default: usize) -> usize {
+        self.num_return_sequences.unwrap_or(default)
+    }
+
     pub fn sampling(&self) -> Sampling {
         let temperature = self
             .temperature
+            .filter(|_| self.do_sample != Some(false))
             .and_then(|v| if v < 1e-7 { None } else { Some(v) });
 
         match temperature {
@@ -83,6 +260,62 @@ impl GenerationConfig {
         }
     }
 
+    /// generation_config.json 中設定的 logits transform，TextGeneration 會自動加入
+    pub fn logits_transforms(&self) -> Vec<Box<dyn LogitsTransform>> {
+        let mut transforms: Vec<Box<dyn LogitsTransform>> = vec![];
+        if let Some(ids) = self.suppress_tokens.as_ref().filter(|ids| !ids.is_empty()) {
+            transforms.push(Box::new(SuppressTokens(ids.clone())));
+        }
+        if let Some(ids) = self
+            .begin_suppress_tokens
+            .as_ref()
+            .filter(|ids| !ids.is_empty())
+        {
+            transforms.push(Box::new(BeginSuppressTokens(ids.clone())));
+        }
+        if let Some(ids) = self
+            .forced_decoder_ids
+            .as_ref()
+            .filter(|ids| !ids.is_empty())
+        {
+            transforms.push(Box::new(ForceTokens(ids.iter().copied().collect())));
+        }
+        if let Some(id) = self.forced_bos_token_id {
+            transforms.push(Box::new(ForcedBos(id)));
+        }
+        match &self.forced_eos_token_id {
+            Some(Eos::Single(id)) => transforms.push(Box::new(ForcedEos(vec![*id]))),
+            Some(Eos::Multi(ids)) if !ids.is_empty() => {
+                transforms.push(Box::new(ForcedEos(ids.clone())))
+            }
+            _ => {}
+        }
+        // 只使用 config 中的 eos，之後 add_eos_token_id 加入的不會生效
+        if let Some(min_new_tokens) = self.min_new_tokens.filter(|&n| n > 0) {
+            transforms.push(Box::new(MinNewTokens {
+                min_new_tokens,
+                eos_token_id: self.get_eos_token_id().unwrap_or_default(),
+            }));
+        }
+        if let Some((start_index, decay_factor)) = self.exponential_decay_length_penalty {
+            transforms.push(Box::new(ExponentialDecayLengthPenalty {
+                start_index,
+                decay_factor,
+                eos_token_id: self.get_eos_token_id().unwrap_or_default(),
+            }));
+        }
+        let frequency = self.frequency_penalty.unwrap_or(0.);
+        let presence = self.presence_penalty.unwrap_or(0.);
+        if frequency != 0. || presence != 0. {
+            transforms.push(Box::new(FrequencyPresencePenalty {
+                frequency,
+                presence,
+                last_n: self.repeat_last_n,
+            }));
+        }
+        transforms
+    }
+
     pub fn logits_processor(&self, seed: u64) -> LogitsProcessor {
         let sampling = self.sampling();
         LogitsProcessor::from_sampling(seed, sampling)
@@ -92,22 +325,278 @@ impl GenerationConfig {
 pub trait Model {
     fn forward(&mut self, x: &Tensor, start_pos: usize) -> Result<Tensor>;
     fn reset(&mut self);
+
+    /// kv cache 中的 token 數，不支援時回傳 None
+    fn kv_cache_len(&self) -> Option<usize> {
+        None
+    }
+
+    /// kv cache 佔用的記憶體 (bytes)，不支援時回傳 None
+    fn kv_memory_bytes(&self) -> Option<usize> {
+        None
+    }
+
+    /// 只保留 kv cache 中前 len 個位置，之後從 start_pos = len 繼續 forward。
+    /// 不支援時回傳 false，呼叫端需要 reset 後重新 prefill
+    fn trim_to(&mut self, len: usize) -> Result<bool> {
+        let _ = len;
+        Ok(false)
+    }
+}
+
+impl<M: Model + ?Sized> Model for &mut M {
+    fn forward(&mut self, x: &Tensor, start_pos: usize) -> Result<Tensor> {
+        (**self).forward(x, start_pos)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn kv_cache_len(&self) -> Option<usize> {
+        (**self).kv_cache_len()
+    }
+
+    fn kv_memory_bytes(&self) -> Option<usize> {
+        (**self).kv_memory_bytes()
+    }
+
+    fn trim_to(&mut self, len: usize) -> Result<bool> {
+        (**self).trim_to(len)
+    }
+}
+
+impl<M: Model + ?Sized> Model for Box<M> {
+    fn forward(&mut self, x: &Tensor, start_pos: usize) -> Result<Tensor> {
+        (**self).forward(x, start_pos)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn kv_cache_len(&self) -> Option<usize> {
+        (**self).kv_cache_len()
+    }
+
+    fn kv_memory_bytes(&self) -> Option<usize> {
+        (**self).kv_memory_bytes()
+    }
+
+    fn trim_to(&mut self, len: usize) -> Result<bool> {
+        (**self).trim_to(len)
+    }
+}
+
+/// 跨執行緒共用同一個已載入的模型。
+///
+/// kv cache 存在模型內，所以不能讓多個生成交錯呼叫 forward；
+/// 每次 `with` 會獨佔模型直到整段生成結束，eg:
+/// `shared.with(|model| TextGeneration::new(model, ...).apply(...))`
+pub struct SharedModel<M> {
+    inner: Arc<Mutex<M>>,
+}
+
+impl<M> Clone for SharedModel<M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<M: Model> SharedModel<M> {
+    pub fn new(model: M) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(model)),
+        }
+    }
+
+    /// 等待取得模型後執行 f
+    pub fn with<T, F: FnOnce(&mut M) -> T>(&self, f: F) -> Result<T> {
+        let mut model = self
+            .inner
+            .lock()
+            .map_err(|_| crate::Error::msg("shared model mutex poisoned"))?;
+        Ok(f(&mut model))
+    }
+
+    /// 模型正在被使用時回傳 None
+    pub fn try_with<T, F: FnOnce(&mut M) -> T>(&self, f: F) -> Option<T> {
+        let mut model = self.inner.try_lock().ok()?;
+        Some(f(&mut model))
+    }
+}
+
+/// 生成結束的原因
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// 生成了 eos token
+    Eos { token_id: u32 },
+    /// 達到 max_new_tokens
+    MaxNewTokens,
+    /// 由 stopping criteria 停止，index 為加入的順序
+    Criteria { index: usize },
+}
+
+/// 每一步生成的結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Token(u32),
+    Finished(StopReason),
+}
+
+/// token 用量，欄位同 OpenAI API 的 usage
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+impl Usage {
+    pub fn new(prompt_tokens: usize, completion_tokens: usize) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+impl std::ops::Add for Usage {
+    type Output = Usage;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Usage::new(
+            self.prompt_tokens + rhs.prompt_tokens,
+            self.completion_tokens + rhs.completion_tokens,
+        )
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+/// generate_n 的單一結果
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GeneratedSequence {
+    /// 生成的 token，不含 prompt，結束於 eos 時包含 eos
+    pub tokens: Vec<u32>,
+    /// 生成 token 的 log probability 總和
+    pub logprob: f64,
+    pub stop_reason: StopReason,
+}
+
+impl GeneratedSequence {
+    /// 依長度正規化的分數 logprob / len^length_penalty，同 transformers beam search 的評分。
+    /// length_penalty > 0 偏好較長的結果，< 0 偏好較短的結果
+    pub fn score(&self, length_penalty: f64) -> f64 {
+        self.logprob / (self.tokens.len().max(1) as f64).powf(length_penalty)
+    }
+}
+
+/// 一次生成的記憶體用量，見 `debug::memory_stats`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub before_prefill: MemoryStats,
+    pub after_prefill: MemoryStats,
+    /// decode 期間觀察到的最大用量：prefill 後與每個 decode step 結束時
+    /// `allocated` 的最大值。`MemoryStats::peak` 是整個 process 的最高值，不適用於此
+    pub peak_decode: Option<u64>,
+}
+
+/// 超過模型 context 長度時的處理方式
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContextOverflow {
+    /// 回傳 `Error::ContextOverflow`
+    Error,
+    /// 丟掉較舊的一半 token 後重新 prefill
+    TruncateLeft,
+    /// 保留最前面 `sink_tokens` 個 token (StreamingLLM 的 attention sink)，
+    /// 其餘同 TruncateLeft
+    SlidingWindow { sink_tokens: usize },
+}
+
+/// TextGeneration 的快照，用於中斷後繼續生成。
+/// 沒有 kv cache，恢復後會重新 prefill；logits transform 與 stopping criteria 需要自行重新加入，
+/// 依原本的順序加入時，有自己亂數的 transform (eg: Xtc) 會恢復快照時的亂數狀態
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    pub config: GenerationConfig,
+    pub seed: u64,
+    /// 消耗亂數的 sampling 次數 (不含 argmax)，用來恢復亂數狀態
+    pub samples: u64,
+    pub repeat_last_n: usize,
+    pub eos_token_id: Vec<u32>,
+    pub max_new_tokens: usize,
+    pub prompt_tokens: usize,
+    pub generated_tokens: usize,
+    #[serde(default)]
+    pub logprob: f64,
+    pub tokens: Vec<u32>,
+    pub context: Vec<u32>,
+    pub finished: Option<StopReason>,
+    pub max_context: Option<usize>,
+    pub overflow: ContextOverflow,
+    /// 每個 logits transform 的亂數狀態，依加入順序，沒有亂數的為 None
+    #[serde(default)]
+    pub transform_rng: Vec<Option<u64>>,
+}
+
+impl Checkpoint {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = File::create(path)?;
+        Ok(serde_json::to_writer(file, self)?)
+    }
 }
 
 pub struct TextGeneration<M: Model> {
     model: M,
     device: Device,
+    config: GenerationConfig,
+    seed: u64,
+    samples: u64,
+    needs_prefill: bool,
     logits_processor: LogitsProcessor,
     repetition_penalty: f32,
     repeat_last_n: usize,
     eos_token_id: Vec<u32>,
+    transforms: Vec<Box<dyn LogitsTransform>>,
+    // set_sampler_chain 加入的 transform 在 transforms 中的位置
+    sampler_chain: Option<std::ops::Range<usize>>,
+    // from_checkpoint 後，重新加入的 transform 依位置恢復亂數狀態
+    transform_rng: Vec<Option<u64>>,
+    stopping_criteria: Vec<Box<dyn StoppingCriteria>>,
 
     max_new_tokens: usize,
+    prompt_tokens: usize,
     generated_tokens: usize,
     tokens: Vec<u32>,
+    finished: Option<StopReason>,
+    logprob: f64,
+    trace: Option<Trace>,
+    memory: Option<MemoryUsage>,
+    native_sampling: bool,
+    device_sampling: bool,
+    deterministic: bool,
+
+    max_context: Option<usize>,
+    overflow: ContextOverflow,
+    // 目前在 kv cache 中的 token，沒有發生 overflow 時與 tokens 相同
+    context: Vec<u32>,
 }
 
 impl<M: Model> TextGeneration<M> {
+    /// repeat_last_n 為 config 沒有設定 repeat_last_n 時的預設值
     pub fn new(
         model: M,
         device: Device,
@@ -118,40 +607,422 @@ impl<M: Model> TextGeneration<M> {
         Self {
             model,
             device,
+            config: config.clone(),
+            seed,
+            samples: 0,
+            needs_prefill: false,
             logits_processor: config.logits_processor(seed),
             repetition_penalty: config.get_repetition_penalty_or(1.),
-            repeat_last_n,
-            eos_token_id: config.get_eos_token_id().unwrap(),
+            repeat_last_n: config.get_repeat_last_n_or(repeat_last_n),
+            eos_token_id: config.get_eos_token_id().unwrap_or_default(),
+            transforms: config.logits_transforms(),
+            sampler_chain: None,
+            transform_rng: Vec::new(),
+            stopping_criteria: Vec::new(),
             max_new_tokens: config.get_max_new_tokens_or(0),
+            prompt_tokens: 0,
             generated_tokens: 0,
             tokens: Vec::new(),
+            finished: None,
+            logprob: 0.,
+            trace: None,
+            memory: None,
+            native_sampling: false,
+            device_sampling: false,
+            deterministic: false,
+            max_context: None,
+            overflow: ContextOverflow::Error,
+            context: Vec::new(),
+        }
+    }
+
+    /// 加入 sampling 前的 logits transform，依加入順序執行
+    pub fn add_transform<T: LogitsTransform + 'static>(&mut self, transform: T) {
+        self.transforms.push(Box::new(transform));
+        self.restore_transform_rng();
+    }
+
+    // 只恢復一次，之後重新加入的 transform 從 seed 開始
+    fn restore_transform_rng(&mut self) {
+        for (transform, state) in self.transforms.iter_mut().zip(&mut self.transform_rng) {
+            if let Some(state) = state.take() {
+                transform.set_rng_state(state);
+            }
+        }
+    }
+
+    /// 加入 stopping criteria，每生成一個 token 後依加入順序檢查
+    pub fn add_stopping_criteria<S: StoppingCriteria + 'static>(&mut self, criteria: S) {
+        self.stopping_criteria.push(Box::new(criteria));
+    }
+
+    /// 依 chain 的順序加入 transform，取代 config 的 temperature、top_k 與 top_p。
+    /// 最後以 temperature 1 sampling，chain 中有 greedy 時改用 argmax。
+    /// 再次呼叫時取代前一個 chain 的 transform；不會重設 sampling 的亂數狀態
+    pub fn set_sampler_chain(&mut self, chain: &SamplerChain) -> Result<()> {
+        self.config.do_sample = Some(!chain.is_greedy());
+        self.config.temperature = Some(1.);
+        self.config.top_k = None;
+        self.config.top_p = None;
+        self.restore_logits_processor()?;
+        let transforms = chain.transforms(self.seed);
+        let end = self.transforms.len();
+        let range = self.sampler_chain.take().unwrap_or(end..end);
+        let start = range.start;
+        let len = transforms.len();
+        self.transforms.splice(range, transforms);
+        self.sampler_chain = Some(start..start + len);
+        self.restore_transform_rng();
+        Ok(())
+    }
+
+    // 依 config 重建 LogitsProcessor，並回到已經 sampling samples 次的亂數狀態。
+    // candle 的 LogitsProcessor 每次非 argmax 的 sampling 固定消耗一次亂數，argmax 不消耗
+    fn restore_logits_processor(&mut self) -> Result<()> {
+        self.logits_processor = self.config.logits_processor(self.seed);
+        let dummy = Tensor::new(&[0f32, 0f32], &Device::Cpu)?;
+        for _ in 0..self.samples {
+            self.logits_processor.sample(&dummy)?;
+        }
+        Ok(())
+    }
+
+    /// OpenAI 風格的 logit_bias
+    pub fn set_logit_bias(&mut self, logit_bias: HashMap<u32, f32>) {
+        self.add_transform(LogitBias(logit_bias));
+    }
+
+    /// 以 model 的 dtype (F16、BF16) 直接 sampling，不把整個 vocab 的 logits 轉為 F32，
+    /// 只轉換 top-k 的候選。只支援 greedy 與 top-k (top-p 只在 top-k 的候選中套用)，
+    /// 有 repetition penalty、transform、stopping criteria 或 trace 時使用原本的方式。
+    ///
+    /// top-k 候選的順序與原本不同，相同 seed 生成的結果不一定相同
+    pub fn set_native_sampling(&mut self, native_sampling: bool) {
+        self.native_sampling = native_sampling;
+    }
+
+    /// 在 model 所在的 device 上做 argmax 與 top-k，不把整個 vocab 的 logits 複製回 host。
+    /// 適用的條件同 set_native_sampling，device 不支援 top-k 的排序時改用 native sampling
+    pub fn set_device_sampling(&mut self, device_sampling: bool) {
+        self.device_sampling = device_sampling;
+    }
+
+    /// 每次 apply 都以 seed 重設 sampling 的亂數，相同的 prompt 會得到相同的結果
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// 以 seed 重設 sampling 與 logits transform (eg: Xtc) 的亂數
+    pub fn reseed(&mut self) {
+        self.logits_processor = self.config.logits_processor(self.seed);
+        self.samples = 0;
+        for transform in self.transforms.iter_mut() {
+            transform.reseed(self.seed);
         }
     }
 
-    pub fn apply(&mut self, ids: &[u32], max_new_tokens: usize) -> Result<u32> {
+    /// 設定模型可接受的最大 context 長度，eg: config.json 的 max_position_embeddings
+    pub fn set_context_limit(
+        &mut self,
+        max_context: usize,
+        overflow: ContextOverflow,
+    ) -> Result<()> {
+        if max_context == 0 {
+            bail!("max_context must be greater than 0");
+        }
+        self.max_context = Some(max_context);
+        self.overflow = overflow;
+        Ok(())
+    }
+
+    pub fn apply(&mut self, ids: &[u32], max_new_tokens: usize) -> Result<Step> {
         self.model.reset();
+        self.start(ids, max_new_tokens);
+        self.prefill(self.context.len())
+    }
+
+    /// 多輪對話時使用：ids 為套用 chat template 後完整的 prompt，與 kv cache 中相同的前綴不再 forward，
+    /// 只 prefill 之後的 token。template 修改了前面的文字時，從第一個不同的 token 開始重新 prefill；
+    /// 模型不支援 trim_to 或完全沒有相同的前綴時同 apply
+    pub fn apply_incremental(&mut self, ids: &[u32], max_new_tokens: usize) -> Result<Step> {
+        // 最後生成的 token 還沒有 forward，不在 kv cache 中
+        let cached = self
+            .model
+            .kv_cache_len()
+            .unwrap_or(self.context.len().saturating_sub(1))
+            .min(self.context.len());
+        let common = self.context[..cached]
+            .iter()
+            .zip(ids)
+            .take_while(|(a, b)| a == b)
+            .count();
+        // 至少 forward 一個 token 才有 logits
+        let keep = common.min(ids.len().saturating_sub(1));
+        if keep == 0 || self.needs_prefill || !self.model.trim_to(keep)? {
+            return self.apply(ids, max_new_tokens);
+        }
+        self.start(ids, max_new_tokens);
+        self.prefill(ids.len() - keep)
+    }
+
+    // 重設生成的狀態，不包含模型的 kv cache
+    fn start(&mut self, ids: &[u32], max_new_tokens: usize) {
+        if self.deterministic {
+            self.reseed();
+        }
         self.tokens = ids.to_vec();
+        self.context = ids.to_vec();
+        self.prompt_tokens = ids.len();
         self.generated_tokens = 0;
+        self.finished = None;
+        self.logprob = 0.;
+        self.needs_prefill = false;
+        for criteria in self.stopping_criteria.iter_mut() {
+            criteria.reset();
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            trace.input_ids = ids.to_vec();
+            trace.steps.clear();
+        }
         self.max_new_tokens = max_new_tokens;
-        self.next_token(self.tokens.len())
     }
 
-    pub fn next(&mut self) -> Result<u32> {
-        self.next_token(1)
+    fn prefill(&mut self, context_size: usize) -> Result<Step> {
+        if self.memory.is_none() {
+            return self.next_token(context_size);
+        }
+
+        let before_prefill = memory_stats(&self.device)?;
+        let step = self.next_token(context_size)?;
+        self.device.synchronize()?;
+        let after_prefill = memory_stats(&self.device)?;
+        self.memory = Some(MemoryUsage {
+            before_prefill,
+            after_prefill,
+            peak_decode: after_prefill.allocated,
+        });
+        Ok(step)
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Step> {
+        if self.needs_prefill {
+            self.needs_prefill = false;
+            self.model.reset();
+            return self.next_token(self.context.len());
+        }
+        let step = self.next_token(1)?;
+        if let Some(memory) = self.memory.as_mut() {
+            let stats = memory_stats(&self.device)?;
+            if let Some(used) = stats.allocated {
+                memory.peak_decode = Some(memory.peak_decode.map_or(used, |peak| peak.max(used)));
+            }
+        }
+        Ok(step)
     }
 
-    pub(crate) fn next_token(&mut self, context_size: usize) -> Result<u32> {
-        if self.generated_tokens >= self.max_new_tokens {
-            return Err(crate::Error::MaxNewTokenExceeded {
-                max_new_tokens: self.max_new_tokens,
+    /// 以同一個 prompt 生成 n 次。模型沒有提供 kv cache 複製，所以每次都會重新 prefill
+    pub fn generate_n(
+        &mut self,
+        ids: &[u32],
+        max_new_tokens: usize,
+        n: usize,
+    ) -> Result<Vec<GeneratedSequence>> {
+        let mut sequences = Vec::with_capacity(n);
+        for _ in 0..n {
+            let mut step = self.apply(ids, max_new_tokens)?;
+            while let Step::Token(_) = step {
+                step = self.next()?;
+            }
+            let Step::Finished(stop_reason) = step else {
+                unreachable!()
+            };
+            sequences.push(GeneratedSequence {
+                tokens: self.tokens[self.prompt_tokens..].to_vec(),
+                logprob: self.logprob,
+                stop_reason,
             });
         }
+        Ok(sequences)
+    }
 
-        let start_pos = self.tokens.len().saturating_sub(context_size);
-        let ctxt = &self.tokens[start_pos..];
-        let input = Tensor::new(ctxt, &self.device)?.unsqueeze(0)?;
-        let logits = self.model.forward(&input, start_pos)?;
-        let logits = logits.squeeze(0)?.squeeze(0)?.to_dtype(DType::F32)?;
+    /// 開始記錄每一步的 top-k log probability，下一次 apply 時生效
+    pub fn record_trace(&mut self, top_k: usize) {
+        self.trace = Some(Trace {
+            seed: self.seed,
+            top_k,
+            ..Default::default()
+        });
+    }
+
+    /// 開始記錄每次生成的記憶體用量，下一次 apply 時生效
+    pub fn record_memory(&mut self) {
+        self.memory = Some(MemoryUsage::default());
+    }
+
+    /// 最近一次 apply 的記憶體用量
+    pub fn memory_usage(&self) -> Option<&MemoryUsage> {
+        self.memory.as_ref()
+    }
+
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    /// 目前已生成 token 的 log probability 總和
+    pub fn logprob(&self) -> f64 {
+        self.logprob
+    }
+
+    pub fn snapshot(&self) -> Checkpoint {
+        Checkpoint {
+            config: self.config.clone(),
+            seed: self.seed,
+            samples: self.samples,
+            repeat_last_n: self.repeat_last_n,
+            eos_token_id: self.eos_token_id.clone(),
+            max_new_tokens: self.max_new_tokens,
+            prompt_tokens: self.prompt_tokens,
+            generated_tokens: self.generated_tokens,
+            logprob: self.logprob,
+            tokens: self.tokens.clone(),
+            context: self.context.clone(),
+            finished: self.finished,
+            max_context: self.max_context,
+            overflow: self.overflow,
+            transform_rng: self.transforms.iter().map(|t| t.rng_state()).collect(),
+        }
+    }
+
+    /// 將目前生成狀態寫入檔案 (JSON)
+    pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.snapshot().save(path)
+    }
+
+    /// 從檔案恢復生成，接著呼叫 next() 即可繼續
+    pub fn resume<P: AsRef<Path>>(path: P, model: M, device: Device) -> Result<Self> {
+        Self::from_checkpoint(Checkpoint::from_file(path)?, model, device)
+    }
+
+    pub fn from_checkpoint(checkpoint: Checkpoint, model: M, device: Device) -> Result<Self> {
+        let mut generation = Self::new(
+            model,
+            device,
+            &checkpoint.config,
+            checkpoint.seed,
+            checkpoint.repeat_last_n,
+        );
+
+        generation.samples = checkpoint.samples;
+        generation.restore_logits_processor()?;
+        generation.transform_rng = checkpoint.transform_rng;
+        generation.eos_token_id = checkpoint.eos_token_id;
+        generation.max_new_tokens = checkpoint.max_new_tokens;
+        generation.prompt_tokens = checkpoint.prompt_tokens;
+        generation.generated_tokens = checkpoint.generated_tokens;
+        generation.logprob = checkpoint.logprob;
+        generation.tokens = checkpoint.tokens;
+        generation.context = checkpoint.context;
+        generation.finished = checkpoint.finished;
+        generation.max_context = checkpoint.max_context;
+        generation.overflow = checkpoint.overflow;
+        generation.needs_prefill = !generation.context.is_empty();
+        Ok(generation)
+    }
+
+    /// 先跑一次 prefill 與 decode，讓 kernel 編譯與記憶體配置在正式生成前完成。
+    /// 不會影響 sampling 的亂數狀態，結束後會清除 kv cache
+    pub fn warmup(&mut self, max_seq: usize, decode_steps: usize) -> Result<()> {
+        let max_seq = match self.max_context {
+            Some(max_context) => max_seq.min(max_context.saturating_sub(decode_steps)),
+            None => max_seq,
+        };
+        let prompt = vec![0u32; max_seq.max(1)];
+        self.model.reset();
+        let input = Tensor::new(prompt.as_slice(), &self.device)?.unsqueeze(0)?;
+        self.model.forward(&input, 0)?;
+        for pos in prompt.len()..prompt.len() + decode_steps {
+            let input = Tensor::new(&[0u32], &self.device)?.unsqueeze(0)?;
+            self.model.forward(&input, pos)?;
+        }
+        self.device.synchronize()?;
+        self.model.reset();
+        Ok(())
+    }
+
+    /// 加入額外的 eos token，eg: tokenizer 的 eos_token_id
+    pub fn add_eos_token_id(&mut self, eos_token_id: u32) {
+        if !self.eos_token_id.contains(&eos_token_id) {
+            self.eos_token_id.push(eos_token_id);
+        }
+    }
+
+    pub fn eos_token_ids(&self) -> &[u32] {
+        &self.eos_token_id
+    }
+
+    pub fn generated_tokens(&self) -> usize {
+        self.generated_tokens
+    }
+
+    /// 最近一次 apply 的 token 用量
+    pub fn usage(&self) -> Usage {
+        Usage::new(self.prompt_tokens, self.generated_tokens)
+    }
+
+    /// 模型 kv cache 中的 token 數
+    pub fn kv_cache_len(&self) -> Option<usize> {
+        self.model.kv_cache_len()
+    }
+
+    pub fn kv_memory_bytes(&self) -> Option<usize> {
+        self.model.kv_memory_bytes()
+    }
+
+    /// 目前所有的 token (prompt + 已生成)
+    pub fn tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    // context 超過上限時，依設定回傳錯誤或是縮減 context，回傳需要重新 prefill 的長度
+    fn fit_context(&mut self, context_size: usize) -> Result<usize> {
+        let Some(max_context) = self.max_context else {
+            return Ok(context_size);
+        };
+        if self.context.len() <= max_context {
+            return Ok(context_size);
+        }
+
+        let sink_tokens = match self.overflow {
+            ContextOverflow::Error => {
+                return Err(crate::Error::ContextOverflow {
+                    max_context,
+                    tokens: self.context.len(),
+                });
+            }
+            ContextOverflow::TruncateLeft => 0,
+            ContextOverflow::SlidingWindow { sink_tokens } => sink_tokens.min(max_context / 2),
+        };
+
+        // 至少保留最後一個 token，否則下一次 forward 沒有輸入
+        let keep = ((max_context - sink_tokens) / 2).max(1);
+        let mut context = self.context[..sink_tokens].to_vec();
+        context.extend_from_slice(&self.context[self.context.len() - keep..]);
+        self.context = context;
+        self.model.reset();
+        Ok(self.context.len())
+    }
+
+    // F32 的 logits 經過 repetition penalty 與 transform 後 sampling，回傳 sampling 時的 logits
+    fn sample(&mut self, logits: Tensor) -> Result<(u32, Tensor)> {
+        let top = match &self.trace {
+            Some(trace) => Some(top_logprobs(&logits, trace.top_k)?),
+            None => None,
+        };
         let logits = if self.repetition_penalty == 1. {
             logits
         } else {
@@ -163,56 +1034,128 @@ impl<M: Model> TextGeneration<M> {
             )?
         };
 
+        let ctx = LogitsContext {
+            tokens: &self.tokens,
+            prompt_tokens: self.prompt_tokens,
+            max_new_tokens: self.max_new_tokens,
+        };
+        let mut logits = logits;
+        for transform in self.transforms.iter_mut() {
+            logits = transform.apply(&logits, &ctx)?;
+        }
+
         let next_token = self.logits_processor.sample(&logits)?;
+        self.count_sample();
+        if let (Some(trace), Some(top_logprobs)) = (self.trace.as_mut(), top) {
+            trace.steps.push(TraceStep {
+                token: next_token,
+                top_logprobs,
+            });
+        }
+        self.logprob += candle_nn::ops::log_softmax(&logits, D::Minus1)?
+            .get(next_token as usize)?
+            .to_scalar::<f32>()? as f64;
+        Ok((next_token, logits))
+    }
+
+    // 只計算消耗亂數的 sampling，中途切換成 argmax 時恢復的亂數位置才正確
+    fn count_sample(&mut self) {
+        if !matches!(self.config.sampling(), Sampling::ArgMax) {
+            self.samples += 1;
+        }
+    }
+
+    // native sampling 可以使用時回傳候選數，greedy 為 1
+    fn native_top_k(&self) -> Option<usize> {
+        if !(self.native_sampling || self.device_sampling)
+            || self.repetition_penalty != 1.
+            || !self.transforms.is_empty()
+            || !self.stopping_criteria.is_empty()
+            || self.trace.is_some()
+        {
+            return None;
+        }
+        match self.config.sampling() {
+            Sampling::ArgMax => Some(1),
+            Sampling::TopK { k, .. } | Sampling::TopKThenTopP { k, .. } => Some(k),
+            _ => None,
+        }
+    }
+
+    fn sample_native(&mut self, logits: &Tensor, k: usize) -> Result<u32> {
+        let candidates = if self.device_sampling {
+            device_top_k(logits, k).or_else(|_| native_top_k(logits, k))?
+        } else {
+            native_top_k(logits, k)?
+        };
+        // 候選已經是 top k，LogitsProcessor 只在候選中 sampling
+        let index = self
+            .logits_processor
+            .sample(&Tensor::new(candidates.logits.as_slice(), &Device::Cpu)?)?
+            as usize;
+        self.count_sample();
+        self.logprob += candidates.logits[index] as f64 - candidates.logsumexp;
+        Ok(candidates.ids[index])
+    }
+
+    pub(crate) fn next_token(&mut self, context_size: usize) -> Result<Step> {
+        if let Some(reason) = self.finished {
+            return Ok(Step::Finished(reason));
+        }
+        if self.generated_tokens >= self.max_new_tokens {
+            self.finished = Some(StopReason::MaxNewTokens);
+            return Ok(Step::Finished(StopReason::MaxNewTokens));
+        }
+
+        let context_size = self.fit_context(context_size)?;
+        let start_pos = self.context.len().saturating_sub(context_size);
+        let ctxt = &self.context[start_pos..];
+        let input = Tensor::new(ctxt, &self.device)?.unsqueeze(0)?;
+        let logits = self.model.forward(&input, start_pos)?;
+        let logits = logits.squeeze(0)?.squeeze(0)?;
+        let (next_token, logits) = match self.native_top_k() {
+            Some(k) => (self.sample_native(&logits, k)?, None),
+            None => {
+                let (next_token, logits) = self.sample(logits.to_dtype(DType::F32)?)?;
+                (next_token, Some(logits))
+            }
+        };
         self.tokens.push(next_token);
+        self.context.push(next_token);
         self.generated_tokens += 1;
-        if self.eos_token_id.contains(&next_token) {
-            Err(crate::Error::Eos {
-                eos_token_id: next_token,
-                generated: self.generated_tokens,
+
+        let reason = if self.eos_token_id.contains(&next_token) {
+            Some(StopReason::Eos {
+                token_id: next_token,
             })
         } else {
-            Ok(next_token)
+            // native sampling 時沒有 stopping criteria
+            let mut reason = None;
+            if let Some(logits) = &logits {
+                let ctx = StoppingContext {
+                    tokens: &self.tokens,
+                    prompt_tokens: self.prompt_tokens,
+                    logits,
+                };
+                for (index, criteria) in self.stopping_criteria.iter_mut().enumerate() {
+                    if criteria.should_stop(&ctx)? {
+                        reason = Some(StopReason::Criteria { index });
+                        break;
+                    }
+                }
+            }
+            reason
+        };
+
+        match reason {
+            Some(reason) => {
+                self.finished = Some(reason);
+                Ok(Step::Finished(reason))
+            }
+            None => Ok(Step::Token(next_token)),
         }
     }
 
-    // pub fn run<F>(&mut self, ids: Vec<u32>, max_new_tokens: usize, mut cb: F) -> Result<usize>
-    // where
-    //     F: FnMut(u32),
-    // {
-    //     match self.apply(ids, max_new_tokens) {
-    //         Ok(next_token) => cb(next_token),
-    //         Err(crate::Error::Eos {
-    //             eos_token_id,
-    //             generated,
-    //         }) => {
-    //             cb(eos_token_id);
-    //             return Ok(generated);
-    //         }
-    //         Err(crate::Error::MaxNewTokenExceeded { max_new_tokens }) => {
-    //             return Ok(max_new_tokens);
-    //         }
-    //         Err(e) => return Err(e),
-    //     }
-
-    //     loop {
-    //         match self.next_token(1) {
-    //             Ok(next_token) => cb(next_token),
-    //             Err(crate::Error::Eos {
-    //                 eos_token_id,
-    //                 generated,
-    //             }) => {
-    //                 cb(eos_token_id);
-    //                 return Ok(generated);
-    //             }
-    //             Err(crate::Error::MaxNewTokenExceeded { max_new_tokens }) => {
-    //                 return Ok(max_new_tokens);
-    //             }
-    //             Err(e) => return Err(e),
-    //         }
-    //     }
-    // }
-
     // pub fn run<F>(&mut self, ids: Vec<u32>, max_new_tokens: usize, mut cb: F) -> Result<usize>
     // where
     //     F: FnMut(u32),
@@ -251,3 +1194,134 @@ impl<M: Model> TextGeneration<M> {
     //     Ok(generated_tokens)
     // }
 }
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// 執行 f，panic 時轉為 Error::Panicked
+fn run_job<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let msg = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(crate::Error::Panicked(msg))
+    })
+}
+
+/// 在獨立的 thread 執行生成，eg: 將 TextGeneration move 進 closure，結束後連同結果回傳。
+/// async 程式可以在 executor 的 blocking thread 中呼叫 join
+pub fn spawn_blocking<T, F>(f: F) -> Result<GenerationHandle<T>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::sync_channel(1);
+    std::thread::Builder::new()
+        .name("mospeada-generation".to_string())
+        .spawn(move || {
+            let _ = sender.send(run_job(f));
+        })?;
+    Ok(GenerationHandle { receiver })
+}
+
+/// spawn_blocking 與 GenerationPool::spawn 的結果
+#[derive(Debug)]
+pub struct GenerationHandle<T> {
+    receiver: mpsc::Receiver<Result<T>>,
+}
+
+impl<T> GenerationHandle<T> {
+    /// 等待生成結束，panic 時回傳 Error::Panicked
+    pub fn join(self) -> Result<T> {
+        match self.receiver.recv() {
+            Ok(result) => result,
+            Err(_) => Err(crate::Error::Panicked(
+                "generation task was dropped".to_string(),
+            )),
+        }
+    }
+
+    /// 最多等待 timeout，超時回傳 Error::Timeout。
+    /// thread 無法中斷，超時後工作會繼續執行到結束，需要時以 MaxTime 限制生成時間
+    pub fn join_timeout(self, timeout: Duration) -> Result<T> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(crate::Error::Timeout(timeout)),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(crate::Error::Panicked(
+                "generation task was dropped".to_string(),
+            )),
+        }
+    }
+
+    /// 已經結束時回傳結果，不會等待
+    pub fn try_join(&self) -> Option<Result<T>> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// 固定數量 thread 的 pool，限制同時執行的生成數，eg: 一個 GPU 一次只跑一個模型
+pub struct GenerationPool {
+    sender: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl GenerationPool {
+    pub fn new(threads: usize) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut workers = Vec::with_capacity(threads.max(1));
+        for i in 0..threads.max(1) {
+            let receiver = receiver.clone();
+            let worker = std::thread::Builder::new()
+                .name(format!("mospeada-generation-{i}"))
+                .spawn(move || {
+                    loop {
+                        let job = match receiver.lock() {
+                            Ok(receiver) => receiver.recv(),
+                            Err(_) => return,
+                        };
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => return,
+                        }
+                    }
+                })?;
+            workers.push(worker);
+        }
+        Ok(Self {
+            sender: Some(sender),
+            workers,
+        })
+    }
+
+    /// 排入工作，有空閒的 thread 時開始執行
+    pub fn spawn<T, F>(&self, f: F) -> GenerationHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let job: Job = Box::new(move || {
+            let _ = sender.send(run_job(f));
+        });
+        if let Some(jobs) = self.sender.as_ref() {
+            // worker 都已結束時 job 會被丟棄，join 會回傳錯誤
+            let _ = jobs.send(job);
+        }
+        GenerationHandle { receiver }
+    }
+}
+
+impl Drop for GenerationPool {
+    /// 等待已排入的工作執行完畢
+    fn drop(&mut self) {
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::repo::{Repo, found};
+use crate::{Result, bail};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Component, Path, PathBuf};
+
+/// 從 HTTPS 目錄下載模型檔案並快取在本地，檔案位於 `{base_url}/{filename}`。
+///
+/// 適用於內部鏡像站、公開或經過代理的 S3 相容儲存與 GCS；需要驗證時以 `with_header`
+/// 加上 eg: `Authorization: Bearer ...`。不支援 S3 SigV4 簽章，請使用 presigned URL 或代理
+pub struct HttpRepo {
+    model_id: String,
+    base_url: String,
+    cache_dir: PathBuf,
+    headers: Vec<(String, String)>,
+}
+
+impl HttpRepo {
+    /// 預設快取在 `~/.cache/mospeada/http/{model_id}`
+    pub fn new(model_id: &str, base_url: &str) -> Self {
+        let cache_dir = std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+            .join(".cache")
+            .join("mospeada")
+            .join("http")
+            .join(model_id);
+        Self {
+            model_id: model_id.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            cache_dir,
+            headers: vec![],
+        }
+    }
+
+    /// S3 相容的 path-style URL: `{endpoint}/{bucket}/{prefix}`，eg: MinIO、R2
+    pub fn s3(model_id: &str, endpoint: &str, bucket: &str, prefix: &str) -> Self {
+        let url = format!(
+            "{}/{bucket}/{}",
+            endpoint.trim_end_matches('/'),
+            prefix.trim_matches('/')
+        );
+        Self::new(model_id, &url)
+    }
+
+    /// GCS 的公開 URL: `https://storage.googleapis.com/{bucket}/{prefix}`
+    pub fn gcs(model_id: &str, bucket: &str, prefix: &str) -> Self {
+        Self::s3(model_id, "https://storage.googleapis.com", bucket, prefix)
+    }
+
+    pub fn with_cache_dir<P: Into<PathBuf>>(mut self, cache_dir: P) -> Self {
+        self.cache_dir = cache_dir.into();
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn cache_dir(&self) -> &PathBuf {
+        &self.cache_dir
+    }
+
+    fn download(&self, filename: &str, file: &PathBuf) -> Result<()> {
+        let url = format!("{}/{filename}", self.base_url);
+        let mut request = ureq::get(&url);
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => {
+                return Err(crate::Error::FileNotFound {
+                    model_id: self.model_id.clone(),
+                    file: filename.to_string(),
+                });
+            }
+            Err(ureq::Error::Status(status, _)) => bail!("{url}: HTTP {status}"),
+            Err(err) => bail!("{url}: {err}"),
+        };
+
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // 先寫到暫存檔，下載完成才改名，避免中斷後留下不完整的快取；
+        // 附加在完整檔名後，避免 tokenizer.json 與 tokenizer.model 共用暫存檔
+        let mut part = file.clone().into_os_string();
+        part.push(".part");
+        let part = PathBuf::from(part);
+        let mut writer = BufWriter::new(File::create(&part)?);
+        std::io::copy(&mut response.into_reader(), &mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        std::fs::rename(part, file)?;
+        Ok(())
+    }
+}
+
+impl Repo for HttpRepo {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn get(&self, filename: &str) -> Result<PathBuf> {
+        // 檔名可能來自遠端的 index 檔，不允許寫到快取目錄之外
+        let path = Path::new(filename);
+        if filename.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            bail!("{}: invalid filename {filename:?}", self.model_id);
+        }
+        let file = self.cache_dir.join(path);
+        if !file.exists() {
+            self.download(filename, &file)?;
+        }
+        Ok(file)
+    }
+
+    fn tokenizer_config_file(&self) -> Result<PathBuf> {
+        self.get("tokenizer_config.json")
+    }
+
+    fn tokenizer_file(&self) -> Result<PathBuf> {
+        self.get("tokenizer.json")
+    }
+
+    fn config_file(&self) -> Result<PathBuf> {
+        self.get("config.json")
+    }
+
+    fn safetensors_files(&self) -> Result<Vec<PathBuf>> {
+        if let Some(single_file) = found(self.get("model.safetensors"))? {
+            return Ok(vec![single_file]);
+        }
+        let index_file = self.get("model.safetensors.index.json")?;
+        let mut files: Vec<_> = crate::repo::read_safetensors_index_file(index_file)?
+            .into_iter()
+            .collect();
+        files.sort();
+        files.iter().map(|f| self.get(f)).collect()
+    }
+
+    fn pytorch_model_file(&self) -> Result<PathBuf> {
+        self.get("pytorch_model.bin")
+    }
+
+    fn generate_config_file(&self) -> Result<PathBuf> {
+        self.get("generation_config.json")
+    }
+}
@@ -0,0 +1,121 @@
+use crate::Result;
+use crate::repo::Repo;
+use candle_core::quantized::gguf_file;
+use candle_core::{DType, Device, DeviceLocation};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 已載入模型的資訊，可以序列化成 JSON 提供給監控或 `/v1/models` 等 API
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ModelInfo {
+    pub model_id: String,
+    pub revision: Option<String>,
+    /// eg: bf16、f32
+    pub dtype: String,
+    /// eg: cpu、cuda:0、metal:0
+    pub device: String,
+    pub context_length: Option<usize>,
+    /// eg: gptq、awq、q4k
+    pub quantization: Option<String>,
+    /// 載入的 adapter，eg: LoRA
+    pub adapters: Vec<String>,
+}
+
+impl ModelInfo {
+    pub fn new(model_id: &str, dtype: DType, device: &Device) -> Self {
+        Self {
+            model_id: model_id.to_string(),
+            revision: None,
+            dtype: dtype.as_str().to_string(),
+            device: device_name(device),
+            context_length: None,
+            quantization: None,
+            adapters: vec![],
+        }
+    }
+
+    /// 從 config.json 讀取 context 長度與 `quantization_config.quant_method`
+    pub fn from_repo<R: Repo>(repo: &R, dtype: DType, device: &Device) -> Result<Self> {
+        let config = repo.model_config()?;
+        let mut info = Self::new(repo.model_id(), dtype, device);
+        info.context_length = config.max_position_embeddings();
+        info.quantization = config
+            .get("quantization_config")
+            .and_then(|q| q.get("quant_method"))
+            .and_then(|m| m.as_str())
+            .map(str::to_string);
+        Ok(info)
+    }
+
+    /// 從 GGUF 的 metadata 讀取 context 長度，quantization 為最多 tensor 使用的格式
+    pub fn from_gguf(model_id: &str, content: &gguf_file::Content, device: &Device) -> Self {
+        let mut info = Self::new(model_id, DType::F32, device);
+        let architecture = content
+            .metadata
+            .get("general.architecture")
+            .and_then(|v| v.to_string().ok());
+        info.context_length = architecture
+            .and_then(|arch| content.metadata.get(&format!("{arch}.context_length")))
+            .and_then(|v| v.to_u32().ok())
+            .map(|v| v as usize);
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for tensor in content.tensor_infos.values() {
+            *counts
+                .entry(format!("{:?}", tensor.ggml_dtype).to_lowercase())
+                .or_default() += 1;
+        }
+        info.quantization = counts
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)))
+            .map(|(dtype, _)| dtype);
+        info
+    }
+
+    pub fn with_revision(mut self, revision: &str) -> Self {
+        self.revision = Some(revision.to_string());
+        self
+    }
+
+    pub fn with_context_length(mut self, context_length: usize) -> Self {
+        self.context_length = Some(context_length);
+        self
+    }
+
+    pub fn with_adapter(mut self, adapter: &str) -> Self {
+        self.adapters.push(adapter.to_string());
+        self
+    }
+}
+
+/// 執行環境與所有已載入模型的資訊，可以作為 health check 的回應
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EngineInfo {
+    /// mospeada 的版本
+    pub version: String,
+    /// 有載入的模型時為 true
+    pub ready: bool,
+    pub models: Vec<ModelInfo>,
+}
+
+impl EngineInfo {
+    pub fn new(models: Vec<ModelInfo>) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            ready: !models.is_empty(),
+            models,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+pub fn device_name(device: &Device) -> String {
+    match device.location() {
+        DeviceLocation::Cpu => "cpu".to_string(),
+        DeviceLocation::Cuda { gpu_id } => format!("cuda:{gpu_id}"),
+        DeviceLocation::Metal { gpu_id } => format!("metal:{gpu_id}"),
+    }
+}
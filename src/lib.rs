@@ -1,13 +1,54 @@
 #[cfg(feature = "http")]
 pub mod hf_hub;
 
+#[cfg(feature = "http")]
+pub mod http_repo;
+
+#[cfg(feature = "http")]
+pub mod modelscope;
+
+#[cfg(feature = "chat-template")]
+pub mod chat;
+
 #[cfg(feature = "chat-template")]
 pub mod chat_template;
 
+#[cfg(feature = "vectorstore")]
+pub mod vectorstore;
+
+#[cfg(all(feature = "vectorstore", feature = "chat-template"))]
+pub mod rag;
+
+pub mod audit;
+pub mod bench;
+pub mod chunking;
+pub mod classification;
+pub mod config;
+pub mod convert;
+pub mod debug;
+pub mod determinism;
+pub mod embedding;
 pub mod error;
+pub mod few_shot;
 pub mod generation;
+pub mod info;
+pub mod logits;
+pub mod map_reduce;
+pub mod moderation;
+pub mod ollama;
+pub mod postprocess;
+pub mod reasoning;
+pub mod registry;
 pub mod repo;
+pub mod response_cache;
+pub mod sampler;
+pub mod sentencepiece;
+pub mod sink;
+pub mod stopping;
+pub mod structured;
+pub mod testing;
 pub mod tokenizers;
+pub mod trace;
 pub mod utils;
 
 pub use error::{Error, Result};
@@ -0,0 +1,634 @@
+use crate::{Result, bail};
+use candle_core::{DType, Tensor, WithDType};
+use std::collections::HashMap;
+
+/// transform 可以看到的生成狀態
+#[derive(Debug, Clone, Copy)]
+pub struct LogitsContext<'a> {
+    /// prompt + 已生成的 token
+    pub tokens: &'a [u32],
+    pub prompt_tokens: usize,
+    pub max_new_tokens: usize,
+}
+
+impl LogitsContext<'_> {
+    /// 已生成的 token 數
+    pub fn generated(&self) -> usize {
+        self.tokens.len().saturating_sub(self.prompt_tokens)
+    }
+
+    pub fn generated_tokens(&self) -> &[u32] {
+        &self.tokens[self.prompt_tokens.min(self.tokens.len())..]
+    }
+}
+
+/// 在 sampling 前調整 logits，依加入 TextGeneration 的順序執行。
+/// logits 為 F32、shape (vocab_size,)
+pub trait LogitsTransform: Send {
+    fn apply(&mut self, logits: &Tensor, ctx: &LogitsContext) -> Result<Tensor>;
+
+    /// 有自己亂數的 transform 回傳目前的亂數狀態，存入 Checkpoint，eg: Xtc
+    fn rng_state(&self) -> Option<u64> {
+        None
+    }
+
+    /// 從 Checkpoint 恢復時設定亂數狀態
+    fn set_rng_state(&mut self, _state: u64) {}
+
+    /// 以 seed 重設亂數，`TextGeneration::reseed` 時呼叫
+    fn reseed(&mut self, _seed: u64) {}
+}
+
+impl<F> LogitsTransform for F
+where
+    F: FnMut(&Tensor, &LogitsContext) -> Result<Tensor> + Send,
+{
+    fn apply(&mut self, logits: &Tensor, ctx: &LogitsContext) -> Result<Tensor> {
+        self(logits, ctx)
+    }
+}
+
+// 在 host 端修改 logits 後放回原本的 device
+pub(crate) fn map_logits<F>(logits: &Tensor, f: F) -> Result<Tensor>
+where
+    F: FnOnce(&mut [f32]),
+{
+    let mut values = logits.to_vec1::<f32>()?;
+    f(&mut values);
+    Ok(Tensor::from_vec(values, logits.shape(), logits.device())?)
+}
+
+/// OpenAI 的 logit_bias: token id -> 加到 logits 的值，-inf 可以禁止該 token
+#[derive(Debug, Clone, Default)]
+pub struct LogitBias(pub HashMap<u32, f32>);
+
+impl LogitsTransform for LogitBias {
+    fn apply(&mut self, logits: &Tensor, _ctx: &LogitsContext) -> Result<Tensor> {
+        if self.0.is_empty() {
+            return Ok(logits.clone());
+        }
+        map_logits(logits, |values| {
+            for (&id, &bias) in &self.0 {
+                if let Some(v) = values.get_mut(id as usize) {
+                    *v += bias;
+                }
+            }
+        })
+    }
+}
+
+/// 只在第一個生成的 token 生效，限制只能選 allowed 中的 token，見 token healing
+#[derive(Debug, Clone)]
+pub struct TokenHealingConstraint {
+    allowed: Vec<u32>,
+}
+
+impl TokenHealingConstraint {
+    pub fn new(allowed: Vec<u32>) -> Self {
+        Self { allowed }
+    }
+}
+
+impl LogitsTransform for TokenHealingConstraint {
+    fn apply(&mut self, logits: &Tensor, ctx: &LogitsContext) -> Result<Tensor> {
+        if ctx.generated() > 0 || self.allowed.is_empty() {
+            return Ok(logits.clone());
+        }
+        map_logits(logits, |values| {
+            let mut masked = vec![f32::NEG_INFINITY; values.len()];
+            for &id in &self.allowed {
+                if let Some(v) = values.get(id as usize) {
+                    masked[id as usize] = *v;
+                }
+            }
+            values.copy_from_slice(&masked);
+        })
+    }
+}
+
+/// generation_config.json 的 suppress_tokens: 每一步都禁止這些 token
+#[derive(Debug, Clone)]
+pub struct SuppressTokens(pub Vec<u32>);
+
+impl LogitsTransform for SuppressTokens {
+    fn apply(&mut self, logits: &Tensor, _ctx: &LogitsContext) -> Result<Tensor> {
+        suppress(logits, &self.0)
+    }
+}
+
+/// generation_config.json 的 begin_suppress_tokens: 只禁止第一個生成的 token，
+/// eg: Whisper 不能一開始就輸出空白或 eos
+#[derive(Debug, Clone)]
+pub struct BeginSuppressTokens(pub Vec<u32>);
+
+impl LogitsTransform for BeginSuppressTokens {
+    fn apply(&mut self, logits: &Tensor, ctx: &LogitsContext) -> Result<Tensor> {
+        if ctx.generated() > 0 {
+            return Ok(logits.clone());
+        }
+        suppress(logits, &self.0)
+    }
+}
+
+/// generation_config.json 的 forced_decoder_ids: 序列長度 (prompt + 已生成) 為 index 時
+/// 強制生成指定的 token，同 transformers 的 ForceTokensLogitsProcessor。
+/// eg: Whisper 的語言與任務 token
+#[derive(Debug, Clone, Default)]
+pub struct ForceTokens(pub HashMap<usize, u32>);
+
+impl LogitsTransform for ForceTokens {
+    fn apply(&mut self, logits: &Tensor, ctx: &LogitsContext) -> Result<Tensor> {
+        match self.0.get(&ctx.tokens.len()) {
+            Some(&id) => force(logits, &[id]),
+            None => Ok(logits.clone()),
+        }
+    }
+}
+
+/// 第一個生成的 token 強制為 forced_bos_token_id，eg: 多語翻譯模型的目標語言
+#[derive(Debug, Clone)]
+pub struct ForcedBos(pub u32);
+
+impl LogitsTransform for ForcedBos {
+    fn apply(&mut self, logits: &Tensor, ctx: &LogitsContext) -> Result<Tensor> {
+        if ctx.generated() > 0 {
+            return Ok(logits.clone());
+        }
+        force(logits, &[self.0])
+    }
+}
+
+/// 達到 max_new_tokens 前的最後一個 token 強制為 forced_eos_token_id 之一
+#[derive(Debug, Clone)]
+pub struct ForcedEos(pub Vec<u32>);
+
+impl LogitsTransform for ForcedEos {
+    fn apply(&mut self, logits: &Tensor, ctx: &LogitsContext) -> Result<Tensor> {
+        if ctx.generated() + 1 != ctx.max_new_tokens {
+            return Ok(logits.clone());
+        }
+        force(logits, &self.0)
+    }
+}
+
+/// 生成 min_new_tokens 個 token 前禁止 eos，同 transformers 的 MinNewTokensLengthLogitsProcessor
+#[derive(Debug, Clone)]
+pub struct MinNewTokens {
+    pub min_new_tokens: usize,
+    pub eos_token_id: Vec<u32>,
+}
+
+impl LogitsTransform for MinNewTokens {
+    fn apply(&mut self, logits: &Tensor, ctx: &LogitsContext) -> Result<Tensor> {
+        if ctx.generated() >= self.min_new_tokens {
+            return Ok(logits.clone());
+        }
+        suppress(logits, &self.eos_token_id)
+    }
+}
+
+/// 生成超過 start_index 個 token 後，以指數成長提高 eos 的 logits，讓輸出自然收尾。
+/// 同 transformers 的 ExponentialDecayLengthPenalty
+#[derive(Debug, Clone)]
+pub struct ExponentialDecayLengthPenalty {
+    pub start_index: usize,
+    pub decay_factor: f32,
+    pub eos_token_id: Vec<u32>,
+}
+
+impl LogitsTransform for ExponentialDecayLengthPenalty {
+    fn apply(&mut self, logits: &Tensor, ctx: &LogitsContext) -> Result<Tensor> {
+        let generated = ctx.generated();
+        if generated <= self.start_index || self.eos_token_id.is_empty() {
+            return Ok(logits.clone());
+        }
+        let scale = self
+            .decay_factor
+            .powi((generated - self.start_index) as i32)
+            - 1.;
+        map_logits(logits, |values| {
+            for &id in &self.eos_token_id {
+                if let Some(v) = values.get_mut(id as usize) {
+                    *v += v.abs() * scale;
+                }
+            }
+        })
+    }
+}
+
+/// OpenAI 的 frequency_penalty 與 presence_penalty，只計算生成的 token，不含 prompt。
+/// logits 減去 出現次數 * frequency + (有出現 ? presence : 0)，last_n 限制只看最後 n 個生成的 token。
+/// 與 repetition_penalty 的乘除不同，為固定的減法
+#[derive(Debug, Clone, Default)]
+pub struct FrequencyPresencePenalty {
+    pub frequency: f32,
+    pub presence: f32,
+    pub last_n: Option<usize>,
+}
+
+impl LogitsTransform for FrequencyPresencePenalty {
+    fn apply(&mut self, logits: &Tensor, ctx: &LogitsContext) -> Result<Tensor> {
+        let generated = &ctx.tokens[ctx.tokens.len() - ctx.generated()..];
+        let generated = match self.last_n {
+            Some(n) => &generated[generated.len().saturating_sub(n)..],
+            None => generated,
+        };
+        if generated.is_empty() || (self.frequency == 0. && self.presence == 0.) {
+            return Ok(logits.clone());
+        }
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for &id in generated {
+            *counts.entry(id).or_default() += 1;
+        }
+        map_logits(logits, |values| {
+            for (&id, &count) in &counts {
+                if let Some(v) = values.get_mut(id as usize) {
+                    *v -= count as f32 * self.frequency + self.presence;
+                }
+            }
+        })
+    }
+}
+
+/// DRY (Don't Repeat Yourself)，同 text-generation-webui 與 llama.cpp 的 DRY sampler。
+/// 若接下來的 token 會延續 context 中已出現過的序列，依重複的長度以指數降低該 token 的 logits：
+/// multiplier * base^(長度 - allowed_length)。sequence_breakers (eg: 換行、冒號) 會中斷比對
+#[derive(Debug, Clone)]
+pub struct Dry {
+    pub multiplier: f32,
+    pub base: f32,
+    pub allowed_length: usize,
+    pub sequence_breakers: Vec<u32>,
+    /// 只比對最後 n 個 token，None 為整個 context
+    pub last_n: Option<usize>,
+}
+
+impl Dry {
+    /// base 1.75、allowed_length 2，同 text-generation-webui 的預設值
+    pub fn new(multiplier: f32) -> Self {
+        Self {
+            multiplier,
+            base: 1.75,
+            allowed_length: 2,
+            sequence_breakers: vec![],
+            last_n: None,
+        }
+    }
+
+    pub fn with_sequence_breakers(mut self, sequence_breakers: Vec<u32>) -> Self {
+        self.sequence_breakers = sequence_breakers;
+        self
+    }
+
+    pub fn with_last_n(mut self, last_n: usize) -> Self {
+        self.last_n = Some(last_n);
+        self
+    }
+
+    /// 每個會延續重複序列的 token 與重複的長度
+    pub fn match_lengths(&self, tokens: &[u32]) -> HashMap<u32, usize> {
+        let tokens = match self.last_n {
+            Some(n) => &tokens[tokens.len().saturating_sub(n)..],
+            None => tokens,
+        };
+        let mut lengths = HashMap::new();
+        let Some((&last, _)) = tokens.split_last() else {
+            return lengths;
+        };
+        let is_breaker = |id: &u32| self.sequence_breakers.contains(id);
+        if is_breaker(&last) {
+            return lengths;
+        }
+        let end = tokens.len() - 1;
+        for i in 0..end {
+            let next = tokens[i + 1];
+            if tokens[i] != last || is_breaker(&next) {
+                continue;
+            }
+            let mut length = 1;
+            while length <= i
+                && tokens[i - length] == tokens[end - length]
+                && !is_breaker(&tokens[i - length])
+            {
+                length += 1;
+            }
+            let entry = lengths.entry(next).or_insert(0);
+            *entry = (*entry).max(length);
+        }
+        lengths
+    }
+}
+
+impl LogitsTransform for Dry {
+    fn apply(&mut self, logits: &Tensor, ctx: &LogitsContext) -> Result<Tensor> {
+        if self.multiplier == 0. {
+            return Ok(logits.clone());
+        }
+        let penalties: Vec<(u32, f32)> = self
+            .match_lengths(ctx.tokens)
+            .into_iter()
+            .filter(|(_, length)| *length >= self.allowed_length)
+            .map(|(id, length)| {
+                let exponent = (length - self.allowed_length) as i32;
+                (id, self.multiplier * self.base.powi(exponent))
+            })
+            .collect();
+        if penalties.is_empty() {
+            return Ok(logits.clone());
+        }
+        map_logits(logits, |values| {
+            for &(id, penalty) in &penalties {
+                if let Some(v) = values.get_mut(id as usize) {
+                    *v -= penalty;
+                }
+            }
+        })
+    }
+}
+
+/// XTC (Exclude Top Choices)，同 llama.cpp。以 probability 的機率觸發，
+/// 觸發時將機率不低於 threshold 的 token 中，除了機率最低的一個之外全部移除，讓輸出較有創意
+#[derive(Debug, Clone)]
+pub struct Xtc {
+    pub threshold: f32,
+    pub probability: f32,
+    state: u64,
+}
+
+impl Xtc {
+    pub fn new(threshold: f32, probability: f32, seed: u64) -> Self {
+        Self {
+            threshold,
+            probability,
+            state: seed,
+        }
+    }
+
+    // splitmix64，回傳 [0, 1)
+    fn random(&mut self) -> f32 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+impl LogitsTransform for Xtc {
+    fn apply(&mut self, logits: &Tensor, _ctx: &LogitsContext) -> Result<Tensor> {
+        if self.probability <= 0. || self.threshold > 0.5 || self.random() >= self.probability {
+            return Ok(logits.clone());
+        }
+        let probs = candle_nn::ops::softmax_last_dim(logits)?.to_vec1::<f32>()?;
+        let mut top: Vec<usize> = (0..probs.len())
+            .filter(|&i| probs[i] >= self.threshold)
+            .collect();
+        if top.len() < 2 {
+            return Ok(logits.clone());
+        }
+        top.sort_by(|&a, &b| probs[b].total_cmp(&probs[a]));
+        top.pop();
+        map_logits(logits, |values| {
+            for i in top {
+                values[i] = f32::NEG_INFINITY;
+            }
+        })
+    }
+
+    fn rng_state(&self) -> Option<u64> {
+        Some(self.state)
+    }
+
+    fn set_rng_state(&mut self, state: u64) {
+        self.state = state;
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+}
+
+/// top-n-sigma：只保留 logits 不低於 max - n * 標準差 的 token，不受 temperature 影響
+#[derive(Debug, Clone, Copy)]
+pub struct TopNSigma(pub f32);
+
+impl LogitsTransform for TopNSigma {
+    fn apply(&mut self, logits: &Tensor, _ctx: &LogitsContext) -> Result<Tensor> {
+        map_logits(logits, |values| {
+            let finite: Vec<f32> = values.iter().copied().filter(|v| v.is_finite()).collect();
+            if finite.is_empty() {
+                return;
+            }
+            let n = finite.len() as f32;
+            let mean = finite.iter().sum::<f32>() / n;
+            let std = (finite.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / n).sqrt();
+            let max = finite.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            let min = max - self.0 * std;
+            for v in values.iter_mut() {
+                if *v < min {
+                    *v = f32::NEG_INFINITY;
+                }
+            }
+        })
+    }
+}
+
+/// 只保留 logits 最大的 k 個 token
+#[derive(Debug, Clone, Copy)]
+pub struct TopK(pub usize);
+
+impl LogitsTransform for TopK {
+    fn apply(&mut self, logits: &Tensor, _ctx: &LogitsContext) -> Result<Tensor> {
+        map_logits(logits, |values| {
+            if self.0 == 0 || self.0 >= values.len() {
+                return;
+            }
+            let mut sorted = values.to_vec();
+            sorted.sort_by(|a, b| b.total_cmp(a));
+            let min = sorted[self.0 - 1];
+            for v in values.iter_mut() {
+                if *v < min {
+                    *v = f32::NEG_INFINITY;
+                }
+            }
+        })
+    }
+}
+
+/// nucleus sampling，保留累積機率達到 p 的最少 token
+#[derive(Debug, Clone, Copy)]
+pub struct TopP(pub f32);
+
+impl LogitsTransform for TopP {
+    fn apply(&mut self, logits: &Tensor, _ctx: &LogitsContext) -> Result<Tensor> {
+        if self.0 >= 1. {
+            return Ok(logits.clone());
+        }
+        let probs = candle_nn::ops::softmax_last_dim(logits)?.to_vec1::<f32>()?;
+        let mut order: Vec<usize> = (0..probs.len()).collect();
+        order.sort_by(|&a, &b| probs[b].total_cmp(&probs[a]));
+        let mut cumulative = 0.;
+        let mut keep = order.len();
+        for (i, &id) in order.iter().enumerate() {
+            cumulative += probs[id];
+            if cumulative >= self.0 {
+                keep = i + 1;
+                break;
+            }
+        }
+        map_logits(logits, |values| {
+            for &id in &order[keep..] {
+                values[id] = f32::NEG_INFINITY;
+            }
+        })
+    }
+}
+
+/// 移除機率低於 p * 最高機率的 token
+#[derive(Debug, Clone, Copy)]
+pub struct MinP(pub f32);
+
+impl LogitsTransform for MinP {
+    fn apply(&mut self, logits: &Tensor, _ctx: &LogitsContext) -> Result<Tensor> {
+        let probs = candle_nn::ops::softmax_last_dim(logits)?.to_vec1::<f32>()?;
+        let min = probs.iter().copied().fold(0f32, f32::max) * self.0;
+        map_logits(logits, |values| {
+            for (v, p) in values.iter_mut().zip(probs) {
+                if p < min {
+                    *v = f32::NEG_INFINITY;
+                }
+            }
+        })
+    }
+}
+
+/// logits 除以 temperature，0 時只保留最大的 token
+#[derive(Debug, Clone, Copy)]
+pub struct Temperature(pub f32);
+
+impl LogitsTransform for Temperature {
+    fn apply(&mut self, logits: &Tensor, ctx: &LogitsContext) -> Result<Tensor> {
+        if self.0 < 1e-7 {
+            return TopK(1).apply(logits, ctx);
+        }
+        Ok((logits / self.0 as f64)?)
+    }
+}
+
+// 只保留 ids，其餘設為 -inf
+pub(crate) fn force(logits: &Tensor, ids: &[u32]) -> Result<Tensor> {
+    if ids.is_empty() {
+        return Ok(logits.clone());
+    }
+    map_logits(logits, |values| {
+        let mut forced = vec![f32::NEG_INFINITY; values.len()];
+        for &id in ids {
+            if let Some(v) = forced.get_mut(id as usize) {
+                *v = 0.;
+            }
+        }
+        values.copy_from_slice(&forced);
+    })
+}
+
+fn suppress(logits: &Tensor, ids: &[u32]) -> Result<Tensor> {
+    if ids.is_empty() {
+        return Ok(logits.clone());
+    }
+    map_logits(logits, |values| {
+        for &id in ids {
+            if let Some(v) = values.get_mut(id as usize) {
+                *v = f32::NEG_INFINITY;
+            }
+        }
+    })
+}
+
+/// logits 經過 log_softmax 後機率最高的 k 個 token，由高到低排序
+pub fn top_logprobs(logits: &Tensor, k: usize) -> Result<Vec<(u32, f32)>> {
+    let logprobs = candle_nn::ops::log_softmax(logits, candle_core::D::Minus1)?.to_vec1::<f32>()?;
+    let mut indexed: Vec<(u32, f32)> = logprobs
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| (i as u32, v))
+        .collect();
+    let k = k.min(indexed.len());
+    if k == 0 {
+        return Ok(vec![]);
+    }
+    indexed.select_nth_unstable_by(k - 1, |a, b| b.1.total_cmp(&a.1));
+    indexed.truncate(k);
+    indexed.sort_by(|a, b| b.1.total_cmp(&a.1));
+    Ok(indexed)
+}
+
+/// 以 model 的 dtype 取出的 top-k 候選，由高到低排序
+#[derive(Debug, Clone)]
+pub(crate) struct Candidates {
+    pub ids: Vec<u32>,
+    pub logits: Vec<f32>,
+    /// 完整 vocab 的 log-sum-exp，用來計算 logprob
+    pub logsumexp: f64,
+}
+
+/// 不把 logits 轉為 F32，以原本的 dtype (F16、BF16) 複製到 host 後取 top k，
+/// 只有候選會轉為 f32
+pub(crate) fn native_top_k(logits: &Tensor, k: usize) -> Result<Candidates> {
+    match logits.dtype() {
+        DType::F16 => Ok(top_k_of(&logits.to_vec1::<half::f16>()?, k)),
+        DType::BF16 => Ok(top_k_of(&logits.to_vec1::<half::bf16>()?, k)),
+        DType::F32 => Ok(top_k_of(&logits.to_vec1::<f32>()?, k)),
+        DType::F64 => Ok(top_k_of(&logits.to_vec1::<f64>()?, k)),
+        dtype => bail!("unsupported logits dtype {dtype:?}"),
+    }
+}
+
+/// 在 logits 所在的 device 上取 top k，只有候選與 log-sum-exp 會複製回 host。
+/// k 為 1 時使用 argmax，其餘使用 arg sort，backend 不支援時 (eg: CUDA 的 vocab 太大) 回傳錯誤
+pub(crate) fn device_top_k(logits: &Tensor, k: usize) -> Result<Candidates> {
+    let logsumexp = logits
+        .to_dtype(DType::F32)?
+        .log_sum_exp(0)?
+        .to_scalar::<f32>()? as f64;
+    let (ids, values) = if k <= 1 {
+        let id = logits.argmax(0)?;
+        let value = logits.gather(&id.unsqueeze(0)?, 0)?;
+        (id.unsqueeze(0)?, value)
+    } else {
+        let (values, ids) = logits.sort_last_dim(false)?;
+        let k = k.min(ids.dim(0)?);
+        (ids.narrow(0, 0, k)?, values.narrow(0, 0, k)?)
+    };
+    Ok(Candidates {
+        ids: ids.to_vec1::<u32>()?,
+        logits: values.to_dtype(DType::F32)?.to_vec1::<f32>()?,
+        logsumexp,
+    })
+}
+
+fn top_k_of<T: WithDType>(values: &[T], k: usize) -> Candidates {
+    let k = k.clamp(1, values.len().max(1));
+    let mut ids: Vec<u32> = (0..values.len() as u32).collect();
+    let cmp = |a: &u32, b: &u32| {
+        values[*b as usize]
+            .to_f64()
+            .total_cmp(&values[*a as usize].to_f64())
+    };
+    if k < ids.len() {
+        ids.select_nth_unstable_by(k - 1, cmp);
+        ids.truncate(k);
+    }
+    ids.sort_by(cmp);
+
+    let max = ids.first().map_or(0., |&i| values[i as usize].to_f64());
+    let sum: f64 = values.iter().map(|v| (v.to_f64() - max).exp()).sum();
+    Candidates {
+        logits: ids
+            .iter()
+            .map(|&i| values[i as usize].to_f64() as f32)
+            .collect(),
+        ids,
+        logsumexp: max + sum.ln(),
+    }
+}
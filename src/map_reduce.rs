@@ -0,0 +1,100 @@
+use crate::chunking::{Chunk, split_by_sentences, split_by_tokens};
+use crate::tokenizers::Tokenizer;
+use crate::{Result, bail};
+
+/// 切割文件的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkBy {
+    /// 依 token 數切割，相鄰兩段重疊 overlap 個 token
+    Tokens { overlap: usize },
+    /// 以句子為單位組成片段
+    Sentences,
+}
+
+/// 超過 context 長度的文件的 map-reduce 生成:
+/// 切割文件 -> 每段以 map prompt 生成 -> 合併結果以 reduce prompt 生成最後的結果。
+///
+/// prompt 中的 `{text}` 會被替換為片段或合併後的結果
+#[derive(Debug, Clone)]
+pub struct MapReduce {
+    map_prompt: String,
+    reduce_prompt: String,
+    max_tokens: usize,
+    chunk_by: ChunkBy,
+    separator: String,
+}
+
+/// map-reduce 的中間與最後結果
+#[derive(Debug, Clone)]
+pub struct MapReduceOutput {
+    pub chunks: Vec<Chunk>,
+    /// 每個片段的生成結果
+    pub partials: Vec<String>,
+    pub result: String,
+}
+
+impl MapReduce {
+    /// 預設每段最多 1024 個 token，以句子切割
+    pub fn new(map_prompt: &str, reduce_prompt: &str) -> Self {
+        Self {
+            map_prompt: map_prompt.to_string(),
+            reduce_prompt: reduce_prompt.to_string(),
+            max_tokens: 1024,
+            chunk_by: ChunkBy::Sentences,
+            separator: "\n\n".to_string(),
+        }
+    }
+
+    /// 每段最多 max_tokens 個 token，需要保留 prompt 與生成的空間
+    pub fn with_chunking(mut self, max_tokens: usize, chunk_by: ChunkBy) -> Self {
+        self.max_tokens = max_tokens;
+        self.chunk_by = chunk_by;
+        self
+    }
+
+    /// 合併每段結果時的分隔，預設為空一行
+    pub fn with_separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_string();
+        self
+    }
+
+    pub fn chunks(&self, text: &str, tokenizer: &Tokenizer) -> Result<Vec<Chunk>> {
+        match self.chunk_by {
+            ChunkBy::Tokens { overlap } => {
+                split_by_tokens(text, tokenizer, self.max_tokens, overlap)
+            }
+            ChunkBy::Sentences => split_by_sentences(text, tokenizer, self.max_tokens),
+        }
+    }
+
+    /// generate 以 prompt 生成文字，eg: 套用 chat template、encode、TextGeneration 生成後 decode
+    pub fn run<F>(
+        &self,
+        text: &str,
+        tokenizer: &Tokenizer,
+        mut generate: F,
+    ) -> Result<MapReduceOutput>
+    where
+        F: FnMut(&str) -> Result<String>,
+    {
+        let chunks = self.chunks(text, tokenizer)?;
+        if chunks.is_empty() {
+            bail!("nothing to generate from an empty document");
+        }
+        let partials = chunks
+            .iter()
+            .map(|chunk| generate(&self.map_prompt.replace("{text}", &chunk.text)))
+            .collect::<Result<Vec<_>>>()?;
+        let combined = partials
+            .iter()
+            .map(|p| p.trim())
+            .collect::<Vec<_>>()
+            .join(&self.separator);
+        let result = generate(&self.reduce_prompt.replace("{text}", &combined))?;
+        Ok(MapReduceOutput {
+            chunks,
+            partials,
+            result,
+        })
+    }
+}
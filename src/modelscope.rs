@@ -0,0 +1,38 @@
+use crate::{Result, http_repo::HttpRepo};
+use std::path::PathBuf;
+
+/// ModelScope 的網址，可以用環境變數 MODELSCOPE_ENDPOINT 改成鏡像站
+pub const ENDPOINT: &str = "https://www.modelscope.cn";
+
+/// 同 hf_hub::from_pretrained，從 ModelScope 下載模型。
+/// revision 預設為 master；cache_dir 預設為 `~/.cache/modelscope/mospeada`
+pub fn from_pretrained(
+    model_id: &str,
+    revision: Option<&str>,
+    cache_dir: Option<&str>,
+    token: Option<&str>,
+) -> Result<HttpRepo> {
+    let endpoint = std::env::var("MODELSCOPE_ENDPOINT").unwrap_or_else(|_| ENDPOINT.to_string());
+    let revision = revision.unwrap_or("master");
+    let base_url = format!(
+        "{}/models/{model_id}/resolve/{revision}",
+        endpoint.trim_end_matches('/')
+    );
+
+    let cache_dir = match cache_dir {
+        Some(cache_dir) => PathBuf::from(cache_dir),
+        None => std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+            .join(".cache")
+            .join("modelscope")
+            .join("mospeada"),
+    };
+
+    let repo =
+        HttpRepo::new(model_id, &base_url).with_cache_dir(cache_dir.join(model_id).join(revision));
+    Ok(match token {
+        Some(token) => repo.with_header("Authorization", &format!("Bearer {token}")),
+        None => repo,
+    })
+}
@@ -0,0 +1,248 @@
+use crate::stopping::{StoppingContext, StoppingCriteria};
+use crate::tokenizers::Tokenizer;
+use crate::{Result, bail};
+use regex::Regex;
+
+/// 審查的結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// 以替換後的文字取代原本的 prompt 或輸出片段
+    Redact(String),
+    /// 停止生成，保留已輸出的文字
+    Stop(String),
+    /// 拒絕 prompt，或是停止生成並丟棄輸出
+    Block(String),
+}
+
+impl Verdict {
+    /// Stop 或 Block
+    pub fn is_stop(&self) -> bool {
+        matches!(self, Self::Stop(_) | Self::Block(_))
+    }
+}
+
+/// prompt 與輸出的安全過濾。生成前以 check_prompt 檢查 prompt，串流輸出時以
+/// check_output 檢查每一段新輸出的文字
+pub trait Moderation: Send {
+    fn check_prompt(&mut self, _prompt: &str) -> Result<Verdict> {
+        Ok(Verdict::Allow)
+    }
+
+    /// text 為新輸出的片段，generated 為到目前為止全部的輸出 (包含 text)
+    fn check_output(&mut self, _text: &str, _generated: &str) -> Result<Verdict> {
+        Ok(Verdict::Allow)
+    }
+
+    /// 開始新的生成時呼叫
+    fn reset(&mut self) {}
+}
+
+/// 以關鍵字或 regex 過濾，關鍵字不分 ASCII 大小寫，regex 需要時以 `(?i)` 指定。
+///
+/// 封鎖的關鍵字在 prompt 中時回傳 Block，在輸出中時回傳 Stop；
+/// 遮蔽的關鍵字以 replacement 取代，輸出時只檢查新的片段，跨片段的關鍵字不會被遮蔽
+#[derive(Debug, Clone)]
+pub struct KeywordFilter {
+    blocked: Vec<String>,
+    redacted: Vec<String>,
+    blocked_patterns: Vec<Regex>,
+    redacted_patterns: Vec<Regex>,
+    replacement: String,
+}
+
+impl Default for KeywordFilter {
+    fn default() -> Self {
+        Self {
+            blocked: vec![],
+            redacted: vec![],
+            blocked_patterns: vec![],
+            redacted_patterns: vec![],
+            replacement: "***".to_string(),
+        }
+    }
+}
+
+fn patterns<S: AsRef<str>>(patterns: impl IntoIterator<Item = S>) -> Result<Vec<Regex>> {
+    let mut regexes = vec![];
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        match Regex::new(pattern) {
+            Ok(regex) => regexes.push(regex),
+            Err(err) => bail!("invalid pattern {pattern:?}: {err}"),
+        }
+    }
+    Ok(regexes)
+}
+
+fn keywords<S: AsRef<str>>(words: impl IntoIterator<Item = S>) -> impl Iterator<Item = String> {
+    words
+        .into_iter()
+        .map(|w| w.as_ref().to_ascii_lowercase())
+        .filter(|w| !w.is_empty())
+}
+
+impl KeywordFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn block<S: AsRef<str>>(mut self, words: impl IntoIterator<Item = S>) -> Self {
+        self.blocked.extend(keywords(words));
+        self
+    }
+
+    pub fn redact<S: AsRef<str>>(mut self, words: impl IntoIterator<Item = S>) -> Self {
+        self.redacted.extend(keywords(words));
+        self
+    }
+
+    /// 封鎖符合 regex 的文字，eg: 信用卡號碼 `\b(?:\d[ -]?){13,16}\b`
+    pub fn block_regex<S: AsRef<str>>(
+        mut self,
+        patterns: impl IntoIterator<Item = S>,
+    ) -> Result<Self> {
+        self.blocked_patterns.extend(self::patterns(patterns)?);
+        Ok(self)
+    }
+
+    /// 遮蔽符合 regex 的文字，eg: email `[\w.+-]+@[\w-]+\.[\w.]+`
+    pub fn redact_regex<S: AsRef<str>>(
+        mut self,
+        patterns: impl IntoIterator<Item = S>,
+    ) -> Result<Self> {
+        self.redacted_patterns.extend(self::patterns(patterns)?);
+        Ok(self)
+    }
+
+    pub fn with_replacement(mut self, replacement: &str) -> Self {
+        self.replacement = replacement.to_string();
+        self
+    }
+
+    fn find_blocked(&self, text: &str) -> Option<&str> {
+        let lower = text.to_ascii_lowercase();
+        self.blocked
+            .iter()
+            .find(|w| lower.contains(w.as_str()))
+            .map(String::as_str)
+            .or_else(|| {
+                self.blocked_patterns
+                    .iter()
+                    .find(|r| r.is_match(text))
+                    .map(Regex::as_str)
+            })
+    }
+
+    // ASCII 小寫不改變 byte 位置，可以直接對應回原文
+    fn redact_text(&self, text: &str) -> Option<String> {
+        let lower = text.to_ascii_lowercase();
+        let mut ranges = vec![];
+        for word in &self.redacted {
+            ranges.extend(
+                lower
+                    .match_indices(word.as_str())
+                    .map(|(i, w)| (i, i + w.len())),
+            );
+        }
+        for regex in &self.redacted_patterns {
+            ranges.extend(
+                regex
+                    .find_iter(text)
+                    .filter(|m| !m.is_empty())
+                    .map(|m| (m.start(), m.end())),
+            );
+        }
+        if ranges.is_empty() {
+            return None;
+        }
+        ranges.sort();
+
+        let mut redacted = String::with_capacity(text.len());
+        let mut pos = 0;
+        for (start, end) in ranges {
+            if start < pos {
+                pos = pos.max(end);
+                continue;
+            }
+            redacted.push_str(&text[pos..start]);
+            redacted.push_str(&self.replacement);
+            pos = end;
+        }
+        redacted.push_str(&text[pos..]);
+        Some(redacted)
+    }
+}
+
+impl Moderation for KeywordFilter {
+    fn check_prompt(&mut self, prompt: &str) -> Result<Verdict> {
+        if let Some(word) = self.find_blocked(prompt) {
+            return Ok(Verdict::Block(format!(
+                "prompt contains blocked keyword {word:?}"
+            )));
+        }
+        Ok(self
+            .redact_text(prompt)
+            .map_or(Verdict::Allow, Verdict::Redact))
+    }
+
+    fn check_output(&mut self, text: &str, generated: &str) -> Result<Verdict> {
+        if let Some(word) = self.find_blocked(generated) {
+            return Ok(Verdict::Stop(format!(
+                "output contains blocked keyword {word:?}"
+            )));
+        }
+        Ok(self
+            .redact_text(text)
+            .map_or(Verdict::Allow, Verdict::Redact))
+    }
+}
+
+/// 將 Moderation 加入 TextGeneration 的 stopping criteria，check_output 回傳 Stop 或 Block
+/// 時停止生成，StopReason 為 Criteria。Redact 需要在串流輸出時自行呼叫 check_output 處理
+pub struct ModerationStop<M> {
+    tokenizer: Tokenizer,
+    moderation: M,
+    // 已經串流 decode 的 token 數與文字，每一步只 decode 新的 token
+    tokens: usize,
+    generated: String,
+}
+
+impl<M: Moderation> ModerationStop<M> {
+    pub fn new(tokenizer: &Tokenizer, moderation: M) -> Self {
+        let mut tokenizer = tokenizer.clone();
+        tokenizer.clear();
+        Self {
+            tokenizer,
+            moderation,
+            tokens: 0,
+            generated: String::new(),
+        }
+    }
+}
+
+impl<M: Moderation> StoppingCriteria for ModerationStop<M> {
+    fn should_stop(&mut self, ctx: &StoppingContext) -> Result<bool> {
+        let text_len = self.generated.len();
+        for &token in &ctx.generated_tokens()[self.tokens..] {
+            if let Some(text) = self.tokenizer.next_token(token)? {
+                self.generated.push_str(&text);
+            }
+        }
+        self.tokens = ctx.generated_tokens().len();
+        if self.generated.len() == text_len {
+            return Ok(false);
+        }
+        let verdict = self
+            .moderation
+            .check_output(&self.generated[text_len..], &self.generated)?;
+        Ok(verdict.is_stop())
+    }
+
+    fn reset(&mut self) {
+        self.tokenizer.clear();
+        self.tokens = 0;
+        self.generated.clear();
+        self.moderation.reset();
+    }
+}
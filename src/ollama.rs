@@ -0,0 +1,141 @@
+use crate::{Result, bail, repo::Repo};
+use serde::Deserialize;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_REGISTRY: &str = "registry.ollama.ai";
+const DEFAULT_NAMESPACE: &str = "library";
+const DEFAULT_TAG: &str = "latest";
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Layer {
+    #[serde(rename = "mediaType")]
+    media_type: String,
+    digest: String,
+}
+
+/// 讀取 Ollama 已下載的模型，直接使用 blobs 中的 GGUF，不需要另外複製一份。
+///
+/// 檔名對應 manifest 中的 layer: `model.gguf`、`template`、`system`、`params`、`license`。
+/// Ollama 只有 GGUF，safetensors 與 tokenizer.json 等檔案不存在
+pub struct OllamaRepo {
+    model_id: String,
+    models_dir: PathBuf,
+    layers: Vec<Layer>,
+}
+
+impl OllamaRepo {
+    /// model 格式同 `ollama pull`，eg: `qwen2.5:0.5b`、`user/model:tag`。
+    /// 模型目錄預設為 $OLLAMA_MODELS 或 `~/.ollama/models`
+    pub fn new(model: &str) -> Result<Self> {
+        let models_dir = match std::env::var_os("OLLAMA_MODELS") {
+            Some(dir) => PathBuf::from(dir),
+            None => match std::env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".ollama").join("models"),
+                None => bail!("cannot find ollama models directory"),
+            },
+        };
+        Self::with_models_dir(models_dir, model)
+    }
+
+    pub fn with_models_dir<P: AsRef<Path>>(models_dir: P, model: &str) -> Result<Self> {
+        let models_dir = models_dir.as_ref().to_path_buf();
+        let manifest_file = models_dir.join("manifests").join(manifest_path(model));
+        let manifest: Manifest = match File::open(&manifest_file) {
+            Ok(file) => serde_json::from_reader(file)?,
+            Err(_) => bail!("ollama model {model} not found: {manifest_file:?}"),
+        };
+        Ok(Self {
+            model_id: model.to_string(),
+            models_dir,
+            layers: manifest.layers,
+        })
+    }
+
+    fn layer(&self, media_type: &str) -> Result<PathBuf> {
+        let Some(layer) = self
+            .layers
+            .iter()
+            .find(|l| l.media_type == format!("application/vnd.ollama.image.{media_type}"))
+        else {
+            bail!("{}: no {media_type} layer", self.model_id)
+        };
+        // blob 檔名把 digest 的 ':' 換成 '-'
+        Ok(self
+            .models_dir
+            .join("blobs")
+            .join(layer.digest.replace(':', "-")))
+    }
+
+    /// 模型的 GGUF 檔案
+    pub fn gguf_file(&self) -> Result<PathBuf> {
+        self.layer("model")
+    }
+
+    /// Ollama 的 Go template，不是 Jinja
+    pub fn template(&self) -> Result<String> {
+        Ok(std::fs::read_to_string(self.layer("template")?)?)
+    }
+}
+
+// [host/][namespace/]name[:tag] -> host/namespace/name/tag
+fn manifest_path(model: &str) -> PathBuf {
+    let (name, tag) = match model.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => (name, tag),
+        _ => (model, DEFAULT_TAG),
+    };
+    let mut parts: Vec<&str> = name.split('/').collect();
+    let name = parts.pop().unwrap_or_default();
+    let (registry, namespace) = match parts.as_slice() {
+        [] => (DEFAULT_REGISTRY.to_string(), DEFAULT_NAMESPACE.to_string()),
+        [namespace] => (DEFAULT_REGISTRY.to_string(), namespace.to_string()),
+        [registry, rest @ ..] => (registry.to_string(), rest.join("/")),
+    };
+    PathBuf::from(registry).join(namespace).join(name).join(tag)
+}
+
+impl Repo for OllamaRepo {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn get(&self, filename: &str) -> Result<PathBuf> {
+        match filename {
+            "model.gguf" => self.layer("model"),
+            "template" | "system" | "params" | "license" => self.layer(filename),
+            _ => bail!(
+                "{}: ollama models only provide GGUF, {filename} not found",
+                self.model_id
+            ),
+        }
+    }
+
+    fn tokenizer_config_file(&self) -> Result<PathBuf> {
+        self.get("tokenizer_config.json")
+    }
+
+    fn tokenizer_file(&self) -> Result<PathBuf> {
+        self.get("tokenizer.json")
+    }
+
+    fn config_file(&self) -> Result<PathBuf> {
+        self.get("config.json")
+    }
+
+    fn safetensors_files(&self) -> Result<Vec<PathBuf>> {
+        Ok(vec![self.get("model.safetensors")?])
+    }
+
+    fn pytorch_model_file(&self) -> Result<PathBuf> {
+        self.get("pytorch_model.bin")
+    }
+
+    fn generate_config_file(&self) -> Result<PathBuf> {
+        self.get("generation_config.json")
+    }
+}
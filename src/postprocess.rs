@@ -0,0 +1,133 @@
+/// 生成結束後處理輸出的文字，eg: 去掉 markdown 的 code fence
+pub trait PostProcessor: Send + Sync {
+    fn process(&self, text: &str) -> String;
+}
+
+impl<F> PostProcessor for F
+where
+    F: Fn(&str) -> String + Send + Sync,
+{
+    fn process(&self, text: &str) -> String {
+        self(text)
+    }
+}
+
+/// 去掉 code fence 的標記行 (eg: ```json)，保留其中的內容
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StripCodeFences;
+
+impl PostProcessor for StripCodeFences {
+    fn process(&self, text: &str) -> String {
+        if !text.contains("```") {
+            return text.to_string();
+        }
+        text.lines()
+            .filter(|line| !line.trim_start().starts_with("```"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// 將連續的空白合併為一個空白，三行以上的空行合併為一行空行，並去掉頭尾的空白
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CollapseWhitespace;
+
+impl PostProcessor for CollapseWhitespace {
+    fn process(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut spaces = false;
+        let mut newlines = 0;
+        for c in text.trim().chars() {
+            if c == '\n' {
+                newlines += 1;
+                spaces = false;
+            } else if c.is_whitespace() {
+                spaces = true;
+            } else {
+                if newlines > 0 {
+                    out.push_str(if newlines > 1 { "\n\n" } else { "\n" });
+                } else if spaces {
+                    out.push(' ');
+                }
+                newlines = 0;
+                spaces = false;
+                out.push(c);
+            }
+        }
+        out
+    }
+}
+
+/// 從第一個出現的 stop string 開始截斷，eg: StopStrings 停止時輸出會包含 stop string
+#[derive(Debug, Clone, Default)]
+pub struct TrimStopStrings(pub Vec<String>);
+
+impl PostProcessor for TrimStopStrings {
+    fn process(&self, text: &str) -> String {
+        let end = self
+            .0
+            .iter()
+            .filter(|stop| !stop.is_empty())
+            .filter_map(|stop| text.find(stop.as_str()))
+            .min()
+            .unwrap_or(text.len());
+        text[..end].to_string()
+    }
+}
+
+/// 去掉開頭的文字，eg: prefill 或是 base model 重複輸出的 "Assistant:"
+#[derive(Debug, Clone, Default)]
+pub struct StripPrefix(pub String);
+
+impl PostProcessor for StripPrefix {
+    fn process(&self, text: &str) -> String {
+        let trimmed = text.trim_start();
+        match trimmed.strip_prefix(self.0.trim()) {
+            Some(rest) if !self.0.trim().is_empty() => rest.to_string(),
+            _ => text.to_string(),
+        }
+    }
+}
+
+/// 依加入順序執行的 PostProcessor
+#[derive(Default)]
+pub struct PostProcess {
+    processors: Vec<Box<dyn PostProcessor>>,
+}
+
+impl PostProcess {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<P: PostProcessor + 'static>(mut self, processor: P) -> Self {
+        self.processors.push(Box::new(processor));
+        self
+    }
+
+    pub fn strip_prefix(self, prefix: &str) -> Self {
+        self.with(StripPrefix(prefix.to_string()))
+    }
+
+    pub fn trim_stop_strings<S: Into<String>>(self, stops: impl IntoIterator<Item = S>) -> Self {
+        self.with(TrimStopStrings(stops.into_iter().map(Into::into).collect()))
+    }
+
+    pub fn strip_code_fences(self) -> Self {
+        self.with(StripCodeFences)
+    }
+
+    pub fn collapse_whitespace(self) -> Self {
+        self.with(CollapseWhitespace)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processors.is_empty()
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        self.processors
+            .iter()
+            .fold(text.to_string(), |text, processor| processor.process(&text))
+    }
+}
@@ -0,0 +1,223 @@
+use crate::chat_template::ChatTemplate;
+use crate::chunking::split_by_tokens;
+use crate::tokenizers::Tokenizer;
+use crate::vectorstore::{Metric, VectorStore};
+use crate::{Result, bail};
+use serde_json::{Value, json};
+
+/// 檢索到的片段，id 為在 VectorStore 中的 id
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retrieved {
+    pub id: usize,
+    pub score: f32,
+    /// index 時的文件名稱
+    pub doc: String,
+    pub text: String,
+}
+
+/// 生成的回答與引用的片段
+#[derive(Debug, Clone, PartialEq)]
+pub struct RagAnswer {
+    pub answer: String,
+    /// 回答中以 `[id]` 引用、且在 retrieved 中的片段，依第一次出現的順序
+    pub citations: Vec<usize>,
+    pub retrieved: Vec<Retrieved>,
+}
+
+/// retrieval-augmented generation: 依 token 數切割文件並建立向量索引，
+/// 以 query 檢索 top-k 片段，放到 chat template 的 `documents` 後生成回答。
+///
+/// embed 將多段文字轉成向量，eg: 以 `EmbeddingPrompts` 加上前綴後交給 embedding 模型
+pub struct Rag<E> {
+    tokenizer: Tokenizer,
+    embed: E,
+    store: Option<VectorStore>,
+    metric: Metric,
+    max_tokens: usize,
+    overlap: usize,
+    top_k: usize,
+    instruction: String,
+}
+
+impl<E> Rag<E>
+where
+    E: Fn(&[String]) -> Result<Vec<Vec<f32>>>,
+{
+    /// 預設每段最多 256 個 token、重疊 32 個 token，檢索 4 段
+    pub fn new(tokenizer: &Tokenizer, embed: E) -> Self {
+        Self {
+            tokenizer: tokenizer.clone(),
+            embed,
+            store: None,
+            metric: Metric::Cosine,
+            max_tokens: 256,
+            overlap: 32,
+            top_k: 4,
+            instruction: "Answer the question using the documents below. \
+                          Cite the documents you use by their id, eg: [0]."
+                .to_string(),
+        }
+    }
+
+    /// 每段最多 max_tokens 個 token，相鄰兩段重疊 overlap 個 token
+    pub fn with_chunking(mut self, max_tokens: usize, overlap: usize) -> Self {
+        self.max_tokens = max_tokens;
+        self.overlap = overlap;
+        self
+    }
+
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// 建立索引時使用的相似度，已有索引時無效
+    pub fn with_metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// 使用已建立的索引，eg: `VectorStore::load`，metadata 需要有 doc 與 text
+    pub fn with_store(mut self, store: VectorStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// template 沒有使用 `documents` 時，放在 system 訊息開頭的說明
+    pub fn with_instruction(mut self, instruction: &str) -> Self {
+        self.instruction = instruction.to_string();
+        self
+    }
+
+    pub fn store(&self) -> Option<&VectorStore> {
+        self.store.as_ref()
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let vectors = (self.embed)(texts)?;
+        if vectors.len() != texts.len() {
+            bail!(
+                "embed returned {} vectors for {} texts",
+                vectors.len(),
+                texts.len()
+            );
+        }
+        Ok(vectors)
+    }
+
+    /// 切割文件並加入索引，回傳片段的 id
+    pub fn index(&mut self, doc: &str, text: &str) -> Result<Vec<usize>> {
+        let chunks = split_by_tokens(text, &self.tokenizer, self.max_tokens, self.overlap)?;
+        if chunks.is_empty() {
+            return Ok(vec![]);
+        }
+        let texts: Vec<_> = chunks.iter().map(|c| c.text.clone()).collect();
+        let vectors = self.embed(&texts)?;
+
+        let metric = self.metric;
+        let store = self
+            .store
+            .get_or_insert_with(|| VectorStore::new(vectors[0].len(), metric));
+        chunks
+            .into_iter()
+            .zip(vectors)
+            .map(|(chunk, vector)| {
+                let metadata = json!({
+                    "doc": doc,
+                    "text": chunk.text,
+                    "start": chunk.start,
+                    "end": chunk.end,
+                });
+                store.add(vector, metadata)
+            })
+            .collect()
+    }
+
+    /// 與 query 最相似的 top-k 片段，由高到低排序
+    pub fn retrieve(&self, query: &str) -> Result<Vec<Retrieved>> {
+        let Some(store) = &self.store else {
+            return Ok(vec![]);
+        };
+        let query = self.embed(&[query.to_string()])?;
+        let text = |v: &Value, key: &str| v[key].as_str().unwrap_or_default().to_string();
+        Ok(store
+            .search(&query[0], self.top_k)?
+            .into_iter()
+            .map(|r| Retrieved {
+                id: r.id,
+                score: r.score,
+                doc: text(r.metadata, "doc"),
+                text: text(r.metadata, "text"),
+            })
+            .collect())
+    }
+
+    /// 以 `documents` (id、title、text) render prompt。template 沒有使用 documents 時，
+    /// 改為在 system 訊息中以 `[id] text` 列出
+    pub fn render(
+        &self,
+        template: &ChatTemplate,
+        query: &str,
+        retrieved: &[Retrieved],
+    ) -> Result<String> {
+        let documents: Vec<_> = retrieved
+            .iter()
+            .map(|r| json!({ "id": r.id.to_string(), "title": r.doc, "text": r.text }))
+            .collect();
+        let user = json!({ "role": "user", "content": query });
+        let prompt = template.apply(json!({
+            "messages": [user],
+            "documents": documents,
+            "add_generation_prompt": true,
+        }))?;
+        if retrieved.iter().all(|r| prompt.contains(&r.text)) {
+            return Ok(prompt);
+        }
+
+        let mut system = self.instruction.clone();
+        for r in retrieved {
+            system += &format!("\n\n[{}] {}", r.id, r.text);
+        }
+        template.apply(json!({
+            "messages": [{ "role": "system", "content": system }, user],
+            "add_generation_prompt": true,
+        }))
+    }
+
+    /// generate 以 prompt 生成回答，eg: encode、TextGeneration 生成後 decode
+    pub fn answer<F>(
+        &self,
+        template: &ChatTemplate,
+        query: &str,
+        mut generate: F,
+    ) -> Result<RagAnswer>
+    where
+        F: FnMut(&str) -> Result<String>,
+    {
+        let retrieved = self.retrieve(query)?;
+        let answer = generate(&self.render(template, query, &retrieved)?)?;
+        let citations = citations(&answer, &retrieved);
+        Ok(RagAnswer {
+            answer,
+            citations,
+            retrieved,
+        })
+    }
+}
+
+// 回答中的 [id]，只保留檢索到的片段
+fn citations(answer: &str, retrieved: &[Retrieved]) -> Vec<usize> {
+    let mut ids = vec![];
+    for part in answer.split('[').skip(1) {
+        let Some((id, _)) = part.split_once(']') else {
+            continue;
+        };
+        let Ok(id) = id.trim().parse::<usize>() else {
+            continue;
+        };
+        if retrieved.iter().any(|r| r.id == id) && !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    ids
+}
@@ -0,0 +1,252 @@
+use crate::logits::{LogitsContext, LogitsTransform, force};
+use crate::tokenizers::Tokenizer;
+use crate::{Result, bail};
+use candle_core::Tensor;
+use serde::{Deserialize, Serialize};
+
+pub const THINK_START: &str = "<think>";
+pub const THINK_END: &str = "</think>";
+
+/// 推理模型 (DeepSeek-R1、Qwen3) 的輸出，拆成 `<think>...</think>` 中的推理與最後的回答
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reasoning {
+    pub reasoning: Option<String>,
+    pub content: String,
+}
+
+impl Reasoning {
+    /// 拆開完整的輸出。沒有 `</think>` 時視為推理還沒結束
+    pub fn parse(text: &str) -> Self {
+        let mut parser = ReasoningParser::new();
+        let mut delta = parser.push(text);
+        delta.append(parser.finish());
+        parser.into_reasoning(delta)
+    }
+
+    /// prompt 已經以 `<think>` 結尾時使用，eg: DeepSeek-R1 的 template
+    pub fn parse_in_reasoning(text: &str) -> Self {
+        let mut parser = ReasoningParser::new().starts_in_reasoning();
+        let mut delta = parser.push(text);
+        delta.append(parser.finish());
+        parser.into_reasoning(delta)
+    }
+
+    /// decode 生成的 token 後拆開，eg: `GeneratedSequence::tokens`
+    pub fn decode(tokenizer: &Tokenizer, tokens: &[u32]) -> Result<Self> {
+        // 不略過 special token，保留 <think> 等標記
+        let text = match tokenizer.tokenizer().decode(tokens, false) {
+            Ok(text) => text,
+            Err(err) => bail!("cannot decode: {err}"),
+        };
+        Ok(Self::parse(&text))
+    }
+}
+
+/// 串流輸出的片段，分成推理與回答兩個 channel
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReasoningDelta {
+    pub reasoning: String,
+    pub content: String,
+}
+
+impl ReasoningDelta {
+    pub fn is_empty(&self) -> bool {
+        self.reasoning.is_empty() && self.content.is_empty()
+    }
+
+    fn append(&mut self, other: Self) {
+        self.reasoning.push_str(&other.reasoning);
+        self.content.push_str(&other.content);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    // 還沒有輸出，可能以 <think> 開始
+    Start,
+    Reasoning,
+    // </think> 之後，略過開頭的空白
+    AfterReasoning,
+    Content,
+}
+
+/// 逐段拆開 `<think>...</think>`，標記被切在兩段之間時會先暫存。
+///
+/// 只有輸出開頭的 `<think>` 會被視為推理的開始
+#[derive(Debug, Clone)]
+pub struct ReasoningParser {
+    start: String,
+    end: String,
+    state: State,
+    buffer: String,
+    has_reasoning: bool,
+}
+
+impl Default for ReasoningParser {
+    fn default() -> Self {
+        Self::with_tags(THINK_START, THINK_END)
+    }
+}
+
+impl ReasoningParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_tags(start: &str, end: &str) -> Self {
+        Self {
+            start: start.to_string(),
+            end: end.to_string(),
+            state: State::Start,
+            buffer: String::new(),
+            has_reasoning: false,
+        }
+    }
+
+    /// prompt 已經以 `<think>` 結尾，輸出從推理開始
+    pub fn starts_in_reasoning(mut self) -> Self {
+        self.state = State::Reasoning;
+        self.has_reasoning = true;
+        self
+    }
+
+    /// 目前是否在推理中
+    pub fn in_reasoning(&self) -> bool {
+        self.state == State::Reasoning
+    }
+
+    pub fn push(&mut self, text: &str) -> ReasoningDelta {
+        self.buffer.push_str(text);
+        let mut delta = ReasoningDelta::default();
+        loop {
+            match self.state {
+                State::Start => {
+                    let trimmed = self.buffer.trim_start();
+                    if let Some(rest) = trimmed.strip_prefix(self.start.as_str()) {
+                        self.buffer = rest.to_string();
+                        self.state = State::Reasoning;
+                        self.has_reasoning = true;
+                    } else if self.start.starts_with(trimmed) {
+                        // 可能是 <think> 的開頭，等待更多輸出
+                        return delta;
+                    } else {
+                        self.state = State::Content;
+                    }
+                }
+                State::Reasoning => match self.buffer.find(self.end.as_str()) {
+                    Some(i) => {
+                        delta.reasoning.push_str(&self.buffer[..i]);
+                        self.buffer.drain(..i + self.end.len());
+                        self.state = State::AfterReasoning;
+                    }
+                    None => {
+                        let keep = partial_suffix(&self.buffer, &self.end);
+                        let emit = self.buffer.len() - keep;
+                        delta.reasoning.push_str(&self.buffer[..emit]);
+                        self.buffer.drain(..emit);
+                        return delta;
+                    }
+                },
+                State::AfterReasoning => {
+                    let trimmed = self.buffer.trim_start();
+                    if trimmed.is_empty() {
+                        self.buffer.clear();
+                        return delta;
+                    }
+                    self.buffer = trimmed.to_string();
+                    self.state = State::Content;
+                }
+                State::Content => {
+                    delta.content.push_str(&self.buffer);
+                    self.buffer.clear();
+                    return delta;
+                }
+            }
+        }
+    }
+
+    /// 輸出結束，回傳暫存的文字
+    pub fn finish(&mut self) -> ReasoningDelta {
+        let buffer = std::mem::take(&mut self.buffer);
+        match self.state {
+            State::Reasoning => ReasoningDelta {
+                reasoning: buffer,
+                ..Default::default()
+            },
+            _ => ReasoningDelta {
+                content: buffer,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn into_reasoning(self, delta: ReasoningDelta) -> Reasoning {
+        Reasoning {
+            reasoning: self
+                .has_reasoning
+                .then(|| delta.reasoning.trim().to_string()),
+            content: delta.content,
+        }
+    }
+}
+
+// text 結尾與 tag 開頭相同的最長長度
+fn partial_suffix(text: &str, tag: &str) -> usize {
+    (1..tag.len().min(text.len() + 1))
+        .rev()
+        .find(|&n| {
+            text.is_char_boundary(text.len() - n) && tag.starts_with(&text[text.len() - n..])
+        })
+        .unwrap_or(0)
+}
+
+/// 限制推理的 token 數，超過 budget 後強制生成 end tokens 結束推理，
+/// 同 Qwen3 的 thinking budget。
+///
+/// 推理從最後一個 start token 開始 (可以在 prompt 中)，生成 end tokens 後結束
+#[derive(Debug, Clone)]
+pub struct ThinkingBudget {
+    pub start_token: u32,
+    pub end_tokens: Vec<u32>,
+    pub budget: usize,
+}
+
+impl ThinkingBudget {
+    /// 使用 tokenizer 中的 `<think>` 與 `</think>`
+    pub fn from_tokenizer(tokenizer: &Tokenizer, budget: usize) -> Result<Self> {
+        let (Some(start_token), Some(end_token)) = (
+            tokenizer.get_token(THINK_START),
+            tokenizer.get_token(THINK_END),
+        ) else {
+            bail!("tokenizer has no {THINK_START} or {THINK_END} token")
+        };
+        Ok(Self {
+            start_token,
+            end_tokens: vec![end_token],
+            budget,
+        })
+    }
+}
+
+impl LogitsTransform for ThinkingBudget {
+    fn apply(&mut self, logits: &Tensor, ctx: &LogitsContext) -> Result<Tensor> {
+        let Some(start) = ctx.tokens.iter().rposition(|&t| t == self.start_token) else {
+            return Ok(logits.clone());
+        };
+        let thinking = &ctx.tokens[start + 1..];
+        if self.end_tokens.is_empty()
+            || thinking.len() < self.budget
+            || thinking
+                .windows(self.end_tokens.len())
+                .any(|w| w == self.end_tokens.as_slice())
+        {
+            return Ok(logits.clone());
+        }
+        // 已經強制生成部份的 end tokens 時，接著生成下一個
+        let matched = (1..self.end_tokens.len())
+            .rev()
+            .find(|&n| thinking.ends_with(&self.end_tokens[..n]))
+            .unwrap_or(0);
+        force(logits, &self.end_tokens[matched..matched + 1])
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{Result, bail};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+type Loader<T> = Arc<dyn Fn() -> Result<T> + Send + Sync>;
+
+struct Entry<T> {
+    loader: Loader<T>,
+    bytes: u64,
+    loaded: Option<Arc<T>>,
+    last_used: u64,
+}
+
+struct Inner<T> {
+    entries: HashMap<String, Entry<T>>,
+    clock: u64,
+}
+
+/// 以名稱管理多個模型，第一次 get 時才載入，超過記憶體預算時卸載最久沒用到的模型。
+///
+/// 卸載只會釋放 registry 持有的 Arc，正在使用中的模型在使用結束後才會釋放。
+/// 載入期間會鎖住整個 registry，避免同一個模型被重複載入
+pub struct ModelRegistry<T> {
+    budget: u64,
+    inner: Mutex<Inner<T>>,
+}
+
+impl<T> ModelRegistry<T> {
+    /// budget: 所有已載入模型的記憶體上限 (bytes)
+    pub fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Inner<T>>> {
+        match self.inner.lock() {
+            Ok(inner) => Ok(inner),
+            Err(_) => bail!("model registry lock poisoned"),
+        }
+    }
+
+    /// 註冊模型，bytes 為載入後預估佔用的記憶體，eg: safetensors 檔案大小。
+    /// 同名的模型會被取代
+    pub fn register<F>(&self, name: &str, bytes: u64, loader: F) -> Result<()>
+    where
+        F: Fn() -> Result<T> + Send + Sync + 'static,
+    {
+        self.lock()?.entries.insert(
+            name.to_string(),
+            Entry {
+                loader: Arc::new(loader),
+                bytes,
+                loaded: None,
+                last_used: 0,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn unregister(&self, name: &str) -> Result<bool> {
+        Ok(self.lock()?.entries.remove(name).is_some())
+    }
+
+    /// 取得模型，尚未載入時先載入
+    pub fn get(&self, name: &str) -> Result<Arc<T>> {
+        let mut inner = self.lock()?;
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        let Some(entry) = inner.entries.get_mut(name) else {
+            bail!("model {name} is not registered")
+        };
+        entry.last_used = clock;
+        if let Some(model) = &entry.loaded {
+            return Ok(model.clone());
+        }
+        let bytes = entry.bytes;
+        let loader = entry.loader.clone();
+        if bytes > self.budget {
+            bail!(
+                "model {name} needs {bytes} bytes, exceeds budget {}",
+                self.budget
+            );
+        }
+
+        while used_bytes(&inner) + bytes > self.budget {
+            let Some(lru) = inner
+                .entries
+                .iter_mut()
+                .filter(|(_, e)| e.loaded.is_some())
+                .min_by_key(|(_, e)| e.last_used)
+            else {
+                break;
+            };
+            lru.1.loaded = None;
+        }
+
+        let model = Arc::new(loader()?);
+        if let Some(entry) = inner.entries.get_mut(name) {
+            entry.loaded = Some(model.clone());
+        }
+        Ok(model)
+    }
+
+    /// 卸載模型，之後的 get 會重新載入
+    pub fn unload(&self, name: &str) -> Result<bool> {
+        let mut inner = self.lock()?;
+        Ok(inner
+            .entries
+            .get_mut(name)
+            .and_then(|e| e.loaded.take())
+            .is_some())
+    }
+
+    pub fn is_loaded(&self, name: &str) -> Result<bool> {
+        Ok(self
+            .lock()?
+            .entries
+            .get(name)
+            .is_some_and(|e| e.loaded.is_some()))
+    }
+
+    /// 已註冊的模型名稱
+    pub fn names(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = self.lock()?.entries.keys().cloned().collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// 已載入模型的預估記憶體總和
+    pub fn used_bytes(&self) -> Result<u64> {
+        let inner = self.lock()?;
+        Ok(used_bytes(&inner))
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+}
+
+fn used_bytes<T>(inner: &Inner<T>) -> u64 {
+    inner
+        .entries
+        .values()
+        .filter(|e| e.loaded.is_some())
+        .map(|e| e.bytes)
+        .sum()
+}
@@ -1,11 +1,17 @@
-use crate::{Error as E, Result, bail, generation::GenerationConfig};
+use crate::{
+    Error as E, Result, bail, config::ModelConfig, generation::GenerationConfig,
+    tokenizers::SpecialTokens,
+};
 use candle_core::quantized::gguf_file;
-use candle_core::{DType, Device};
+use candle_core::{DType, Device, Shape, Tensor};
 use candle_nn::VarBuilder;
+use candle_nn::var_builder::SimpleBackend;
 use serde_json::Value;
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 /// 代表模型 repo
 pub trait Repo {
@@ -41,28 +47,82 @@ pub trait Repo {
         Ok(config)
     }
 
+    /// 回傳寬鬆解析的 ModelConfig
+    fn model_config(&self) -> Result<ModelConfig> {
+        ModelConfig::from_file(self.config_file()?)
+    }
+
     /// 回傳 GenerationConfig struct
     fn generate_config(&self) -> Result<GenerationConfig> {
         GenerationConfig::from_file(self.generate_config_file()?)
     }
 
+    /// 回傳 special_tokens_map.json、added_tokens.json 與 tokenizer_config.json 中的特殊 token
+    fn special_tokens(&self) -> Result<SpecialTokens> {
+        let mut files = vec![];
+        for f in [
+            "special_tokens_map.json",
+            "added_tokens.json",
+            "tokenizer_config.json",
+        ] {
+            files.extend(found(self.get(f))?);
+        }
+        SpecialTokens::from_files(&files)
+    }
+
     fn load_model<C, M, F>(&self, dtype: DType, device: &Device, load: F) -> Result<M>
+    where
+        C: serde::de::DeserializeOwned,
+        F: Fn(&C, VarBuilder) -> candle_core::Result<M>,
+    {
+        self.load_model_with(dtype, device, &LoadOptions::default(), load)
+    }
+
+    /// 同 load_model，可以指定 safetensors 的讀取方式
+    fn load_model_with<C, M, F>(
+        &self,
+        dtype: DType,
+        device: &Device,
+        options: &LoadOptions,
+        load: F,
+    ) -> Result<M>
     where
         C: serde::de::DeserializeOwned,
         F: Fn(&C, VarBuilder) -> candle_core::Result<M>,
     {
         let config: C = self.config()?;
 
-        let vb = if let Ok(safetensor_files) = self.safetensors_files() {
-            unsafe { VarBuilder::from_mmaped_safetensors(&safetensor_files, dtype, device) }
-        } else {
-            let pytorch_model_file = self.pytorch_model_file()?;
-            VarBuilder::from_pth(pytorch_model_file, dtype, device)
-        }?;
+        // 只有檔案不存在才改用下一種格式，網路或權限錯誤直接回傳
+        let safetensors =
+            found(self.safetensors_files())?.filter(|files| files.iter().all(|f| f.exists()));
+        let vb = match safetensors {
+            Some(files) => safetensors_var_builder(&files, dtype, device, options)?,
+            None => match found(self.pytorch_model_file())?.filter(|f| f.exists()) {
+                Some(file) => VarBuilder::from_pth(file, dtype, device)?,
+                None => return Err(weights_not_found(self.model_id())),
+            },
+        };
 
         Ok(load(&config, vb)?)
     }
 
+    /// 同 load_model_with，依 DtypePolicy 決定每個權重的 dtype
+    fn load_model_with_policy<C, M, F>(
+        &self,
+        policy: &DtypePolicy,
+        device: &Device,
+        options: &LoadOptions,
+        load: F,
+    ) -> Result<M>
+    where
+        C: serde::de::DeserializeOwned,
+        F: Fn(&C, VarBuilder) -> candle_core::Result<M>,
+    {
+        self.load_model_with(policy.dtype, device, options, |config: &C, vb| {
+            load(config, policy.apply(vb))
+        })
+    }
+
     // 避開 R: std::io::Seek + std::io::Read, 與 File 型別不同的問題。
     #[inline(always)]
     fn call_from_gguf<R, F, M>(
@@ -90,6 +150,168 @@ pub trait Repo {
     }
 }
 
+/// safetensors 的讀取方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LoadMode {
+    /// mmap 後在載入 tensor 時才讀取需要的 page
+    #[default]
+    Mmap,
+    /// 先依序讀完整個檔案並建立所有 tensor，適合網路磁碟等隨機讀取較慢的環境
+    Eager,
+}
+
+/// `Repo::load_model_with` 的設定
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadOptions {
+    pub mode: LoadMode,
+    /// mmap 前先依序讀過檔案，讓資料進入 page cache，減少之後的 page fault
+    pub prefetch: bool,
+}
+
+impl LoadOptions {
+    pub fn eager() -> Self {
+        Self {
+            mode: LoadMode::Eager,
+            prefetch: false,
+        }
+    }
+
+    pub fn prefetch() -> Self {
+        Self {
+            mode: LoadMode::Mmap,
+            prefetch: true,
+        }
+    }
+}
+
+/// 載入權重時使用的 dtype，可以依權重名稱的前綴指定不同的 dtype。
+///
+/// eg: bf16 的模型將 lm_head 以 F32 載入。被指定的 layer 輸入仍是 dtype，
+/// 需要在模型中轉換，eg: `lm_head.forward(&x.to_dtype(DType::F32)?)`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtypePolicy {
+    pub dtype: DType,
+    /// 權重名稱的前綴與 dtype，最長的前綴優先
+    pub overrides: Vec<(String, DType)>,
+}
+
+impl DtypePolicy {
+    pub fn new(dtype: DType) -> Self {
+        Self {
+            dtype,
+            overrides: vec![],
+        }
+    }
+
+    /// prefix 為權重名稱或是 module 路徑，eg: `lm_head`、`model.layers.0`
+    pub fn with_override(mut self, prefix: &str, dtype: DType) -> Self {
+        self.overrides.push((prefix.to_string(), dtype));
+        self
+    }
+
+    /// 指定權重使用的 dtype
+    pub fn dtype_of(&self, name: &str) -> DType {
+        self.overrides
+            .iter()
+            .filter(|(prefix, _)| {
+                name.strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.dtype, |(_, dtype)| *dtype)
+    }
+
+    /// 包裝 VarBuilder，讀取權重時使用 dtype_of 的 dtype
+    pub fn apply<'a>(&self, vb: VarBuilder<'a>) -> VarBuilder<'a> {
+        if self.overrides.is_empty() {
+            return vb.to_dtype(self.dtype);
+        }
+        let device = vb.device().clone();
+        let backend = PolicyBackend {
+            vb,
+            policy: self.clone(),
+        };
+        VarBuilder::from_backend(Box::new(backend), self.dtype, device)
+    }
+}
+
+impl From<DType> for DtypePolicy {
+    fn from(dtype: DType) -> Self {
+        Self::new(dtype)
+    }
+}
+
+struct PolicyBackend<'a> {
+    vb: VarBuilder<'a>,
+    policy: DtypePolicy,
+}
+
+impl SimpleBackend for PolicyBackend<'_> {
+    fn get(
+        &self,
+        s: Shape,
+        name: &str,
+        h: candle_nn::Init,
+        _dtype: DType,
+        _dev: &Device,
+    ) -> candle_core::Result<Tensor> {
+        self.vb
+            .get_with_hints_dtype(s, name, h, self.policy.dtype_of(name))
+    }
+
+    fn contains_tensor(&self, name: &str) -> bool {
+        self.vb.contains_tensor(name)
+    }
+}
+
+/// 檔案不存在時回傳 None，其他錯誤照樣回傳
+pub(crate) fn found<T>(result: Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(v) => Ok(Some(v)),
+        Err(err) if err.is_not_found() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn weights_not_found(model_id: &str) -> E {
+    E::WeightsNotFound {
+        model_id: model_id.to_string(),
+        tried: [
+            "model.safetensors",
+            "model.safetensors.index.json",
+            "pytorch_model.bin",
+        ]
+        .map(String::from)
+        .to_vec(),
+    }
+}
+
+/// 依 LoadOptions 建立 safetensors 的 VarBuilder
+pub fn safetensors_var_builder<P: AsRef<Path>>(
+    files: &[P],
+    dtype: DType,
+    device: &Device,
+    options: &LoadOptions,
+) -> Result<VarBuilder<'static>> {
+    match options.mode {
+        LoadMode::Mmap => {
+            if options.prefetch {
+                for file in files {
+                    std::io::copy(&mut File::open(file)?, &mut std::io::sink())?;
+                }
+            }
+            Ok(unsafe { VarBuilder::from_mmaped_safetensors(files, dtype, device)? })
+        }
+        LoadMode::Eager => {
+            let mut tensors = HashMap::new();
+            for file in files {
+                tensors.extend(candle_core::safetensors::load(file, device)?);
+            }
+            Ok(VarBuilder::from_tensors(tensors, dtype, device))
+        }
+    }
+}
+
 /// 本地存放位置
 pub struct LocalRepo {
     /// 模型 ID
@@ -110,6 +332,19 @@ impl LocalRepo {
     fn get_file<P: AsRef<Path>>(&self, p: P) -> PathBuf {
         self.path.join(p)
     }
+
+    /// 目錄中的 GGUF 檔案，eg: llama.cpp 的 models 目錄
+    pub fn gguf_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        for entry in std::fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "gguf") {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
 }
 
 impl Repo for LocalRepo {
@@ -152,6 +387,156 @@ impl Repo for LocalRepo {
     }
 }
 
+/// 檔案放在記憶體中的 repo，eg: 以 `include_bytes!` 把小模型編進執行檔。
+///
+/// config 與單一的 model.safetensors 直接從記憶體讀取；需要檔案路徑時 (eg: tokenizer)
+/// 才會把檔案寫到暫存目錄，drop 時刪除
+pub struct MemRepo {
+    model_id: String,
+    files: HashMap<String, Cow<'static, [u8]>>,
+    dir: OnceLock<PathBuf>,
+}
+
+impl MemRepo {
+    pub fn new(model_id: &str) -> Self {
+        Self {
+            model_id: model_id.to_owned(),
+            files: HashMap::new(),
+            dir: OnceLock::new(),
+        }
+    }
+
+    /// 加入檔案，content 可以是 `&'static [u8]` 或 `Vec<u8>`
+    pub fn with_file<C: Into<Cow<'static, [u8]>>>(mut self, filename: &str, content: C) -> Self {
+        self.files.insert(filename.to_owned(), content.into());
+        self
+    }
+
+    /// 檔案內容
+    pub fn bytes(&self, filename: &str) -> Result<&[u8]> {
+        match self.files.get(filename) {
+            Some(content) => Ok(content),
+            None => Err(E::FileNotFound {
+                model_id: self.model_id.clone(),
+                file: filename.to_string(),
+            }),
+        }
+    }
+
+    fn dir(&self) -> Result<&PathBuf> {
+        if let Some(dir) = self.dir.get() {
+            return Ok(dir);
+        }
+        let dir = crate::utils::unique_temp_dir("mem-repo")?;
+        Ok(self.dir.get_or_init(|| dir))
+    }
+
+    fn parse<T: serde::de::DeserializeOwned>(&self, filename: &str) -> Result<T> {
+        Ok(serde_json::from_slice(self.bytes(filename)?)?)
+    }
+}
+
+impl Drop for MemRepo {
+    fn drop(&mut self) {
+        if let Some(dir) = self.dir.get() {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+impl Repo for MemRepo {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn get(&self, filename: &str) -> Result<PathBuf> {
+        let content = self.bytes(filename)?;
+        let file = self.dir()?.join(filename);
+        if !file.exists() {
+            if let Some(parent) = file.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&file, content)?;
+        }
+        Ok(file)
+    }
+
+    fn tokenizer_config_file(&self) -> Result<PathBuf> {
+        self.get("tokenizer_config.json")
+    }
+
+    fn tokenizer_file(&self) -> Result<PathBuf> {
+        self.get("tokenizer.json")
+    }
+
+    fn config_file(&self) -> Result<PathBuf> {
+        self.get("config.json")
+    }
+
+    fn safetensors_files(&self) -> Result<Vec<PathBuf>> {
+        if self.files.contains_key("model.safetensors") {
+            return Ok(vec![self.get("model.safetensors")?]);
+        }
+        let index_file = self.get("model.safetensors.index.json")?;
+        let mut files: Vec<_> = read_safetensors_index_file(index_file)?
+            .into_iter()
+            .collect();
+        files.sort();
+        files.iter().map(|f| self.get(f)).collect()
+    }
+
+    fn pytorch_model_file(&self) -> Result<PathBuf> {
+        self.get("pytorch_model.bin")
+    }
+
+    fn generate_config_file(&self) -> Result<PathBuf> {
+        self.get("generation_config.json")
+    }
+
+    fn config<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        self.parse("config.json")
+    }
+
+    fn model_config(&self) -> Result<ModelConfig> {
+        Ok(ModelConfig::new(self.parse("config.json")?))
+    }
+
+    fn generate_config(&self) -> Result<GenerationConfig> {
+        self.parse("generation_config.json")
+    }
+
+    /// 單一的 model.safetensors 直接從記憶體載入，分片時才寫到暫存目錄
+    fn load_model_with<C, M, F>(
+        &self,
+        dtype: DType,
+        device: &Device,
+        options: &LoadOptions,
+        load: F,
+    ) -> Result<M>
+    where
+        C: serde::de::DeserializeOwned,
+        F: Fn(&C, VarBuilder) -> candle_core::Result<M>,
+    {
+        let config: C = self.config()?;
+
+        let vb = match self.files.get("model.safetensors") {
+            Some(Cow::Borrowed(data)) => VarBuilder::from_slice_safetensors(data, dtype, device)?,
+            Some(Cow::Owned(data)) => {
+                VarBuilder::from_buffered_safetensors(data.clone(), dtype, device)?
+            }
+            None => match found(self.safetensors_files())? {
+                Some(files) => safetensors_var_builder(&files, dtype, device, options)?,
+                None => match found(self.pytorch_model_file())? {
+                    Some(file) => VarBuilder::from_pth(file, dtype, device)?,
+                    None => return Err(weights_not_found(&self.model_id)),
+                },
+            },
+        };
+
+        Ok(load(&config, vb)?)
+    }
+}
+
 /// Reads a safetensors index file and returns a set of safetensors files.
 pub(crate) fn read_safetensors_index_file<P: AsRef<Path>>(json_file: P) -> Result<HashSet<String>> {
     let json_file = File::open(json_file)?;
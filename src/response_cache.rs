@@ -0,0 +1,211 @@
+use crate::Result;
+use crate::generation::GenerationConfig;
+use candle_transformers::generation::Sampling;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+// FNV-1a，跨平台與版本穩定，可以作為磁碟上的檔名
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// 快取的 key：hash 用於查詢與檔名，material 為 hash 前的內容，命中時再比對一次
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey {
+    hash: String,
+    material: String,
+}
+
+impl CacheKey {
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    key: String,
+    response: String,
+}
+
+/// 生成結果的快取，key 為 model id、套用 template 後的 prompt、生成設定與 extra 的 hash。
+/// 記憶體中為 LRU，可以另外存到目錄中。sampling 的結果預設不快取，需要 with_sampling 開啟
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    capacity: usize,
+    // hash -> (material, response)
+    entries: HashMap<String, (String, String)>,
+    // 最近使用的在最後
+    order: VecDeque<String>,
+    dir: Option<PathBuf>,
+    sampling: bool,
+    hits: usize,
+    misses: usize,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            dir: None,
+            sampling: false,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// 同時存到 dir，記憶體中被移除的結果仍可以從磁碟讀取
+    pub fn with_dir<P: AsRef<Path>>(mut self, dir: P) -> Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        self.dir = Some(dir.as_ref().to_path_buf());
+        Ok(self)
+    }
+
+    /// 是否快取 sampling 的結果，開啟後相同的 seed 與設定會得到相同的結果
+    pub fn with_sampling(mut self, sampling: bool) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// extra 為不在 GenerationConfig 中但會影響結果的設定，
+    /// eg: 傳給 apply 的 max_new_tokens、logit bias、sampler chain 與其他 transform
+    pub fn key(
+        model_id: &str,
+        prompt: &str,
+        config: &GenerationConfig,
+        seed: u64,
+        extra: &Value,
+    ) -> Result<CacheKey> {
+        let config = serde_json::to_string(config)?;
+        let material = [
+            model_id,
+            prompt,
+            &config,
+            &extra.to_string(),
+            &seed.to_string(),
+        ]
+        .join("\0");
+        let hash = fnv1a(0xcbf2_9ce4_8422_2325, material.as_bytes());
+        Ok(CacheKey {
+            hash: format!("{hash:016x}"),
+            material,
+        })
+    }
+
+    /// greedy 一定可以快取，sampling 需要 with_sampling 開啟
+    pub fn is_cacheable(&self, config: &GenerationConfig) -> bool {
+        self.sampling || matches!(config.sampling(), Sampling::ArgMax)
+    }
+
+    /// hash 相同但內容不同時視為沒有快取
+    pub fn get(&mut self, key: &CacheKey) -> Result<Option<String>> {
+        if let Some((material, response)) = self.entries.get(&key.hash)
+            && *material == key.material
+        {
+            let response = response.clone();
+            self.touch(&key.hash);
+            self.hits += 1;
+            return Ok(Some(response));
+        }
+        if let Some(path) = self.path(&key.hash).filter(|p| p.exists())
+            && let Ok(entry) = serde_json::from_reader::<_, Entry>(std::fs::File::open(path)?)
+            && entry.key == key.material
+        {
+            self.insert_memory(key, entry.response.clone());
+            self.hits += 1;
+            return Ok(Some(entry.response));
+        }
+        self.misses += 1;
+        Ok(None)
+    }
+
+    pub fn insert(&mut self, key: &CacheKey, response: &str) -> Result<()> {
+        if let Some(path) = self.path(&key.hash) {
+            let entry = Entry {
+                key: key.material.clone(),
+                response: response.to_string(),
+            };
+            serde_json::to_writer(std::fs::File::create(path)?, &entry)?;
+        }
+        self.insert_memory(key, response.to_string());
+        Ok(())
+    }
+
+    /// 有快取時直接回傳，否則呼叫 generate 並存入快取。無法快取的設定每次都呼叫 generate
+    pub fn get_or_generate<F>(
+        &mut self,
+        model_id: &str,
+        prompt: &str,
+        config: &GenerationConfig,
+        seed: u64,
+        extra: &Value,
+        generate: F,
+    ) -> Result<String>
+    where
+        F: FnOnce() -> Result<String>,
+    {
+        if !self.is_cacheable(config) {
+            return generate();
+        }
+        let key = Self::key(model_id, prompt, config, seed, extra)?;
+        if let Some(response) = self.get(&key)? {
+            return Ok(response);
+        }
+        let response = generate()?;
+        self.insert(&key, &response)?;
+        Ok(response)
+    }
+
+    /// 清除記憶體中的快取，不會刪除磁碟上的檔案
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(format!("{key}.json")))
+    }
+
+    fn touch(&mut self, key: &str) {
+        if let Some(index) = self.order.iter().position(|k| k == key) {
+            let key = self.order.remove(index).unwrap();
+            self.order.push_back(key);
+        }
+    }
+
+    fn insert_memory(&mut self, key: &CacheKey, response: String) {
+        let entry = (key.material.clone(), response);
+        if self.entries.insert(key.hash.clone(), entry).is_some() {
+            self.touch(&key.hash);
+            return;
+        }
+        self.order.push_back(key.hash.clone());
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
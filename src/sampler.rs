@@ -0,0 +1,187 @@
+use crate::logits::{
+    Dry, FrequencyPresencePenalty, LogitsTransform, MinP, Temperature, TopK, TopNSigma, TopP, Xtc,
+};
+use crate::{Error, Result, bail};
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+/// sampler chain 中的一個步驟
+#[derive(Debug, Clone, PartialEq)]
+pub enum SamplerSpec {
+    TopK(usize),
+    TopP(f32),
+    MinP(f32),
+    Temperature(f32),
+    TopNSigma(f32),
+    /// multiplier
+    Dry(f32),
+    /// threshold, probability
+    Xtc(f32, f32),
+    FrequencyPenalty(f32),
+    PresencePenalty(f32),
+    /// 最後以 argmax 選擇，沒有時依機率 sampling
+    Greedy,
+}
+
+impl SamplerSpec {
+    pub fn transform(&self, seed: u64) -> Option<Box<dyn LogitsTransform>> {
+        Some(match *self {
+            Self::TopK(k) => Box::new(TopK(k)),
+            Self::TopP(p) => Box::new(TopP(p)),
+            Self::MinP(p) => Box::new(MinP(p)),
+            Self::Temperature(t) => Box::new(Temperature(t)),
+            Self::TopNSigma(n) => Box::new(TopNSigma(n)),
+            Self::Dry(multiplier) => Box::new(Dry::new(multiplier)),
+            Self::Xtc(threshold, probability) => Box::new(Xtc::new(threshold, probability, seed)),
+            Self::FrequencyPenalty(frequency) => Box::new(FrequencyPresencePenalty {
+                frequency,
+                ..Default::default()
+            }),
+            Self::PresencePenalty(presence) => Box::new(FrequencyPresencePenalty {
+                presence,
+                ..Default::default()
+            }),
+            Self::Greedy => return None,
+        })
+    }
+}
+
+// 沒有值時使用的預設值
+fn arg<T: FromStr>(name: &str, args: &[&str], index: usize, default: Option<T>) -> Result<T> {
+    match args.get(index) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map_err(|_| Error::msg(format!("invalid value for {name}: {value}"))),
+        None => match default {
+            Some(default) => Ok(default),
+            None => bail!("{name} requires a value"),
+        },
+    }
+}
+
+impl FromStr for SamplerSpec {
+    type Err = Error;
+
+    /// eg: "top_k=40"、"dry"、"xtc=0.1,0.5"
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, args) = match s.split_once('=') {
+            Some((name, args)) => (name.trim(), args.split(',').collect::<Vec<_>>()),
+            None => (s.trim(), vec![]),
+        };
+        Ok(match name {
+            "top_k" => Self::TopK(arg(name, &args, 0, None)?),
+            "top_p" => Self::TopP(arg(name, &args, 0, None)?),
+            "min_p" => Self::MinP(arg(name, &args, 0, None)?),
+            "temp" | "temperature" => Self::Temperature(arg(name, &args, 0, None)?),
+            "top_n_sigma" => Self::TopNSigma(arg(name, &args, 0, Some(1.))?),
+            "dry" => Self::Dry(arg(name, &args, 0, Some(0.8))?),
+            "xtc" => Self::Xtc(
+                arg(name, &args, 0, Some(0.1))?,
+                arg(name, &args, 1, Some(0.5))?,
+            ),
+            "frequency_penalty" => Self::FrequencyPenalty(arg(name, &args, 0, None)?),
+            "presence_penalty" => Self::PresencePenalty(arg(name, &args, 0, None)?),
+            "greedy" => Self::Greedy,
+            _ => bail!("unknown sampler: {name}"),
+        })
+    }
+}
+
+impl fmt::Display for SamplerSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TopK(k) => write!(f, "top_k={k}"),
+            Self::TopP(p) => write!(f, "top_p={p}"),
+            Self::MinP(p) => write!(f, "min_p={p}"),
+            Self::Temperature(t) => write!(f, "temp={t}"),
+            Self::TopNSigma(n) => write!(f, "top_n_sigma={n}"),
+            Self::Dry(multiplier) => write!(f, "dry={multiplier}"),
+            Self::Xtc(threshold, probability) => write!(f, "xtc={threshold},{probability}"),
+            Self::FrequencyPenalty(v) => write!(f, "frequency_penalty={v}"),
+            Self::PresencePenalty(v) => write!(f, "presence_penalty={v}"),
+            Self::Greedy => write!(f, "greedy"),
+        }
+    }
+}
+
+// JSON 字串不含引號，eg: {"top_k": "40"}
+fn json_arg(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// 以設定描述的 sampler chain，依順序套用在 logits 上，
+/// eg: "top_k=40 -> top_p=0.9 -> temp=0.8 -> dry" 或 JSON `["top_k=40", {"top_p": 0.9}, {"xtc": [0.1, 0.5]}]`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SamplerChain(pub Vec<SamplerSpec>);
+
+impl SamplerChain {
+    /// JSON 陣列，元素為字串或只有一個 key 的物件
+    pub fn from_json(json: &str) -> Result<Self> {
+        Self::from_value(&serde_json::from_str(json)?)
+    }
+
+    pub fn from_value(value: &Value) -> Result<Self> {
+        let Some(items) = value.as_array() else {
+            bail!("sampler chain must be a JSON array");
+        };
+        let mut specs = Vec::with_capacity(items.len());
+        for item in items {
+            let spec = match item {
+                Value::String(s) => s.parse()?,
+                Value::Object(map) if map.len() == 1 => {
+                    let (name, value) = map.iter().next().unwrap();
+                    let args = match value {
+                        Value::Array(values) => values.iter().map(json_arg).collect(),
+                        Value::Null => vec![],
+                        value => vec![json_arg(value)],
+                    };
+                    if args.is_empty() {
+                        name.parse()?
+                    } else {
+                        format!("{name}={}", args.join(",")).parse()?
+                    }
+                }
+                _ => bail!("invalid sampler: {item}"),
+            };
+            specs.push(spec);
+        }
+        Ok(Self(specs))
+    }
+
+    /// 最後是否以 argmax 選擇
+    pub fn is_greedy(&self) -> bool {
+        self.0.contains(&SamplerSpec::Greedy)
+    }
+
+    pub fn transforms(&self, seed: u64) -> Vec<Box<dyn LogitsTransform>> {
+        self.0
+            .iter()
+            .filter_map(|spec| spec.transform(seed))
+            .collect()
+    }
+}
+
+impl FromStr for SamplerChain {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.split("->")
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>>>()
+            .map(Self)
+    }
+}
+
+impl fmt::Display for SamplerChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let specs: Vec<String> = self.0.iter().map(ToString::to_string).collect();
+        write!(f, "{}", specs.join(" -> "))
+    }
+}
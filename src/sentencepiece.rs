@@ -0,0 +1,338 @@
+use crate::tokenizers::Tokenizer;
+use crate::{Result, bail};
+use std::collections::HashMap;
+use std::path::Path;
+use tokenizers::decoders::DecoderWrapper;
+use tokenizers::decoders::byte_fallback::ByteFallback;
+use tokenizers::decoders::fuse::Fuse;
+use tokenizers::decoders::sequence::Sequence as DecoderSequence;
+use tokenizers::decoders::strip::Strip as DecoderStrip;
+use tokenizers::models::bpe::BPE;
+use tokenizers::models::unigram::Unigram;
+use tokenizers::normalizers::replace::ReplacePattern;
+use tokenizers::normalizers::{NormalizerWrapper, Precompiled, Prepend, Replace, Sequence, Strip};
+use tokenizers::pre_tokenizers::metaspace::{Metaspace, PrependScheme};
+use tokenizers::{AddedToken, Tokenizer as HFTokenizer};
+
+/// SentencePiece 以 ▁ 表示空白
+const SPACE: char = '▁';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PieceType {
+    Normal,
+    Unknown,
+    Control,
+    UserDefined,
+    Unused,
+    Byte,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Piece {
+    pub piece: String,
+    pub score: f32,
+    pub kind: PieceType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelType {
+    Unigram,
+    Bpe,
+    Word,
+    Char,
+}
+
+/// sentencepiece_model.proto 中轉換 tokenizer 需要的欄位
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentencePieceModel {
+    pub pieces: Vec<Piece>,
+    pub model_type: ModelType,
+    pub unk_id: usize,
+    pub byte_fallback: bool,
+    pub precompiled_charsmap: Vec<u8>,
+    pub add_dummy_prefix: bool,
+    pub remove_extra_whitespaces: bool,
+}
+
+impl SentencePieceModel {
+    pub fn from_file<P: AsRef<Path>>(model: P) -> Result<Self> {
+        Self::from_bytes(&std::fs::read(model)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut model = Self {
+            pieces: vec![],
+            model_type: ModelType::Unigram,
+            unk_id: 0,
+            byte_fallback: false,
+            precompiled_charsmap: vec![],
+            add_dummy_prefix: true,
+            remove_extra_whitespaces: true,
+        };
+
+        let mut reader = Reader::new(bytes);
+        while let Some((field, wire)) = reader.field()? {
+            match (field, wire) {
+                (1, 2) => model.pieces.push(piece(reader.bytes()?)?),
+                (2, 2) => model.trainer_spec(reader.bytes()?)?,
+                (3, 2) => model.normalizer_spec(reader.bytes()?)?,
+                _ => reader.skip(wire)?,
+            }
+        }
+        if model.pieces.is_empty() {
+            bail!("invalid sentencepiece model: no pieces");
+        }
+        if model.unk_id >= model.pieces.len() {
+            bail!(
+                "invalid sentencepiece model: unk_id {} out of range",
+                model.unk_id
+            );
+        }
+        Ok(model)
+    }
+
+    fn trainer_spec(&mut self, bytes: &[u8]) -> Result<()> {
+        let mut reader = Reader::new(bytes);
+        while let Some((field, wire)) = reader.field()? {
+            match (field, wire) {
+                (3, 0) => {
+                    self.model_type = match reader.varint()? {
+                        1 => ModelType::Unigram,
+                        2 => ModelType::Bpe,
+                        3 => ModelType::Word,
+                        4 => ModelType::Char,
+                        other => bail!("invalid sentencepiece model type {other}"),
+                    }
+                }
+                (35, 0) => self.byte_fallback = reader.varint()? != 0,
+                (40, 0) => self.unk_id = reader.varint()? as usize,
+                _ => reader.skip(wire)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn normalizer_spec(&mut self, bytes: &[u8]) -> Result<()> {
+        let mut reader = Reader::new(bytes);
+        while let Some((field, wire)) = reader.field()? {
+            match (field, wire) {
+                (2, 2) => self.precompiled_charsmap = reader.bytes()?.to_vec(),
+                (3, 0) => self.add_dummy_prefix = reader.varint()? != 0,
+                (4, 0) => self.remove_extra_whitespaces = reader.varint()? != 0,
+                _ => reader.skip(wire)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// 同 transformers 的 SpmConverter (Unigram) 與 LlamaConverter (BPE)
+    pub fn to_tokenizer(&self) -> Result<Tokenizer> {
+        let mut tokenizer = match self.model_type {
+            ModelType::Unigram => self.unigram()?,
+            ModelType::Bpe => self.bpe()?,
+            other => bail!("sentencepiece model type {other:?} not supported"),
+        };
+
+        // control 與 user defined 的 piece 需要完整比對，不經過 model 切割
+        for piece in &self.pieces {
+            match piece.kind {
+                PieceType::Control => {
+                    tokenizer.add_special_tokens(&[AddedToken::from(piece.piece.clone(), true)]);
+                }
+                PieceType::UserDefined => {
+                    tokenizer.add_tokens(&[AddedToken::from(piece.piece.clone(), false)]);
+                }
+                _ => {}
+            }
+        }
+        Ok(Tokenizer::from_hf(tokenizer))
+    }
+
+    fn unigram(&self) -> Result<HFTokenizer> {
+        let vocab = self
+            .pieces
+            .iter()
+            .map(|p| (p.piece.clone(), p.score as f64))
+            .collect();
+        let model = Unigram::from(vocab, Some(self.unk_id), self.byte_fallback)?;
+        let mut tokenizer = HFTokenizer::new(model);
+
+        let mut normalizers: Vec<NormalizerWrapper> = vec![];
+        if !self.precompiled_charsmap.is_empty() {
+            let Ok(precompiled) = Precompiled::from(&self.precompiled_charsmap) else {
+                bail!("invalid sentencepiece precompiled_charsmap");
+            };
+            normalizers.push(precompiled.into());
+        }
+        if self.remove_extra_whitespaces {
+            normalizers.push(Strip::new(true, true).into());
+            normalizers.push(Replace::new(ReplacePattern::Regex(" {2,}".into()), " ")?.into());
+        }
+        if !normalizers.is_empty() {
+            tokenizer.with_normalizer(Some(Sequence::new(normalizers)));
+        }
+
+        let scheme = match self.add_dummy_prefix {
+            true => PrependScheme::Always,
+            false => PrependScheme::Never,
+        };
+        tokenizer
+            .with_pre_tokenizer(Some(Metaspace::new(SPACE, scheme, true)))
+            .with_decoder(Some(Metaspace::new(SPACE, scheme, true)));
+        Ok(tokenizer)
+    }
+
+    fn bpe(&self) -> Result<HFTokenizer> {
+        let vocab: HashMap<String, u32> = self
+            .pieces
+            .iter()
+            .enumerate()
+            .map(|(id, p)| (p.piece.clone(), id as u32))
+            .collect();
+
+        // 由 piece 推算 merges：可以拆成兩個 piece 的組合，依合併後 piece 的分數排序
+        let mut merges = vec![];
+        for (id, piece) in self.pieces.iter().enumerate() {
+            if piece.kind != PieceType::Normal {
+                continue;
+            }
+            merges.extend(piece.piece.char_indices().skip(1).filter_map(|(i, _)| {
+                let (left, right) = piece.piece.split_at(i);
+                let ids = (*vocab.get(left)?, *vocab.get(right)?);
+                Some((piece.score, id, ids, left.to_string(), right.to_string()))
+            }));
+        }
+        merges.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+        let merges = merges.into_iter().map(|m| (m.3, m.4)).collect();
+
+        let model = BPE::builder()
+            .vocab_and_merges(vocab, merges)
+            .unk_token(self.pieces[self.unk_id].piece.clone())
+            .fuse_unk(true)
+            .byte_fallback(self.byte_fallback)
+            .build()?;
+        let mut tokenizer = HFTokenizer::new(model);
+
+        let mut normalizers: Vec<NormalizerWrapper> = vec![];
+        if self.add_dummy_prefix {
+            normalizers.push(Prepend::new(SPACE.to_string()).into());
+        }
+        normalizers.push(Replace::new(" ", SPACE.to_string())?.into());
+        tokenizer.with_normalizer(Some(Sequence::new(normalizers)));
+
+        let mut decoders: Vec<DecoderWrapper> = vec![
+            Replace::new(SPACE.to_string(), " ")?.into(),
+            ByteFallback::default().into(),
+            Fuse::default().into(),
+        ];
+        if self.add_dummy_prefix {
+            decoders.push(DecoderStrip::new(' ', 1, 0).into());
+        }
+        tokenizer.with_decoder(Some(DecoderSequence::new(decoders)));
+        Ok(tokenizer)
+    }
+}
+
+fn piece(bytes: &[u8]) -> Result<Piece> {
+    let mut piece = Piece {
+        piece: String::new(),
+        score: 0.,
+        kind: PieceType::Normal,
+    };
+    let mut reader = Reader::new(bytes);
+    while let Some((field, wire)) = reader.field()? {
+        match (field, wire) {
+            (1, 2) => match std::str::from_utf8(reader.bytes()?) {
+                Ok(s) => piece.piece = s.to_string(),
+                Err(_) => bail!("invalid sentencepiece model: piece is not utf-8"),
+            },
+            (2, 5) => piece.score = f32::from_le_bytes(reader.fixed32()?),
+            (3, 0) => {
+                piece.kind = match reader.varint()? {
+                    1 => PieceType::Normal,
+                    2 => PieceType::Unknown,
+                    3 => PieceType::Control,
+                    4 => PieceType::UserDefined,
+                    5 => PieceType::Unused,
+                    6 => PieceType::Byte,
+                    other => bail!("invalid sentencepiece piece type {other}"),
+                }
+            }
+            _ => reader.skip(wire)?,
+        }
+    }
+    Ok(piece)
+}
+
+// protobuf wire format，只支援需要的 wire type
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn field(&mut self) -> Result<Option<(u64, u8)>> {
+        if self.pos >= self.bytes.len() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        Ok(Some((key >> 3, (key & 7) as u8)))
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let Some(&byte) = self.bytes.get(self.pos) else {
+                bail!("invalid sentencepiece model: truncated varint");
+            };
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("invalid sentencepiece model: varint too long")
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.saturating_add(len);
+        if end > self.bytes.len() {
+            bail!("invalid sentencepiece model: truncated field");
+        }
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.varint()? as usize;
+        self.take(len)
+    }
+
+    fn fixed32(&mut self) -> Result<[u8; 4]> {
+        Ok(self.take(4)?.try_into().unwrap())
+    }
+
+    fn skip(&mut self, wire: u8) -> Result<()> {
+        match wire {
+            0 => {
+                self.varint()?;
+            }
+            1 => {
+                self.take(8)?;
+            }
+            2 => {
+                self.bytes()?;
+            }
+            5 => {
+                self.take(4)?;
+            }
+            other => bail!("invalid sentencepiece model: wire type {other}"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use crate::Result;
+use crate::generation::{Model, Step, StopReason, TextGeneration};
+use crate::tokenizers::Tokenizer;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// 寫入的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkFormat {
+    /// 直接寫入文字
+    Raw,
+    /// Server-Sent Events，每段文字為一個 `data:` frame，結束時寫入 `data: [DONE]`
+    Sse,
+}
+
+/// 合併多個 token 的文字再寫入，減少快速模型的 syscall 與 SSE frame 數量。
+/// 累積 tokens 段文字或超過 interval 時寫入，遇到句尾時立即寫入
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Coalesce {
+    pub tokens: usize,
+    pub interval: Option<Duration>,
+    /// 遇到句尾標點或換行時立即寫入
+    pub sentence_boundary: bool,
+}
+
+impl Coalesce {
+    pub fn new(tokens: usize) -> Self {
+        Self {
+            tokens: tokens.max(1),
+            interval: None,
+            sentence_boundary: true,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    pub fn with_sentence_boundary(mut self, sentence_boundary: bool) -> Self {
+        self.sentence_boundary = sentence_boundary;
+        self
+    }
+}
+
+fn is_sentence_end(text: &str) -> bool {
+    matches!(
+        text.trim_end_matches(' ').chars().last(),
+        Some('.' | '!' | '?' | '\n' | '。' | '！' | '？' | '…')
+    )
+}
+
+/// 將生成的文字寫到任何 `Write`，eg: stdout、檔案或 TCP socket
+pub struct TextSink<W: Write> {
+    writer: W,
+    format: SinkFormat,
+    flush: bool,
+    coalesce: Option<Coalesce>,
+    pending: String,
+    pending_tokens: usize,
+    last_write: Instant,
+}
+
+impl<W: Write> TextSink<W> {
+    /// 直接寫入文字，每段文字都 flush
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            format: SinkFormat::Raw,
+            flush: true,
+            coalesce: None,
+            pending: String::new(),
+            pending_tokens: 0,
+            last_write: Instant::now(),
+        }
+    }
+
+    /// 以 SSE frame 寫入
+    pub fn sse(writer: W) -> Self {
+        Self {
+            format: SinkFormat::Sse,
+            ..Self::new(writer)
+        }
+    }
+
+    /// 是否每段文字都 flush，寫到檔案時可以關閉
+    pub fn with_flush(mut self, flush: bool) -> Self {
+        self.flush = flush;
+        self
+    }
+
+    /// 合併文字後再寫入，eg: 每 4 個 token 或 50ms 寫入一次
+    pub fn with_coalesce(mut self, coalesce: Coalesce) -> Self {
+        self.coalesce = Some(coalesce);
+        self
+    }
+
+    /// 可以在每次 stream 前依 request 設定，None 為每段文字都立即寫入。
+    /// 會先寫入尚未寫入的文字
+    pub fn set_coalesce(&mut self, coalesce: Option<Coalesce>) -> Result<()> {
+        self.write_pending()?;
+        self.coalesce = coalesce;
+        Ok(())
+    }
+
+    /// 寫入文字，有設定 coalesce 時會先暫存。
+    /// writer 阻塞時 (eg: client 讀取較慢) 生成也會暫停
+    pub fn write_text(&mut self, text: &str) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        let Some(coalesce) = self.coalesce else {
+            return self.write_now(text);
+        };
+        self.pending.push_str(text);
+        self.pending_tokens += 1;
+        if self.pending_tokens >= coalesce.tokens
+            || coalesce
+                .interval
+                .is_some_and(|interval| self.last_write.elapsed() >= interval)
+            || (coalesce.sentence_boundary && is_sentence_end(&self.pending))
+        {
+            self.write_pending()?;
+        }
+        Ok(())
+    }
+
+    /// 寫入暫存的文字
+    pub fn write_pending(&mut self) -> Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        self.pending_tokens = 0;
+        self.write_now(&pending)
+    }
+
+    fn write_now(&mut self, text: &str) -> Result<()> {
+        self.last_write = Instant::now();
+        if text.is_empty() {
+            return Ok(());
+        }
+        match self.format {
+            SinkFormat::Raw => self.writer.write_all(text.as_bytes())?,
+            SinkFormat::Sse => self.write_event(None, text)?,
+        }
+        if self.flush {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// 寫入一個 SSE event，多行的資料拆成多個 `data:` 欄位
+    pub fn write_event(&mut self, event: Option<&str>, data: &str) -> Result<()> {
+        if let Some(event) = event {
+            writeln!(self.writer, "event: {event}")?;
+        }
+        for line in data.split('\n') {
+            writeln!(self.writer, "data: {line}")?;
+        }
+        writeln!(self.writer)?;
+        Ok(())
+    }
+
+    /// 結束串流，SSE 會寫入 `data: [DONE]`
+    pub fn finish(&mut self) -> Result<()> {
+        self.write_pending()?;
+        if self.format == SinkFormat::Sse {
+            self.write_event(None, "[DONE]")?;
+        }
+        Ok(self.writer.flush()?)
+    }
+
+    /// 生成直到結束，邊 decode 邊寫入，回傳停止的原因
+    pub fn stream<M: Model>(
+        &mut self,
+        generation: &mut TextGeneration<M>,
+        tokenizer: &mut Tokenizer,
+        ids: &[u32],
+        max_new_tokens: usize,
+    ) -> Result<StopReason> {
+        tokenizer.clear();
+        let mut step = generation.apply(ids, max_new_tokens)?;
+        let reason = loop {
+            match step {
+                Step::Token(token) => {
+                    if let Some(text) = tokenizer.next_token(token)? {
+                        self.write_text(&text)?;
+                    }
+                }
+                Step::Finished(reason) => break reason,
+            }
+            step = generation.next()?;
+        };
+        if let Some(text) = tokenizer.decode_rest()? {
+            self.write_text(&text)?;
+        }
+        self.finish()?;
+        Ok(reason)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
@@ -0,0 +1,200 @@
+use crate::Result;
+use crate::tokenizers::Tokenizer;
+use candle_core::Tensor;
+use std::time::{Duration, Instant};
+
+/// stopping criteria 可以看到的生成狀態，每生成一個 token 檢查一次
+#[derive(Debug, Clone, Copy)]
+pub struct StoppingContext<'a> {
+    /// prompt + 已生成的 token，最後一個是剛生成的 token
+    pub tokens: &'a [u32],
+    pub prompt_tokens: usize,
+    /// sampling 時使用的 logits (經過 transform)，F32、shape (vocab_size,)
+    pub logits: &'a Tensor,
+}
+
+impl StoppingContext<'_> {
+    pub fn generated_tokens(&self) -> &[u32] {
+        &self.tokens[self.prompt_tokens.min(self.tokens.len())..]
+    }
+
+    /// 剛生成的 token
+    pub fn last_token(&self) -> Option<u32> {
+        self.tokens.last().copied()
+    }
+}
+
+/// 決定是否停止生成，依加入 TextGeneration 的順序檢查，在 eos 檢查之後
+pub trait StoppingCriteria: Send {
+    fn should_stop(&mut self, ctx: &StoppingContext) -> Result<bool>;
+
+    /// 每次 apply 開始新的生成時呼叫
+    fn reset(&mut self) {}
+}
+
+impl<F> StoppingCriteria for F
+where
+    F: FnMut(&StoppingContext) -> Result<bool> + Send,
+{
+    fn should_stop(&mut self, ctx: &StoppingContext) -> Result<bool> {
+        self(ctx)
+    }
+}
+
+/// 生成指定的 token 時停止，eg: chat template 的 `<|im_end|>`
+#[derive(Debug, Clone)]
+pub struct StopTokens(pub Vec<u32>);
+
+impl StoppingCriteria for StopTokens {
+    fn should_stop(&mut self, ctx: &StoppingContext) -> Result<bool> {
+        Ok(ctx
+            .generated_tokens()
+            .last()
+            .is_some_and(|token| self.0.contains(token)))
+    }
+}
+
+/// 生成的文字包含指定字串時停止，同 OpenAI API 的 stop
+#[derive(Debug, Clone)]
+pub struct StopStrings {
+    tokenizer: Tokenizer,
+    stops: Vec<String>,
+}
+
+impl StopStrings {
+    pub fn new<S: Into<String>>(tokenizer: &Tokenizer, stops: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            tokenizer: tokenizer.clone(),
+            stops: stops
+                .into_iter()
+                .map(Into::into)
+                .filter(|s: &String| !s.is_empty())
+                .collect(),
+        }
+    }
+}
+
+impl StoppingCriteria for StopStrings {
+    fn should_stop(&mut self, ctx: &StoppingContext) -> Result<bool> {
+        if self.stops.is_empty() {
+            return Ok(false);
+        }
+        let text = self.tokenizer.decode(ctx.generated_tokens())?;
+        Ok(self.stops.iter().any(|stop| text.contains(stop.as_str())))
+    }
+}
+
+/// 從 apply 開始超過指定時間後停止
+#[derive(Debug, Clone)]
+pub struct MaxTime {
+    limit: Duration,
+    start: Instant,
+}
+
+impl MaxTime {
+    pub fn new(limit: Duration) -> Self {
+        Self {
+            limit,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl StoppingCriteria for MaxTime {
+    fn should_stop(&mut self, _ctx: &StoppingContext) -> Result<bool> {
+        Ok(self.start.elapsed() >= self.limit)
+    }
+
+    fn reset(&mut self) {
+        self.start = Instant::now();
+    }
+}
+
+/// 模型有把握時提早結束，適合偶爾會停不下來的擷取任務。
+///
+/// 連續 patience 步 eos 的機率超過 eos_threshold，或是生成超過 min_tokens 後連續
+/// patience 步 entropy (nats) 低於 entropy_threshold 時停止
+#[derive(Debug, Clone)]
+pub struct ConfidentStop {
+    eos_token_ids: Vec<u32>,
+    eos_threshold: f32,
+    entropy_threshold: Option<f32>,
+    min_tokens: usize,
+    patience: usize,
+    eos_steps: usize,
+    entropy_steps: usize,
+}
+
+impl ConfidentStop {
+    /// 預設 eos_threshold 為 0.95，patience 為 3，不檢查 entropy
+    pub fn new(eos_token_ids: Vec<u32>) -> Self {
+        Self {
+            eos_token_ids,
+            eos_threshold: 0.95,
+            entropy_threshold: None,
+            min_tokens: 0,
+            patience: 3,
+            eos_steps: 0,
+            entropy_steps: 0,
+        }
+    }
+
+    pub fn with_eos_threshold(mut self, threshold: f32) -> Self {
+        self.eos_threshold = threshold;
+        self
+    }
+
+    pub fn with_entropy_threshold(mut self, threshold: f32, min_tokens: usize) -> Self {
+        self.entropy_threshold = Some(threshold);
+        self.min_tokens = min_tokens;
+        self
+    }
+
+    pub fn with_patience(mut self, patience: usize) -> Self {
+        self.patience = patience.max(1);
+        self
+    }
+}
+
+impl StoppingCriteria for ConfidentStop {
+    fn should_stop(&mut self, ctx: &StoppingContext) -> Result<bool> {
+        let logits = ctx.logits.flatten_all()?.to_vec1::<f32>()?;
+        let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let exp = logits.iter().map(|v| (v - max).exp()).collect::<Vec<_>>();
+        let sum: f32 = exp.iter().sum();
+
+        let p_eos: f32 = self
+            .eos_token_ids
+            .iter()
+            .filter_map(|&id| exp.get(id as usize))
+            .map(|v| v / sum)
+            .sum();
+        self.eos_steps = if p_eos >= self.eos_threshold {
+            self.eos_steps + 1
+        } else {
+            0
+        };
+
+        if let Some(threshold) = self.entropy_threshold {
+            let entropy: f32 = exp
+                .iter()
+                .map(|v| v / sum)
+                .filter(|&p| p > 0.)
+                .map(|p| -p * p.ln())
+                .sum();
+            self.entropy_steps =
+                if ctx.generated_tokens().len() >= self.min_tokens && entropy < threshold {
+                    self.entropy_steps + 1
+                } else {
+                    0
+                };
+        }
+
+        Ok(self.eos_steps >= self.patience || self.entropy_steps >= self.patience)
+    }
+
+    fn reset(&mut self) {
+        self.eos_steps = 0;
+        self.entropy_steps = 0;
+    }
+}
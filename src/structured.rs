@@ -0,0 +1,109 @@
+use crate::{Result, bail};
+use serde::de::DeserializeOwned;
+
+/// 去掉 markdown code fence，eg: ```json ... ```，沒有 fence 時回傳原字串
+pub fn strip_code_fence(text: &str) -> &str {
+    let Some(start) = text.find("```") else {
+        return text;
+    };
+    let body = &text[start + 3..];
+    // 跳過語言標記，eg: json
+    let body = match body.find('\n') {
+        Some(i)
+            if body[..i]
+                .trim()
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-') =>
+        {
+            &body[i + 1..]
+        }
+        _ => body,
+    };
+    match body.find("```") {
+        Some(end) => &body[..end],
+        None => body,
+    }
+}
+
+/// 找出第一個合法的 JSON object 或 array，以括號配對找出範圍，並略過字串中的括號
+pub fn extract_json(text: &str) -> Option<&str> {
+    let text = strip_code_fence(text);
+    text.char_indices()
+        .filter(|(_, c)| *c == '{' || *c == '[')
+        .find_map(|(start, _)| {
+            let end = balanced_end(&text[start..])?;
+            let candidate = &text[start..start + end];
+            serde_json::from_str::<serde_json::Value>(candidate)
+                .is_ok()
+                .then_some(candidate)
+        })
+}
+
+// 回傳對應結尾括號之後的位置
+fn balanced_end(text: &str) -> Option<usize> {
+    let mut stack = vec![];
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => stack.push('}'),
+            '[' => stack.push(']'),
+            '}' | ']' => {
+                if stack.pop() != Some(c) {
+                    return None;
+                }
+                if stack.is_empty() {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// 從生成的文字中取出 JSON 並轉成 T
+pub fn parse_json<T: DeserializeOwned>(text: &str) -> Result<T> {
+    let Some(json) = extract_json(text) else {
+        bail!("no JSON found in output")
+    };
+    Ok(serde_json::from_str(json)?)
+}
+
+/// 解析失敗時，把上次的輸出與錯誤附加到 prompt 後面再生成一次
+pub fn retry_prompt(prompt: &str, output: &str, err: &crate::Error) -> String {
+    format!(
+        "{prompt}\n{output}\n\nThe output above is invalid: {err}\nRespond again with valid JSON only.\n"
+    )
+}
+
+/// 以 `generate` 生成並解析成 T，最多重試 `max_retries` 次
+pub fn generate_json<T, F>(prompt: &str, max_retries: usize, mut generate: F) -> Result<T>
+where
+    T: DeserializeOwned,
+    F: FnMut(&str) -> Result<String>,
+{
+    let mut prompt = prompt.to_string();
+    let mut attempt = 0;
+    loop {
+        let output = generate(&prompt)?;
+        match parse_json(&output) {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < max_retries => {
+                prompt = retry_prompt(&prompt, &output, &err);
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
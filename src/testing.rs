@@ -0,0 +1,180 @@
+use crate::generation::Model;
+use crate::repo::{Repo, found, load_safetensors};
+use crate::{Result, bail};
+use candle_core::Tensor;
+use std::path::PathBuf;
+
+/// 依腳本回傳 logits 的模型，用於測試生成流程，不需要下載模型
+#[derive(Debug, Clone)]
+pub struct MockModel {
+    script: Vec<Vec<f32>>,
+    step: usize,
+    calls: Vec<(Vec<u32>, usize)>,
+    // 模擬 kv cache 中的 token
+    cache: Vec<u32>,
+}
+
+impl MockModel {
+    /// 每一次 forward 依序回傳 script 中的 logits
+    pub fn new(script: Vec<Vec<f32>>) -> Self {
+        Self {
+            script,
+            step: 0,
+            calls: vec![],
+            cache: vec![],
+        }
+    }
+
+    /// 依序強制生成 tokens (one-hot logits)
+    pub fn from_tokens(vocab_size: usize, tokens: &[u32]) -> Self {
+        let script = tokens
+            .iter()
+            .map(|&t| {
+                let mut logits = vec![f32::NEG_INFINITY; vocab_size];
+                logits[t as usize] = 0.;
+                logits
+            })
+            .collect();
+        Self::new(script)
+    }
+
+    /// 目前 kv cache 中的 token
+    pub fn cache(&self) -> &[u32] {
+        &self.cache
+    }
+
+    /// 每次 forward 的輸入與 start_pos
+    pub fn calls(&self) -> &[(Vec<u32>, usize)] {
+        &self.calls
+    }
+}
+
+impl Model for MockModel {
+    fn forward(&mut self, x: &Tensor, start_pos: usize) -> Result<Tensor> {
+        // batch 時只記錄第一列
+        let ids = match x.rank() {
+            2 => x.get(0)?.to_vec1::<u32>()?,
+            _ => x.flatten_all()?.to_vec1::<u32>()?,
+        };
+        if start_pos != self.cache.len() {
+            bail!(
+                "mock model start_pos {start_pos} does not match kv cache length {}",
+                self.cache.len()
+            );
+        }
+        self.cache.extend_from_slice(&ids);
+        self.calls.push((ids, start_pos));
+        let Some(logits) = self.script.get(self.step) else {
+            bail!("mock model script exhausted after {} steps", self.step)
+        };
+        self.step += 1;
+        Ok(Tensor::new(logits.as_slice(), x.device())?
+            .unsqueeze(0)?
+            .unsqueeze(0)?)
+    }
+
+    /// 清除 kv cache 代表重新開始，腳本也從頭開始
+    fn reset(&mut self) {
+        self.step = 0;
+        self.cache.clear();
+    }
+
+    fn kv_cache_len(&self) -> Option<usize> {
+        Some(self.cache.len())
+    }
+
+    // 以 u32 token 計算
+    fn kv_memory_bytes(&self) -> Option<usize> {
+        Some(self.cache.len() * std::mem::size_of::<u32>())
+    }
+
+    fn trim_to(&mut self, len: usize) -> Result<bool> {
+        self.cache.truncate(len);
+        Ok(true)
+    }
+}
+
+/// 記憶體中的 repo。Repo 回傳的是檔案路徑，所以檔案會寫到暫存目錄，drop 時刪除
+pub struct FakeRepo {
+    model_id: String,
+    path: PathBuf,
+}
+
+impl FakeRepo {
+    pub fn new(model_id: &str) -> Result<Self> {
+        Ok(Self {
+            model_id: model_id.to_string(),
+            path: crate::utils::unique_temp_dir("fake-repo")?,
+        })
+    }
+
+    pub fn with_file<C: AsRef<[u8]>>(self, filename: &str, content: C) -> Result<Self> {
+        self.add_file(filename, content)?;
+        Ok(self)
+    }
+
+    pub fn add_file<C: AsRef<[u8]>>(&self, filename: &str, content: C) -> Result<()> {
+        let file = self.path.join(filename);
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(std::fs::write(file, content)?)
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+impl Drop for FakeRepo {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+impl Repo for FakeRepo {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn get(&self, filename: &str) -> Result<PathBuf> {
+        let file = self.path.join(filename);
+        if !file.exists() {
+            return Err(crate::Error::FileNotFound {
+                model_id: self.model_id.clone(),
+                file: filename.to_string(),
+            });
+        }
+        Ok(file)
+    }
+
+    fn tokenizer_config_file(&self) -> Result<PathBuf> {
+        self.get("tokenizer_config.json")
+    }
+
+    fn tokenizer_file(&self) -> Result<PathBuf> {
+        self.get("tokenizer.json")
+    }
+
+    fn config_file(&self) -> Result<PathBuf> {
+        self.get("config.json")
+    }
+
+    fn safetensors_files(&self) -> Result<Vec<PathBuf>> {
+        if let Some(file) = found(self.get("model.safetensors"))? {
+            return Ok(vec![file]);
+        }
+        load_safetensors(
+            self.path.as_path(),
+            self.get("model.safetensors.index.json")?.as_path(),
+        )
+    }
+
+    fn pytorch_model_file(&self) -> Result<PathBuf> {
+        self.get("pytorch_model.bin")
+    }
+
+    fn generate_config_file(&self) -> Result<PathBuf> {
+        self.get("generation_config.json")
+    }
+}
@@ -1,20 +1,420 @@
-use crate::{Result, bail, repo::Repo};
-use std::{path::Path, sync::Arc};
+use crate::chunking::{ceil_char_boundary, floor_char_boundary};
+use crate::generation::GenerationConfig;
+use crate::repo::{Repo, found};
+use crate::{Result, bail};
+use candle_core::{Device, Tensor};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::{fs::File, ops::Range, path::Path};
 use tokenizers::Tokenizer as HFTokenizer;
+use tokenizers::models::bpe::BPE;
+use tokenizers::pre_tokenizers::byte_level::ByteLevel;
+use tokenizers::{
+    AddedToken, Encoding, PaddingDirection, PaddingParams, TruncationDirection, TruncationParams,
+};
+
+/// special_tokens_map.json、added_tokens.json 與 tokenizer_config.json 中的特殊 token
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpecialTokens {
+    pub bos_token: Option<String>,
+    pub eos_token: Option<String>,
+    pub pad_token: Option<String>,
+    pub unk_token: Option<String>,
+    pub additional_special_tokens: Vec<String>,
+    /// added_tokens.json: token -> id
+    pub added_tokens: HashMap<String, u32>,
+}
+
+impl SpecialTokens {
+    pub fn from_pretrained<R: Repo>(repo: &R) -> Result<Self> {
+        repo.special_tokens()
+    }
+
+    /// 依序讀取檔案，前面的檔案優先，不存在的檔案會略過
+    pub fn from_files<P: AsRef<Path>>(files: &[P]) -> Result<Self> {
+        let mut tokens = Self::default();
+        for file in files.iter().map(AsRef::as_ref).filter(|p| p.exists()) {
+            let json: Value = serde_json::from_reader(File::open(file)?)?;
+            if file.file_name().is_some_and(|n| n == "added_tokens.json") {
+                tokens.merge_added_tokens(&json);
+            } else {
+                tokens.merge_map(&json);
+            }
+        }
+        Ok(tokens)
+    }
+
+    fn merge_map(&mut self, json: &Value) {
+        let token = |key: &str| json.get(key).and_then(token_content);
+        self.bos_token = self.bos_token.take().or_else(|| token("bos_token"));
+        self.eos_token = self.eos_token.take().or_else(|| token("eos_token"));
+        self.pad_token = self.pad_token.take().or_else(|| token("pad_token"));
+        self.unk_token = self.unk_token.take().or_else(|| token("unk_token"));
+
+        if let Some(Value::Array(list)) = json.get("additional_special_tokens") {
+            for token in list.iter().filter_map(token_content) {
+                if !self.additional_special_tokens.contains(&token) {
+                    self.additional_special_tokens.push(token);
+                }
+            }
+        }
+    }
+
+    fn merge_added_tokens(&mut self, json: &Value) {
+        if let Some(map) = json.as_object() {
+            for (token, id) in map {
+                if let Some(id) = id.as_u64() {
+                    self.added_tokens.entry(token.clone()).or_insert(id as u32);
+                }
+            }
+        }
+    }
+
+    /// 給 chat template 使用的變數，eg: bos_token, eos_token
+    pub fn template_globals(&self) -> HashMap<&'static str, String> {
+        [
+            ("bos_token", &self.bos_token),
+            ("eos_token", &self.eos_token),
+            ("pad_token", &self.pad_token),
+            ("unk_token", &self.unk_token),
+        ]
+        .into_iter()
+        .filter_map(|(k, v)| v.clone().map(|v| (k, v)))
+        .collect()
+    }
+}
+
+// special_tokens_map.json 的值可能是字串或是 {"content": "..."}
+fn token_content(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => Some(s.clone()),
+        Value::Object(o) => o.get("content").and_then(Value::as_str).map(str::to_string),
+        _ => None,
+    }
+}
+
+/// tokenizer_config.json 中的長度與 padding 設定
+#[derive(Debug, Clone)]
+pub struct TokenizerConfig {
+    /// 超過時截斷，None 代表不限制
+    pub model_max_length: Option<usize>,
+    pub padding_side: PaddingDirection,
+    pub truncation_side: TruncationDirection,
+}
+
+impl Default for TokenizerConfig {
+    fn default() -> Self {
+        Self {
+            model_max_length: None,
+            padding_side: PaddingDirection::Right,
+            truncation_side: TruncationDirection::Right,
+        }
+    }
+}
+
+impl TokenizerConfig {
+    pub fn from_file<P: AsRef<Path>>(tokenizer_config: P) -> Result<Self> {
+        let json: Value = serde_json::from_reader(File::open(tokenizer_config)?)?;
+        let side = |key: &str| json.get(key).and_then(Value::as_str);
+        Ok(Self {
+            // transformers 沒有設定時使用 int(1e30) 代表不限制
+            model_max_length: json
+                .get("model_max_length")
+                .and_then(Value::as_f64)
+                .filter(|&n| n > 0. && n < 1e18)
+                .map(|n| n as usize),
+            padding_side: match side("padding_side") {
+                Some("left") => PaddingDirection::Left,
+                _ => PaddingDirection::Right,
+            },
+            truncation_side: match side("truncation_side") {
+                Some("left") => TruncationDirection::Left,
+                _ => TruncationDirection::Right,
+            },
+        })
+    }
+}
+
+/// `Tokenizer::tokenize` 的結果
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TokenInfo {
+    pub id: u32,
+    /// vocab 中的原始字串，eg: byte-level BPE 的 `Ġhello`
+    pub token: String,
+    /// 對應的原文
+    pub text: String,
+    /// 在原文中的 byte 範圍，組成同一個字元的 token 範圍相同
+    pub bytes: Range<usize>,
+}
+
+/// token healing 的結果，見 `Tokenizer::token_healing`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenHealing {
+    /// 去掉最後一個 token 的 prompt
+    pub ids: Vec<u32>,
+    /// 被去掉的 token (vocab 中的原始字串)
+    pub prefix: String,
+    /// 第一個生成的 token 只能從這些 token 中選擇
+    pub allowed: Vec<u32>,
+}
+
+impl TokenHealing {
+    pub fn constraint(&self) -> crate::logits::TokenHealingConstraint {
+        crate::logits::TokenHealingConstraint::new(self.allowed.clone())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Tokenizer {
     tokenizer: Arc<HFTokenizer>,
+    // 不截斷的 tokenizer，第一次使用時建立，設定改變時清除
+    untruncated: OnceLock<Arc<HFTokenizer>>,
+    special_tokens: SpecialTokens,
     tokens: Vec<u32>,
     prev_index: usize,
     current_index: usize,
+    // 每個 token 在串流輸出文字中的 byte 範圍，只記錄已輸出的 token
+    spans: Vec<Range<usize>>,
+    text_len: usize,
 }
 
 impl Tokenizer {
+    pub fn from_hf(tokenizer: HFTokenizer) -> Self {
+        Self {
+            tokenizer: Arc::new(tokenizer),
+            untruncated: OnceLock::new(),
+            special_tokens: SpecialTokens::default(),
+            tokens: Vec::new(),
+            prev_index: 0,
+            current_index: 0,
+            spans: Vec::new(),
+            text_len: 0,
+        }
+    }
+
+    pub fn with_special_tokens(mut self, special_tokens: SpecialTokens) -> Self {
+        self.special_tokens = special_tokens;
+        self
+    }
+
+    /// 依 config 設定截斷，有 pad token 時 encode_batch 會 pad 到最長的長度
+    pub fn with_config(mut self, config: &TokenizerConfig) -> Result<Self> {
+        self.set_max_length(config.model_max_length, config.truncation_side)?;
+        self.set_padding_side(config.padding_side);
+        Ok(self)
+    }
+
+    /// 覆寫截斷長度，None 代表不截斷
+    pub fn set_max_length(
+        &mut self,
+        max_length: Option<usize>,
+        direction: TruncationDirection,
+    ) -> Result<()> {
+        let truncation = max_length.map(|max_length| TruncationParams {
+            max_length,
+            direction,
+            ..Default::default()
+        });
+        Arc::make_mut(&mut self.tokenizer).with_truncation(truncation)?;
+        self.untruncated = OnceLock::new();
+        Ok(())
+    }
+
+    /// 覆寫 padding 的方向，沒有 pad token 時不 pad
+    pub fn set_padding_side(&mut self, direction: PaddingDirection) {
+        let padding = self.pad_token_id().map(|pad_id| PaddingParams {
+            direction,
+            pad_id,
+            pad_token: self.special_tokens.pad_token.clone().unwrap_or_default(),
+            ..Default::default()
+        });
+        Arc::make_mut(&mut self.tokenizer).with_padding(padding);
+        self.untruncated = OnceLock::new();
+    }
+
+    /// 指定 pad token，已經設定 padding 時沿用原本的方向，否則從右邊 pad
+    pub fn set_pad_token(&mut self, token: &str) -> Result<()> {
+        if self.special_token_id(&Some(token.to_string())).is_none() {
+            bail!("pad token {token} not in vocabulary");
+        }
+        self.special_tokens.pad_token = Some(token.to_string());
+        let direction = self
+            .tokenizer
+            .get_padding()
+            .map_or(PaddingDirection::Right, |p| p.direction);
+        self.set_padding_side(direction);
+        Ok(())
+    }
+
+    /// 決定 pad token：依序為 generation config 的 pad_token_id、tokenizer 的 pad_token、
+    /// generation config 的 eos_token_id 與 tokenizer 的 eos_token
+    pub fn resolve_pad_token_id(&self, config: &GenerationConfig) -> Result<u32> {
+        config
+            .pad_token_id
+            .or_else(|| self.pad_token_id())
+            .or_else(|| config.get_eos_token_id()?.first().copied())
+            .or_else(|| self.eos_token_id())
+            .ok_or(crate::Error::PadTokenNotFound)
+    }
+
+    pub fn max_length(&self) -> Option<usize> {
+        self.tokenizer.get_truncation().map(|t| t.max_length)
+    }
+
+    pub fn special_tokens(&self) -> &SpecialTokens {
+        &self.special_tokens
+    }
+
+    fn special_token_id(&self, token: &Option<String>) -> Option<u32> {
+        let token = token.as_deref()?;
+        self.tokenizer
+            .token_to_id(token)
+            .or_else(|| self.special_tokens.added_tokens.get(token).copied())
+    }
+
+    pub fn bos_token_id(&self) -> Option<u32> {
+        self.special_token_id(&self.special_tokens.bos_token)
+    }
+
+    pub fn eos_token_id(&self) -> Option<u32> {
+        self.special_token_id(&self.special_tokens.eos_token)
+    }
+
+    pub fn pad_token_id(&self) -> Option<u32> {
+        self.special_token_id(&self.special_tokens.pad_token)
+    }
+
+    pub fn unk_token_id(&self) -> Option<u32> {
+        self.special_token_id(&self.special_tokens.unk_token)
+    }
+
+    // 加入 tokenizer_config.json 中 added_tokens_decoder 定義的 token，eg: <|im_start|>
+    fn add_config_tokens<P: AsRef<Path>>(&mut self, tokenizer_config: P) -> Result<()> {
+        let config: serde_json::Value = serde_json::from_reader(File::open(tokenizer_config)?)?;
+        let Some(added) = config
+            .get("added_tokens_decoder")
+            .and_then(|v| v.as_object())
+        else {
+            return Ok(());
+        };
+
+        let mut added: Vec<(u32, AddedToken)> = added
+            .iter()
+            .filter_map(|(id, token)| {
+                let id = id.parse().ok()?;
+                let content = token.get("content")?.as_str()?;
+                let special = token
+                    .get("special")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                Some((id, AddedToken::from(content, special)))
+            })
+            .collect();
+        added.sort_by_key(|(id, _)| *id);
+
+        self.untruncated = OnceLock::new();
+        let tokenizer = Arc::make_mut(&mut self.tokenizer);
+        for (id, token) in added {
+            let size = tokenizer.get_vocab_size(true) as u32;
+            if tokenizer.token_to_id(&token.content).is_none() && id != size {
+                bail!("added token {} expects id {id}, got {size}", token.content);
+            }
+            if token.special {
+                tokenizer.add_special_tokens(&[token]);
+            } else {
+                tokenizer.add_tokens(&[token]);
+            }
+        }
+        Ok(())
+    }
+
     pub fn tokenizer(&self) -> &HFTokenizer {
         &self.tokenizer
     }
 
+    /// encode 時不依 model_max_length 截斷，用於整份文件或計算 token 數。
+    /// 不截斷的 tokenizer 只在第一次呼叫時複製一次
+    pub fn encode_untruncated(&self, text: &str, add_special_tokens: bool) -> Result<Encoding> {
+        let tokenizer = match self.max_length() {
+            None => &self.tokenizer,
+            Some(_) => self.untruncated.get_or_init(|| {
+                let mut tokenizer = self.tokenizer.as_ref().clone();
+                // 沒有 truncation 時不會失敗
+                let _ = tokenizer.with_truncation(None);
+                Arc::new(tokenizer)
+            }),
+        };
+        match tokenizer.encode(text, add_special_tokens) {
+            Ok(encoding) => Ok(encoding),
+            Err(err) => bail!("cannot encode: {err}"),
+        }
+    }
+
+    /// 將文字拆成 token，不加入 bos 等特殊 token 也不截斷，用來計算 token 數或標示 token
+    pub fn tokenize(&self, text: &str) -> Result<Vec<TokenInfo>> {
+        let encoding = self.encode_untruncated(text, false)?;
+        Ok(encoding
+            .get_ids()
+            .iter()
+            .zip(encoding.get_tokens())
+            .zip(encoding.get_offsets())
+            .map(|((&id, token), &(start, end))| {
+                // byte-level 的 token 可能只包含半個字，調整到字元邊界
+                let bytes = floor_char_boundary(text, start)..ceil_char_boundary(text, end);
+                TokenInfo {
+                    id,
+                    token: token.clone(),
+                    text: text[bytes.clone()].to_string(),
+                    bytes,
+                }
+            })
+            .collect())
+    }
+
+    /// 批次 encode，結果的順序與 texts 相同，有設定 padding 時 pad 到最長的長度。
+    /// 開啟 parallel feature 時以 rayon 的 thread pool 平行處理，可以用 `ThreadPool::install` 控制執行緒數
+    pub fn encode_batch<S: AsRef<str> + Sync>(
+        &self,
+        texts: &[S],
+        add_special_tokens: bool,
+    ) -> Result<Vec<Encoding>> {
+        let encode = |text: &S| self.tokenizer.encode(text.as_ref(), add_special_tokens);
+        #[cfg(feature = "parallel")]
+        let encodings = {
+            use rayon::prelude::*;
+            texts
+                .par_iter()
+                .map(encode)
+                .collect::<tokenizers::Result<Vec<_>>>()
+        };
+        #[cfg(not(feature = "parallel"))]
+        let encodings = texts
+            .iter()
+            .map(encode)
+            .collect::<tokenizers::Result<Vec<_>>>();
+
+        let mut encodings = match encodings {
+            Ok(encodings) => encodings,
+            Err(err) => bail!("cannot encode: {err}"),
+        };
+        if let Some(padding) = self.tokenizer.get_padding()
+            && let Err(err) = tokenizers::pad_encodings(&mut encodings, padding)
+        {
+            bail!("cannot pad: {err}");
+        }
+        Ok(encodings)
+    }
+
+    /// decode 並保留特殊 token，同 tokenize 的反向
+    pub fn detokenize(&self, ids: &[u32]) -> Result<String> {
+        match self.tokenizer.decode(ids, false) {
+            Ok(str) => Ok(str),
+            Err(err) => bail!("cannot decode: {err}"),
+        }
+    }
+
     pub fn decode(&self, tokens: &[u32]) -> Result<String> {
         match self.tokenizer.decode(tokens, true) {
             Ok(str) => Ok(str),
@@ -22,37 +422,55 @@ impl Tokenizer {
         }
     }
 
-    // https://github.com/huggingface/text-generation-inference/blob/5ba53d44a18983a4de32d122f4cb46f4a17d9ef6/server/text_generation_server/models/model.py#L68
+    // 已輸出的部份 (prev_index..current_index) 與目前所有未確定 token 的 decode 結果
+    fn pending_text(&self) -> Result<(String, String)> {
+        let prev_text = self.decode(&self.tokens[self.prev_index..self.current_index])?;
+        let text = self.decode(&self.tokens[self.prev_index..])?;
+        Ok((prev_text, text))
+    }
+
+    /// 逐一 token decode，回傳新增的文字。
+    ///
+    /// byte-fallback 的 token (eg: `<0xE4>`) 還沒組成完整的 UTF-8 字元時會 decode 成
+    /// U+FFFD，這時先累積 token，等字元完整後再一起輸出。
+    /// https://github.com/huggingface/text-generation-inference/blob/5ba53d44a18983a4de32d122f4cb46f4a17d9ef6/server/text_generation_server/models/model.py#L68
     pub fn next_token(&mut self, token: u32) -> Result<Option<String>> {
-        let prev_text = if self.tokens.is_empty() {
-            String::new()
-        } else {
-            let tokens = &self.tokens[self.prev_index..self.current_index];
-            self.decode(tokens)?
-        };
         self.tokens.push(token);
-        let text = self.decode(&self.tokens[self.prev_index..])?;
-        if text.len() > prev_text.len() && text.chars().last().unwrap().is_alphanumeric() {
-            let text = text.split_at(prev_text.len());
-            self.prev_index = self.current_index;
-            self.current_index = self.tokens.len();
-            Ok(Some(text.1.to_string()))
-        } else {
-            Ok(None)
+        let (prev_text, text) = self.pending_text()?;
+        if text.len() <= prev_text.len() || text.ends_with(char::REPLACEMENT_CHARACTER) {
+            return Ok(None);
         }
+        self.prev_index = self.current_index;
+        self.current_index = self.tokens.len();
+        let text = new_text(&prev_text, &text).to_string();
+        self.push_spans(text.len());
+        Ok(Some(text))
     }
 
+    // 一起輸出的 token (eg: 組成同一個字元的 byte-fallback token) 共用同一個範圍
+    fn push_spans(&mut self, len: usize) {
+        let span = self.text_len..self.text_len + len;
+        self.spans.resize(self.current_index, span);
+        self.text_len += len;
+    }
+
+    /// 每個 token 在 next_token 與 decode_rest 輸出串接而成的文字中的 byte 範圍，
+    /// 可以用來標示 token、解析不完整的 JSON 或是在 stop string 處截斷，不需要重新 tokenize。
+    ///
+    /// 組成同一段輸出的多個 token 共用同一個範圍；不產生文字的 token 範圍為空
+    pub fn spans(&self) -> Result<Vec<Range<usize>>> {
+        let mut spans = self.spans.clone();
+        let rest = self.decode_rest()?.map_or(0, |text| text.len());
+        let span = self.text_len..self.text_len + rest;
+        spans.resize(self.tokens.len(), span);
+        Ok(spans)
+    }
+
+    /// 輸出剩下還沒輸出的文字，包含不完整的 UTF-8
     pub fn decode_rest(&self) -> Result<Option<String>> {
-        let prev_text = if self.tokens.is_empty() {
-            String::new()
-        } else {
-            let tokens = &self.tokens[self.prev_index..self.current_index];
-            self.decode(tokens)?
-        };
-        let text = self.decode(&self.tokens[self.prev_index..])?;
+        let (prev_text, text) = self.pending_text()?;
         if text.len() > prev_text.len() {
-            let text = text.split_at(prev_text.len());
-            Ok(Some(text.1.to_string()))
+            Ok(Some(new_text(&prev_text, &text).to_string()))
         } else {
             Ok(None)
         }
@@ -62,6 +480,33 @@ impl Tokenizer {
         self.decode(&self.tokens)
     }
 
+    /// token healing: 去掉 prompt 的最後一個 token，並限制第一個生成的 token 必須以該
+    /// token 的字串開頭，避免因為 tokenization 邊界而從字的中間開始生成。
+    /// 生成的文字會包含被去掉的部份。最後一個 token 是特殊 token 時回傳 None
+    pub fn token_healing(&self, ids: &[u32]) -> Option<TokenHealing> {
+        let (&last, rest) = ids.split_last()?;
+        let prefix = self.tokenizer.id_to_token(last)?;
+        let added = self.tokenizer.get_added_tokens_decoder();
+        if added.contains_key(&last) || prefix.is_empty() {
+            return None;
+        }
+
+        let mut allowed: Vec<u32> = self
+            .tokenizer
+            .get_vocab(false)
+            .into_iter()
+            .filter(|(token, id)| token.starts_with(&prefix) && !added.contains_key(id))
+            .map(|(_, id)| id)
+            .collect();
+        allowed.sort_unstable();
+
+        Some(TokenHealing {
+            ids: rest.to_vec(),
+            prefix,
+            allowed,
+        })
+    }
+
     pub fn get_token(&self, token_s: &str) -> Option<u32> {
         self.tokenizer.get_vocab(true).get(token_s).copied()
     }
@@ -70,22 +515,132 @@ impl Tokenizer {
         self.tokens.clear();
         self.prev_index = 0;
         self.current_index = 0;
+        self.spans.clear();
+        self.text_len = 0;
+    }
+}
+
+/// pad 之後的 batch，用於批次生成或 embedding
+#[derive(Debug, Clone)]
+pub struct PaddedBatch {
+    /// U32，shape (batch, max_len)
+    pub input_ids: Tensor,
+    /// U8，shape (batch, max_len)，1 為 token、0 為 pad
+    pub attention_mask: Tensor,
+}
+
+/// 將不同長度的 token 序列 pad 到相同長度。生成時使用 Left，讓每個序列的最後一個 token 對齊
+pub fn pad_batch(
+    sequences: &[Vec<u32>],
+    pad_token_id: u32,
+    direction: PaddingDirection,
+    device: &Device,
+) -> Result<PaddedBatch> {
+    let max_len = sequences.iter().map(Vec::len).max().unwrap_or(0);
+    let mut ids = Vec::with_capacity(sequences.len() * max_len);
+    let mut mask = Vec::with_capacity(sequences.len() * max_len);
+    for seq in sequences {
+        let pad = max_len - seq.len();
+        let padding = std::iter::repeat_n(pad_token_id, pad);
+        match direction {
+            PaddingDirection::Left => {
+                ids.extend(padding.chain(seq.iter().copied()));
+                mask.extend(std::iter::repeat_n(0u8, pad).chain(std::iter::repeat_n(1, seq.len())));
+            }
+            PaddingDirection::Right => {
+                ids.extend(seq.iter().copied().chain(padding));
+                mask.extend(std::iter::repeat_n(1u8, seq.len()).chain(std::iter::repeat_n(0, pad)));
+            }
+        }
     }
+    let shape = (sequences.len(), max_len);
+    Ok(PaddedBatch {
+        input_ids: Tensor::from_vec(ids, shape, device)?,
+        attention_mask: Tensor::from_vec(mask, shape, device)?,
+    })
 }
 
+// text 中 prev_text 之後的部份。normalizer 可能讓 prev_text 不是 text 的前綴，
+// 這時從不超過 prev_text 長度的字元邊界切開，避免切在字元中間
+fn new_text<'a>(prev_text: &str, text: &'a str) -> &'a str {
+    if let Some(rest) = text.strip_prefix(prev_text) {
+        return rest;
+    }
+    let mut index = prev_text.len().min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    &text[index..]
+}
+
+/// 優先使用 tokenizer.json，沒有的話改用 vocab.json + merges.txt 建立 BPE tokenizer，
+/// 或由 sentencepiece 的 tokenizer.model 轉換。
+///
+/// 依 tokenizer_config.json 的 model_max_length、padding_side 與 truncation_side 設定，
+/// 可以用 `set_max_length` 與 `set_padding_side` 覆寫
 pub fn from_pretrained<R: Repo>(repo: &R) -> Result<Tokenizer> {
-    let tokenizer = repo.tokenizer_file()?;
-    from_file(tokenizer)
+    let special_tokens = repo.special_tokens()?;
+    let tokenizer = load_pretrained(repo)?.with_special_tokens(special_tokens);
+    match found(repo.tokenizer_config_file())? {
+        Some(file) if file.exists() => tokenizer.with_config(&TokenizerConfig::from_file(file)?),
+        _ => Ok(tokenizer),
+    }
+}
+
+fn load_pretrained<R: Repo>(repo: &R) -> Result<Tokenizer> {
+    // 只有檔案不存在才改用其他格式，網路或權限錯誤直接回傳
+    if let Some(tokenizer) = found(repo.tokenizer_file())?.filter(|p| p.exists()) {
+        return from_file(tokenizer);
+    }
+
+    let exists = |filename: &str| found(repo.get(filename)).map(|p| p.filter(|p| p.exists()));
+
+    if let (Some(vocab), Some(merges)) = (exists("vocab.json")?, exists("merges.txt")?) {
+        let mut tokenizer = from_bpe_files(vocab, merges)?;
+        if let Some(tokenizer_config) = exists("tokenizer_config.json")? {
+            tokenizer.add_config_tokens(tokenizer_config)?;
+        }
+        return Ok(tokenizer);
+    }
+
+    if let Some(model) = exists("tokenizer.model")? {
+        let mut tokenizer = match from_sentencepiece(model) {
+            Ok(tokenizer) => tokenizer,
+            Err(err) => bail!("{}: tokenizer.model: {err}", repo.model_id()),
+        };
+        if let Some(tokenizer_config) = exists("tokenizer_config.json")? {
+            tokenizer.add_config_tokens(tokenizer_config)?;
+        }
+        return Ok(tokenizer);
+    }
+
+    Err(crate::Error::TokenizerNotFound {
+        model_id: repo.model_id().to_string(),
+    })
 }
 
 pub fn from_file<P: AsRef<Path>>(tokenizer: P) -> Result<Tokenizer> {
     let tokenizer = HFTokenizer::from_file(tokenizer)?;
-    Ok(Tokenizer {
-        tokenizer: Arc::new(tokenizer),
-        tokens: Vec::new(),
-        prev_index: 0,
-        current_index: 0,
-    })
+    Ok(Tokenizer::from_hf(tokenizer))
+}
+
+/// 由 sentencepiece 的 tokenizer.model 建立 tokenizer，支援 Unigram 與 BPE
+pub fn from_sentencepiece<P: AsRef<Path>>(model: P) -> Result<Tokenizer> {
+    crate::sentencepiece::SentencePieceModel::from_file(model)?.to_tokenizer()
+}
+
+/// 由 GPT-2 風格的 vocab.json 與 merges.txt 建立 byte-level BPE tokenizer
+pub fn from_bpe_files<P: AsRef<Path>>(vocab: P, merges: P) -> Result<Tokenizer> {
+    let (Some(vocab), Some(merges)) = (vocab.as_ref().to_str(), merges.as_ref().to_str()) else {
+        bail!("invalid vocab/merges path")
+    };
+
+    let bpe = BPE::from_file(vocab, merges).build()?;
+    let mut tokenizer = HFTokenizer::new(bpe);
+    tokenizer
+        .with_pre_tokenizer(Some(ByteLevel::default().add_prefix_space(false)))
+        .with_decoder(Some(ByteLevel::default()));
+    Ok(Tokenizer::from_hf(tokenizer))
 }
 
 // fn from_files<'s, P: AsRef<Path>>(
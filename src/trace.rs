@@ -0,0 +1,179 @@
+use crate::generation::{GeneratedSequence, Model, TextGeneration};
+use crate::logits::top_logprobs;
+use crate::{Result, bail};
+use candle_core::{DType, Device, Tensor};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// 生成過程的紀錄，記錄的是模型輸出的原始 logits (repetition penalty 與 transform 之前)
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Trace {
+    pub seed: u64,
+    pub top_k: usize,
+    pub input_ids: Vec<u32>,
+    pub steps: Vec<TraceStep>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    /// 選到的 token
+    pub token: u32,
+    /// (token id, log probability)，由高到低
+    pub top_logprobs: Vec<(u32, f32)>,
+}
+
+impl Trace {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = File::create(path)?;
+        Ok(serde_json::to_writer(file, self)?)
+    }
+}
+
+/// 蒸餾資料的一筆紀錄，為 JSONL 的一行
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogitRecord {
+    pub input_ids: Vec<u32>,
+    /// 生成的 token
+    pub tokens: Vec<u32>,
+    /// 每個生成 token 的 top-k (token id, log probability)，由高到低
+    pub top_logprobs: Vec<Vec<(u32, f32)>>,
+}
+
+impl From<&Trace> for LogitRecord {
+    fn from(trace: &Trace) -> Self {
+        Self {
+            input_ids: trace.input_ids.clone(),
+            tokens: trace.steps.iter().map(|step| step.token).collect(),
+            top_logprobs: trace
+                .steps
+                .iter()
+                .map(|step| step.top_logprobs.clone())
+                .collect(),
+        }
+    }
+}
+
+/// 將每次生成的 top-k log probability 寫成 JSONL，作為蒸餾或 reward model 的訓練資料。
+/// 記錄的是模型原始的 logits，不受 temperature 與 transform 影響
+pub struct LogitRecorder<W: Write> {
+    writer: W,
+    records: usize,
+}
+
+impl LogitRecorder<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> LogitRecorder<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, records: 0 }
+    }
+
+    pub fn write(&mut self, record: &LogitRecord) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// 生成一次並寫入紀錄，generation 需要先呼叫 record_trace 設定 top k
+    pub fn generate<M: Model>(
+        &mut self,
+        generation: &mut TextGeneration<M>,
+        ids: &[u32],
+        max_new_tokens: usize,
+    ) -> Result<GeneratedSequence> {
+        if generation.trace().is_none() {
+            bail!("call record_trace before recording logits");
+        }
+        let sequence = generation.generate_n(ids, max_new_tokens, 1)?.remove(0);
+        if let Some(trace) = generation.trace() {
+            self.write(&trace.into())?;
+        }
+        Ok(sequence)
+    }
+
+    /// 已寫入的紀錄數
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayReport {
+    pub steps: usize,
+    /// 所有步驟中 top-k log probability 的最大差異
+    pub max_abs_diff: f32,
+    /// 第一個 top-k token 不同或差異超過 atol 的步驟
+    pub first_mismatch: Option<usize>,
+}
+
+impl ReplayReport {
+    pub fn is_match(&self) -> bool {
+        self.first_mismatch.is_none()
+    }
+}
+
+/// 以紀錄中的 token 重新執行模型，比對每一步的 top-k log probability。
+/// 紀錄時若發生 context overflow，位置會不同，無法 replay
+pub fn replay<M: Model>(
+    trace: &Trace,
+    model: &mut M,
+    device: &Device,
+    atol: f32,
+) -> Result<ReplayReport> {
+    if trace.input_ids.is_empty() {
+        bail!("trace without input ids");
+    }
+
+    let mut report = ReplayReport {
+        steps: trace.steps.len(),
+        max_abs_diff: 0.,
+        first_mismatch: None,
+    };
+
+    model.reset();
+    let mut tokens = trace.input_ids.clone();
+    for (index, step) in trace.steps.iter().enumerate() {
+        let start_pos = if index == 0 { 0 } else { tokens.len() - 1 };
+        let input = Tensor::new(&tokens[start_pos..], device)?.unsqueeze(0)?;
+        let logits = model.forward(&input, start_pos)?;
+        let logits = logits.squeeze(0)?.squeeze(0)?.to_dtype(DType::F32)?;
+        let replayed = top_logprobs(&logits, trace.top_k)?;
+
+        let mut matched = replayed.len() == step.top_logprobs.len();
+        for (id, expected) in &step.top_logprobs {
+            match replayed.iter().find(|(r, _)| r == id) {
+                Some((_, got)) => {
+                    let diff = (got - expected).abs();
+                    report.max_abs_diff = report.max_abs_diff.max(diff);
+                    matched &= diff <= atol;
+                }
+                None => matched = false,
+            }
+        }
+        if !matched && report.first_mismatch.is_none() {
+            report.first_mismatch = Some(index);
+        }
+        tokens.push(step.token);
+    }
+    model.reset();
+    Ok(report)
+}
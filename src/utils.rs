@@ -1,6 +1,8 @@
 use crate::Result;
 use candle_core::Device;
 use candle_core::utils;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub(crate) fn device(cpu: bool, index: usize) -> Result<Device> {
     if cpu {
@@ -29,3 +31,16 @@ pub fn metal(index: usize) -> Result<Device> {
 pub fn cuda(index: usize) -> Result<Device> {
     device(false, index)
 }
+
+static TEMP_DIR_ID: AtomicUsize = AtomicUsize::new(0);
+
+// 建立不會重複的暫存目錄，eg: /tmp/mospeada-{name}-{pid}-{n}
+pub(crate) fn unique_temp_dir(name: &str) -> Result<PathBuf> {
+    let path = std::env::temp_dir().join(format!(
+        "mospeada-{name}-{}-{}",
+        std::process::id(),
+        TEMP_DIR_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&path)?;
+    Ok(path)
+}
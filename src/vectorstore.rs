@@ -0,0 +1,145 @@
+use crate::{Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+/// 向量相似度的計算方式，分數越大越相似
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Metric {
+    #[default]
+    Cosine,
+    Dot,
+    /// 回傳負的歐氏距離
+    L2,
+}
+
+impl Metric {
+    pub fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Self::Dot => dot(a, b),
+            Self::Cosine => {
+                let norm = (dot(a, a) * dot(b, b)).sqrt();
+                if norm == 0. { 0. } else { dot(a, b) / norm }
+            }
+            Self::L2 => -a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| (x - y) * (x - y))
+                .sum::<f32>()
+                .sqrt(),
+        }
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub vector: Vec<f32>,
+    pub metadata: Value,
+}
+
+/// 搜尋結果，id 為 add 時回傳的值
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult<'a> {
+    pub id: usize,
+    pub score: f32,
+    pub metadata: &'a Value,
+}
+
+/// 暴力搜尋的向量索引，適合數萬筆以內的資料。以 JSON 儲存
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VectorStore {
+    dim: usize,
+    metric: Metric,
+    entries: Vec<Entry>,
+}
+
+impl VectorStore {
+    pub fn new(dim: usize, metric: Metric) -> Self {
+        Self {
+            dim,
+            metric,
+            entries: vec![],
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = BufReader::new(File::open(path)?);
+        let store: Self = serde_json::from_reader(file)?;
+        if let Some(entry) = store.entries.iter().find(|e| e.vector.len() != store.dim) {
+            bail!(
+                "vector dimension mismatch: expected {}, got {}",
+                store.dim,
+                entry.vector.len()
+            );
+        }
+        Ok(store)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(serde_json::to_writer(file, self)?)
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Entry> {
+        self.entries.get(id)
+    }
+
+    /// 加入向量與 metadata，回傳 id
+    pub fn add(&mut self, vector: Vec<f32>, metadata: Value) -> Result<usize> {
+        if vector.len() != self.dim {
+            bail!(
+                "vector dimension mismatch: expected {}, got {}",
+                self.dim,
+                vector.len()
+            );
+        }
+        self.entries.push(Entry { vector, metadata });
+        Ok(self.entries.len() - 1)
+    }
+
+    /// 回傳最相似的 k 筆，由高到低排序
+    pub fn search(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult<'_>>> {
+        if query.len() != self.dim {
+            bail!(
+                "query dimension mismatch: expected {}, got {}",
+                self.dim,
+                query.len()
+            );
+        }
+        let mut results: Vec<SearchResult> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(id, entry)| SearchResult {
+                id,
+                score: self.metric.score(query, &entry.vector),
+                metadata: &entry.metadata,
+            })
+            .collect();
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results.truncate(k);
+        Ok(results)
+    }
+}
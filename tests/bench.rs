@@ -0,0 +1,54 @@
+use anyhow::Result;
+use candle_core::Device;
+use mospeada::bench::{BenchConfig, BenchReport, run};
+use mospeada::testing::MockModel;
+
+#[test]
+fn bench_mock_model() -> Result<()> {
+    let config = BenchConfig {
+        batch_sizes: vec![1, 2],
+        prompt_lens: vec![3, 5],
+        decode_tokens: 4,
+        repeats: 2,
+        warmup: 1,
+    };
+    let mut model = MockModel::new(vec![vec![0.; 4]; 1 + config.decode_tokens]);
+    let report = run(&mut model, &Device::Cpu, &config)?;
+    assert_eq!(report.results.len(), 4);
+
+    for result in &report.results {
+        // tokens/sec 以 batch_size × 長度計算
+        let prefill = (result.batch_size * result.prompt_len) as f64;
+        let decode = (result.batch_size * result.decode_tokens) as f64;
+        assert!((result.prefill_tokens_per_sec * result.prefill_secs - prefill).abs() < 1e-6);
+        assert!((result.decode_tokens_per_sec * result.decode_secs - decode).abs() < 1e-6);
+    }
+    let sizes: Vec<_> = report
+        .results
+        .iter()
+        .map(|r| (r.batch_size, r.prompt_len))
+        .collect();
+    assert_eq!(sizes, [(1, 3), (1, 5), (2, 3), (2, 5)]);
+
+    let json = report.to_json()?;
+    let parsed: BenchReport = serde_json::from_str(&json)?;
+    assert_eq!(parsed.device, report.device);
+    assert_eq!(parsed.results.len(), report.results.len());
+    // serde_json 解析浮點數可能差最後一位
+    let close = |a: f64, b: f64| (a - b).abs() <= a.abs() * 1e-12;
+    for (a, b) in parsed.results.iter().zip(&report.results) {
+        assert_eq!(
+            (a.batch_size, a.prompt_len, a.decode_tokens),
+            (b.batch_size, b.prompt_len, b.decode_tokens)
+        );
+        assert!(close(a.prefill_tokens_per_sec, b.prefill_tokens_per_sec));
+        assert!(close(a.decode_tokens_per_sec, b.decode_tokens_per_sec));
+    }
+
+    let config = BenchConfig {
+        prompt_lens: vec![0],
+        ..config
+    };
+    assert!(run(&mut model, &Device::Cpu, &config).is_err());
+    Ok(())
+}
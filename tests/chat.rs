@@ -0,0 +1,99 @@
+#![cfg(feature = "chat-template")]
+
+use anyhow::Result;
+use candle_core::Device;
+use mospeada::chat::{ChatRepl, ChatSession};
+use mospeada::chat_template::ChatTemplate;
+use mospeada::generation::{GenerationConfig, TextGeneration};
+use mospeada::testing::MockModel;
+use mospeada::tokenizers::Tokenizer;
+use std::str::FromStr;
+
+const TEMPLATE: &str = "{% for m in messages %}{{ m.role }} : {{ m.content }} ; {% endfor %}\
+                        {% if add_generation_prompt %}assistant :{% endif %}";
+
+// 每個字與標點都是一個 token
+fn tokenizer() -> Result<Tokenizer> {
+    let tokenizer = tokenizers::Tokenizer::from_str(
+        r#"{
+            "version": "1.0",
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": {
+                    "<unk>": 0, "<eos>": 1, "system": 2, "user": 3, "assistant": 4,
+                    ":": 5, ";": 6, "hi": 7, "hello": 8, "there": 9, "bye": 10, "be": 11, "brief": 12
+                },
+                "unk_token": "<unk>"
+            }
+        }"#,
+    )
+    .map_err(anyhow::Error::msg)?;
+    Ok(Tokenizer::from_hf(tokenizer))
+}
+
+fn config() -> Result<GenerationConfig> {
+    Ok(serde_json::from_str(r#"{ "eos_token_id": 1 }"#)?)
+}
+
+#[test]
+fn repl_commands() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("mospeada-chat-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let session = dir.join("session.json");
+
+    let mut model = MockModel::from_tokens(13, &[8, 9, 1, 10, 1]);
+    let generation = TextGeneration::new(&mut model, Device::Cpu, &config()?, 0, 64);
+    let mut repl = ChatRepl::new(generation, tokenizer()?, ChatTemplate::new(TEMPLATE)?);
+
+    let input = format!(
+        "hi\n/save {}\nbye\n/reset\n/system be brief\nhi\n/foo\n",
+        session.display()
+    );
+    let mut output = vec![];
+    repl.run(input.as_bytes(), &mut output)?;
+    let output = String::from_utf8(output)?;
+    assert!(output.contains("> hello there\n"), "{output}");
+    assert!(output.contains("> bye\n"), "{output}");
+    assert!(output.contains("conversation cleared"), "{output}");
+    assert!(output.contains("unknown command /foo"), "{output}");
+    assert_eq!(repl.system(), Some("be brief"));
+    assert_eq!(
+        repl.messages(),
+        [
+            serde_json::json!({ "role": "user", "content": "hi" }),
+            serde_json::json!({ "role": "assistant", "content": "hello there" }),
+        ]
+    );
+    drop(repl);
+
+    // 第二輪只 forward 新的訊息；system prompt 改變時沒有相同的前綴，重新開始
+    let calls = model.calls();
+    assert_eq!(calls[0], (vec![3, 5, 7, 6, 4, 5], 0));
+    assert_eq!(calls[3].1, 8);
+    assert_eq!(calls[5].1, 0);
+
+    let saved = ChatSession::from_file(&session)?;
+    assert_eq!(saved.messages.len(), 2);
+    assert_eq!(saved.checkpoint.tokens[6..], [8, 9, 1]);
+
+    // 從 /save 的檔案繼續對話
+    let model = MockModel::from_tokens(13, &[10, 1]);
+    let mut repl = ChatRepl::resume(
+        &session,
+        model,
+        Device::Cpu,
+        tokenizer()?,
+        ChatTemplate::new(TEMPLATE)?,
+    )?;
+    let mut output = vec![];
+    assert_eq!(repl.send("bye", &mut output)?, "bye");
+    assert_eq!(repl.messages().len(), 4);
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
@@ -0,0 +1,331 @@
+#![cfg(feature = "chat-template")]
+
+use anyhow::Result;
+use candle_core::quantized::gguf_file;
+use minijinja::context;
+use mospeada::chat_template::{
+    ChatTemplate, ChatTemplateKind, SystemRole, from_gguf, from_pretrained_or,
+};
+use mospeada::repo::MemRepo;
+use mospeada::tokenizers::SpecialTokens;
+
+#[test]
+fn special_tokens_globals() -> Result<()> {
+    let special_tokens = SpecialTokens {
+        bos_token: Some("<s>".to_string()),
+        eos_token: Some("</s>".to_string()),
+        ..Default::default()
+    };
+
+    let template = ChatTemplate::new(
+        "{{ bos_token }}{% for m in messages %}{{ m.content }}{{ eos_token }}{% endfor %}",
+    )?
+    .with_special_tokens(&special_tokens);
+
+    let prompt = template.apply(context! {
+        messages => vec![context! { role => "user", content => "hi" }],
+    })?;
+    assert_eq!(prompt, "<s>hi</s>");
+
+    // 呼叫時傳入的值優先
+    let prompt = template.apply(context! {
+        messages => vec![context! { role => "user", content => "hi" }],
+        bos_token => "",
+    })?;
+    assert_eq!(prompt, "hi</s>");
+
+    Ok(())
+}
+
+const CHATML: &str = "{% for m in messages %}<|im_start|>{{ m.role }}\n{{ m.content }}<|im_end|>\n{% endfor %}{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}";
+
+#[test]
+fn assistant_prefill() -> Result<()> {
+    let template = ChatTemplate::new(CHATML)?;
+    let prompt = template.apply_with_prefill(
+        context! {
+            messages => vec![context! { role => "user", content => "give me json" }],
+            add_generation_prompt => true,
+        },
+        "Sure, here is the JSON:",
+    )?;
+    assert_eq!(
+        prompt,
+        "<|im_start|>user\ngive me json<|im_end|>\n<|im_start|>assistant\nSure, here is the JSON:"
+    );
+
+    let prompt = template.apply_with_prefill(
+        context! {
+            messages => vec![context! { role => "user", content => "hi" }],
+        },
+        "",
+    )?;
+    assert_eq!(
+        prompt,
+        "<|im_start|>user\nhi<|im_end|>\n<|im_start|>assistant\n"
+    );
+    Ok(())
+}
+
+// 與 transformers apply_chat_template(tokenize=False, add_generation_prompt=True) 的輸出比對
+#[test]
+fn bundled_templates() -> Result<()> {
+    let messages = [
+        ("system", "You are a bot."),
+        ("user", "Hello"),
+        ("assistant", "Hi!"),
+        ("user", "How are you?"),
+    ];
+
+    let llama3 = ChatTemplate::from_kind(ChatTemplateKind::Llama3)?;
+    assert_eq!(
+        llama3.test_render(&messages)?,
+        "<|begin_of_text|><|start_header_id|>system<|end_header_id|>\n\nYou are a bot.<|eot_id|>\
+         <|start_header_id|>user<|end_header_id|>\n\nHello<|eot_id|>\
+         <|start_header_id|>assistant<|end_header_id|>\n\nHi!<|eot_id|>\
+         <|start_header_id|>user<|end_header_id|>\n\nHow are you?<|eot_id|>\
+         <|start_header_id|>assistant<|end_header_id|>\n\n"
+    );
+
+    let qwen = ChatTemplate::from_kind(ChatTemplateKind::Qwen)?;
+    assert_eq!(
+        qwen.test_render(&messages[1..2])?,
+        "<|im_start|>system\nYou are Qwen, created by Alibaba Cloud. You are a helpful assistant.<|im_end|>\n\
+         <|im_start|>user\nHello<|im_end|>\n<|im_start|>assistant\n"
+    );
+    assert_eq!(
+        qwen.test_render(&messages[..2])?,
+        ChatTemplate::from_kind(ChatTemplateKind::ChatML)?.test_render(&messages[..2])?
+    );
+
+    let mistral = ChatTemplate::from_kind(ChatTemplateKind::Mistral)?;
+    assert_eq!(
+        mistral.test_render(&messages[1..])?,
+        "<s>[INST] Hello [/INST]Hi!</s>[INST] How are you? [/INST]"
+    );
+    assert_eq!(mistral.system_role(), SystemRole::MergeIntoUser);
+    assert_eq!(
+        mistral.test_render(&messages)?,
+        "<s>[INST] You are a bot.\n\nHello [/INST]Hi!</s>[INST] How are you? [/INST]"
+    );
+    assert!(
+        mistral
+            .clone()
+            .with_system_role(SystemRole::Native)
+            .test_render(&messages)
+            .is_err()
+    );
+
+    let gemma = ChatTemplate::from_kind(ChatTemplateKind::Gemma)?;
+    assert_eq!(
+        gemma.test_render(&messages[1..])?,
+        "<bos><start_of_turn>user\nHello<end_of_turn>\n<start_of_turn>model\nHi!<end_of_turn>\n\
+         <start_of_turn>user\nHow are you?<end_of_turn>\n<start_of_turn>model\n"
+    );
+    assert_eq!(
+        gemma.test_render(&messages)?,
+        "<bos><start_of_turn>user\nYou are a bot.\n\nHello<end_of_turn>\n<start_of_turn>model\nHi!<end_of_turn>\n\
+         <start_of_turn>user\nHow are you?<end_of_turn>\n<start_of_turn>model\n"
+    );
+    assert_eq!(
+        gemma
+            .clone()
+            .with_system_role(SystemRole::Drop)
+            .test_render(&messages)?,
+        gemma.test_render(&messages[1..])?
+    );
+    assert_eq!(
+        gemma
+            .clone()
+            .with_system_prompt("Be brief.")
+            .test_render(&[("user", "Hello")])?,
+        "<bos><start_of_turn>user\nBe brief.\n\nHello<end_of_turn>\n<start_of_turn>model\n"
+    );
+    let err = gemma
+        .with_system_role(SystemRole::Native)
+        .test_render(&messages)
+        .unwrap_err();
+    assert!(err.to_string().contains("System role not supported"));
+    assert_eq!(llama3.system_role(), SystemRole::Native);
+    Ok(())
+}
+
+#[test]
+fn detect_template_kind() {
+    for kind in [
+        ChatTemplateKind::Llama3,
+        ChatTemplateKind::Qwen,
+        ChatTemplateKind::ChatML,
+        ChatTemplateKind::Mistral,
+        ChatTemplateKind::Gemma,
+    ] {
+        assert_eq!(ChatTemplateKind::detect(kind.template()), Some(kind));
+    }
+    assert_eq!(ChatTemplateKind::detect("{{ messages }}"), None);
+}
+
+#[test]
+fn template_defaults() -> Result<()> {
+    let template = ChatTemplate::new(
+        "{% for m in messages %}[{{ m.role }}]{{ m.content }}{% endfor %}\
+         {% if enable_thinking is defined and not enable_thinking %}<think></think>{% endif %}",
+    )?
+    .with_system_prompt("Be brief.")
+    .with_context(context! { enable_thinking => true })
+    .with_context(context! { enable_thinking => false });
+
+    assert_eq!(
+        template.test_render(&[("user", "hi")])?,
+        "[system]Be brief.[user]hi<think></think>"
+    );
+    // 已經有 system 訊息時不加入；呼叫時傳入的值優先
+    assert_eq!(
+        template.apply(context! {
+            messages => vec![
+                context! { role => "system", content => "Be verbose." },
+                context! { role => "user", content => "hi" },
+            ],
+            enable_thinking => true,
+        })?,
+        "[system]Be verbose.[user]hi"
+    );
+
+    let date = ChatTemplate::new("{{ date_string }}")?
+        .with_current_date()
+        .apply(context! {})?;
+    let parts = date.split(' ').collect::<Vec<_>>();
+    assert_eq!(parts.len(), 3);
+    assert_eq!(parts[0].len(), 2);
+    assert!(
+        [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"
+        ]
+        .contains(&parts[1])
+    );
+    assert!(parts[2].parse::<u32>()? >= 2024);
+    Ok(())
+}
+
+#[test]
+fn pretrained_fallback() -> Result<()> {
+    let messages = [("user", "Hello")];
+    let chatml = ChatTemplate::from_kind(ChatTemplateKind::ChatML)?.test_render(&messages)?;
+
+    // 沒有 chat_template 才使用 fallback
+    let repo = MemRepo::new("test/base").with_file("tokenizer_config.json", b"{}".as_slice());
+    let template = from_pretrained_or(&repo, ChatTemplateKind::ChatML)?;
+    assert_eq!(template.test_render(&messages)?, chatml);
+
+    // template 語法錯誤不能被 fallback 蓋掉
+    let repo = MemRepo::new("test/broken").with_file(
+        "tokenizer_config.json",
+        br#"{ "chat_template": "{% for %}" }"#.as_slice(),
+    );
+    assert!(from_pretrained_or(&repo, ChatTemplateKind::ChatML).is_err());
+    Ok(())
+}
+
+fn gguf(metadata: &[(&str, gguf_file::Value)]) -> Result<gguf_file::Content> {
+    let metadata: Vec<_> = metadata.iter().map(|(k, v)| (*k, v)).collect();
+    let mut buffer = std::io::Cursor::new(vec![]);
+    gguf_file::write(&mut buffer, &metadata, &[])?;
+    buffer.set_position(0);
+    Ok(gguf_file::Content::read(&mut buffer)?)
+}
+
+#[test]
+fn gguf_template() -> Result<()> {
+    assert_eq!(
+        ChatTemplateKind::from_name("Mistral-Instruct"),
+        Some(ChatTemplateKind::Mistral)
+    );
+    assert_eq!(
+        ChatTemplateKind::guess("Meta-Llama-3.1-8B-Instruct"),
+        Some(ChatTemplateKind::Llama3)
+    );
+    assert_eq!(ChatTemplateKind::guess("llama"), None);
+
+    let string = |s: &str| gguf_file::Value::String(s.to_string());
+    let content = gguf(&[("tokenizer.chat_template", string("{{ bos_token }}hi"))])?;
+    assert_eq!(
+        from_gguf(&content, None)?.apply(serde_json::json!({}))?,
+        "hi"
+    );
+
+    // 沒有 template 時由名稱推測，並使用 GGUF 中的 special token
+    let content = gguf(&[
+        ("general.name", string("Qwen2.5 0.5B Instruct")),
+        (
+            "tokenizer.ggml.tokens",
+            gguf_file::Value::Array(vec![string("<s>"), string("<|im_end|>")]),
+        ),
+        ("tokenizer.ggml.eos_token_id", gguf_file::Value::U32(1)),
+    ])?;
+    let template = from_gguf(&content, None)?;
+    assert_eq!(
+        template.test_render(&[("user", "hi")])?,
+        ChatTemplate::from_kind(ChatTemplateKind::Qwen)?.test_render(&[("user", "hi")])?
+    );
+    assert!(
+        from_gguf(&content, Some(ChatTemplateKind::Gemma))?
+            .test_render(&[("user", "hi")])?
+            .contains("<start_of_turn>")
+    );
+
+    let content = gguf(&[("general.name", string("unknown"))])?;
+    assert!(matches!(
+        from_gguf(&content, None),
+        Err(mospeada::Error::ChatTemplateMissing { model_id }) if model_id == "unknown"
+    ));
+    Ok(())
+}
+
+#[test]
+fn template_render_error() -> Result<()> {
+    let template = ChatTemplate::new(
+        "{% for m in messages %}\n\
+         {% if m.role == 'tool' %}{{ raise_exception('tool role not supported') }}{% endif %}\
+         {{ m.content }}\n{% endfor %}",
+    )?
+    .with_name("test/tools");
+    let err = template
+        .test_render(&[("user", "a"), ("tool", "b")])
+        .unwrap_err();
+    let text = err.to_string();
+    let mospeada::Error::TemplateRender {
+        name,
+        line,
+        column,
+        message_index,
+        message,
+    } = err
+    else {
+        panic!("unexpected error: {text}");
+    };
+    assert_eq!(name, "test/tools");
+    assert_eq!(line, Some(2));
+    assert!(column.is_some());
+    assert_eq!(message_index, Some(1));
+    assert!(message.contains("tool role not supported"));
+    assert!(
+        text.contains("line 2") && text.contains("messages[1]"),
+        "{text}"
+    );
+
+    for kind in [
+        ChatTemplateKind::Llama3,
+        ChatTemplateKind::Qwen,
+        ChatTemplateKind::ChatML,
+        ChatTemplateKind::Mistral,
+        ChatTemplateKind::Gemma,
+    ] {
+        ChatTemplate::from_kind(kind)?.lint()?;
+    }
+    assert!(template.lint().is_ok());
+    assert!(
+        ChatTemplate::new("{{ raise_exception('broken') }}")?
+            .lint()
+            .is_err()
+    );
+    Ok(())
+}
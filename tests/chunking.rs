@@ -0,0 +1,80 @@
+use anyhow::Result;
+use mospeada::chunking::{split_by_sentences, split_by_tokens, split_sentences};
+use mospeada::map_reduce::{ChunkBy, MapReduce};
+use mospeada::testing::FakeRepo;
+
+// WordLevel + Whitespace: 每個單字或標點一個 token
+const TOKENIZER: &str = r#"{
+    "version": "1.0",
+    "truncation": null,
+    "padding": null,
+    "added_tokens": [],
+    "normalizer": null,
+    "pre_tokenizer": { "type": "Whitespace" },
+    "post_processor": null,
+    "decoder": null,
+    "model": { "type": "WordLevel", "vocab": { "[UNK]": 0 }, "unk_token": "[UNK]" }
+}"#;
+
+#[test]
+fn chunking() -> Result<()> {
+    let repo = FakeRepo::new("test/chunking")?.with_file("tokenizer.json", TOKENIZER)?;
+    let tokenizer = mospeada::tokenizers::from_file(repo.path().join("tokenizer.json"))?;
+
+    let text = "one two three four five six seven";
+    let chunks = split_by_tokens(text, &tokenizer, 3, 1)?;
+    let texts: Vec<_> = chunks.iter().map(|c| c.text.as_str()).collect();
+    assert_eq!(
+        texts,
+        ["one two three", "three four five", "five six seven"]
+    );
+    assert_eq!(&text[chunks[1].start..chunks[1].end], "three four five");
+    assert!(split_by_tokens(text, &tokenizer, 2, 2).is_err());
+
+    let text = "今天天氣很好。我們去散步吧！ Hello world. Bye";
+    assert_eq!(
+        split_sentences(text),
+        ["今天天氣很好。", "我們去散步吧！ ", "Hello world. ", "Bye"]
+    );
+
+    let text = "A b. C d. E f g h i. J.";
+    let chunks = split_by_sentences(text, &tokenizer, 4)?;
+    let texts: Vec<_> = chunks.iter().map(|c| c.text.as_str()).collect();
+    // 單句超過 4 個 token 時再依 token 切割
+    assert_eq!(texts, ["A b.", "C d.", "E f g h", "i.", "J."]);
+    assert_eq!(chunks.iter().map(|c| c.tokens).sum::<usize>(), 14);
+    Ok(())
+}
+
+#[test]
+fn map_reduce() -> Result<()> {
+    let repo = FakeRepo::new("test/map-reduce")?.with_file("tokenizer.json", TOKENIZER)?;
+    let tokenizer = mospeada::tokenizers::from_file(repo.path().join("tokenizer.json"))?;
+
+    let map_reduce = MapReduce::new("Summarize: {text}", "Combine: {text}")
+        .with_chunking(3, ChunkBy::Tokens { overlap: 0 })
+        .with_separator(" | ");
+    let mut prompts = vec![];
+    let output = map_reduce.run("one two three four five", &tokenizer, |prompt| {
+        prompts.push(prompt.to_string());
+        Ok(format!("[{}] ", prompt.len()))
+    })?;
+    assert_eq!(
+        prompts,
+        [
+            "Summarize: one two three",
+            "Summarize: four five",
+            "Combine: [24] | [20]"
+        ]
+    );
+    assert_eq!(output.chunks.len(), 2);
+    assert_eq!(output.partials, ["[24] ", "[20] "]);
+    assert_eq!(output.result, "[20] ");
+
+    assert!(
+        map_reduce
+            .run("", &tokenizer, |_| Ok(String::new()))
+            .is_err()
+    );
+    Ok(())
+}
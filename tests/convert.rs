@@ -0,0 +1,60 @@
+use anyhow::Result;
+use candle_core::quantized::{GgmlDType, gguf_file};
+use candle_core::{DType, Device, Tensor};
+use mospeada::convert::{GgufOptions, safetensors_to_gguf};
+use std::collections::HashMap;
+
+#[test]
+fn export_gguf() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("mospeada-gguf-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let input = dir.join("model.safetensors");
+    let output = dir.join("model.gguf");
+
+    let tensors: HashMap<String, Tensor> = [
+        (
+            "model.proj.weight",
+            Tensor::randn(0f32, 1., (4, 64), &Device::Cpu)?,
+        ),
+        (
+            "model.norm.weight",
+            Tensor::ones(64, DType::F32, &Device::Cpu)?,
+        ),
+        ("model.skip", Tensor::ones(1, DType::F32, &Device::Cpu)?),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v))
+    .collect();
+    candle_core::safetensors::save(&tensors, &input)?;
+
+    let mut options = GgufOptions::new(GgmlDType::Q8_0);
+    options.architecture = Some("test".to_string());
+    options.chat_template = Some("{{ messages }}".to_string());
+    let summary = safetensors_to_gguf(&[&input], &output, &options, |name| {
+        (name != "model.skip").then(|| name.replace("model.", ""))
+    })?;
+    assert_eq!(summary.tensors, 2);
+    assert_eq!(summary.quantized, 1);
+
+    let content = gguf_file::Content::read(&mut std::fs::File::open(&output)?)?;
+    assert_eq!(
+        content.tensor_infos["proj.weight"].ggml_dtype,
+        GgmlDType::Q8_0
+    );
+    assert_eq!(
+        content.tensor_infos["norm.weight"].ggml_dtype,
+        GgmlDType::F32
+    );
+    assert!(!content.tensor_infos.contains_key("skip"));
+    assert_eq!(
+        content.metadata["general.architecture"].to_string()?,
+        "test"
+    );
+    assert_eq!(
+        content.metadata["tokenizer.chat_template"].to_string()?,
+        "{{ messages }}"
+    );
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
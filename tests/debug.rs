@@ -0,0 +1,108 @@
+use anyhow::Result;
+use candle_core::{DType, Device, Tensor};
+use mospeada::debug::{PrintOptions, assert_close, diff_summary, format_tensor};
+
+#[test]
+fn format_tensors() -> Result<()> {
+    let options = PrintOptions::default();
+
+    let t = Tensor::new(&[[1f32, -2.5], [3., 4.]], &Device::Cpu)?;
+    assert_eq!(
+        format_tensor(&t, &options)?,
+        "tensor([[ 1.0000, -2.5000],\n        [ 3.0000,  4.0000]], dtype=F32, shape=[2, 2])"
+    );
+
+    let t = Tensor::arange(0u32, 8, &Device::Cpu)?.reshape((2, 1, 2, 2))?;
+    assert_eq!(
+        format_tensor(&t, &options)?,
+        "tensor([[[[0, 1],\n          [2, 3]]],\n\n\n        [[[4, 5],\n          [6, 7]]]], dtype=U32, shape=[2, 1, 2, 2])"
+    );
+
+    let t = Tensor::arange(0f32, 10., &Device::Cpu)?.to_dtype(DType::BF16)?;
+    let options = PrintOptions {
+        precision: 1,
+        threshold: 5,
+        edge_items: 2,
+    };
+    assert_eq!(
+        format_tensor(&t, &options)?,
+        "tensor([0.0, 1.0, ..., 8.0, 9.0], dtype=BF16, shape=[10])"
+    );
+
+    let t = Tensor::new(1.5f32, &Device::Cpu)?;
+    assert_eq!(
+        format_tensor(&t, &PrintOptions::default())?,
+        "tensor(1.5000, dtype=F32, shape=[])"
+    );
+    Ok(())
+}
+
+#[test]
+fn compare_tensors() -> Result<()> {
+    let a = Tensor::new(&[[1f32, 2.], [3., 4.]], &Device::Cpu)?;
+    let b = Tensor::new(&[[1f32, 2.001], [3., 5.]], &Device::Cpu)?;
+
+    let summary = diff_summary(&a, &b, 1e-5, 1e-2)?;
+    assert_eq!(summary.mismatches, 1);
+    assert_eq!(summary.first_mismatches, vec![(vec![1, 1], 4., 5.)]);
+    assert_eq!(summary.max_abs, 1.);
+
+    assert!(assert_close(&a, &a, 1e-5, 1e-8).is_ok());
+    let err = assert_close(&a, &b, 1e-5, 1e-2).unwrap_err();
+    assert!(err.to_string().contains("at [1, 1]: 4 vs 5"));
+
+    assert!(diff_summary(&a, &a.flatten_all()?, 0., 0.).is_err());
+    Ok(())
+}
+
+#[test]
+fn inspect_tensors() -> Result<()> {
+    use mospeada::debug::{diff_tensors, expected_tensors, list_tensors};
+    use std::collections::HashMap;
+
+    let path = std::env::temp_dir().join(format!(
+        "mospeada-inspect-{}.safetensors",
+        std::process::id()
+    ));
+    let tensors: HashMap<String, Tensor> = [
+        (
+            "emb.weight".to_string(),
+            Tensor::zeros((4, 2), DType::F32, &Device::Cpu)?,
+        ),
+        (
+            "head.weight".to_string(),
+            Tensor::zeros((3, 2), DType::F32, &Device::Cpu)?,
+        ),
+        (
+            "extra".to_string(),
+            Tensor::zeros(1, DType::BF16, &Device::Cpu)?,
+        ),
+    ]
+    .into_iter()
+    .collect();
+    candle_core::safetensors::save(&tensors, &path)?;
+
+    let listed = list_tensors(&path)?;
+    assert_eq!(listed.len(), 3);
+    assert_eq!(listed[0].name, "emb.weight");
+    assert_eq!(listed[0].shape, vec![4, 2]);
+    assert_eq!(listed[0].dtype, "F32");
+    assert_eq!(listed[1].dtype, "BF16");
+
+    let expected = expected_tensors(&(), DType::F32, &Device::Cpu, |_, vb| {
+        let emb = candle_nn::embedding(4, 2, vb.pp("emb"))?;
+        let head = candle_nn::linear_no_bias(2, 5, vb.pp("head"))?;
+        let norm = vb.get(2, "norm.weight")?;
+        Ok((emb, head, norm))
+    })?;
+    let diff = diff_tensors(&listed, &expected);
+    assert_eq!(diff.missing, vec!["norm.weight"]);
+    assert_eq!(diff.unexpected, vec!["extra"]);
+    assert_eq!(
+        diff.shape_mismatches,
+        vec![("head.weight".to_string(), vec![3, 2], vec![5, 2])]
+    );
+
+    std::fs::remove_file(path)?;
+    Ok(())
+}
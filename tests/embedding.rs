@@ -0,0 +1,203 @@
+use anyhow::Result;
+use candle_core::{Device, Tensor};
+use mospeada::embedding::{
+    DEFAULT_TASK, EmbeddingPrompts, Quantization, QuantizedEmbedding, SparseEmbedding, SparseIndex,
+    embed_batched, hamming_distance, maxsim, maxsim_batch, micro_batches, multi_vector, quantize,
+    splade, truncate,
+};
+use mospeada::repo::{LocalRepo, MemRepo};
+use mospeada::tokenizers::Tokenizer;
+use std::str::FromStr;
+
+#[test]
+fn matryoshka_truncate() -> Result<()> {
+    let embeddings = Tensor::new(&[[3f32, 4., 12.], [0., 2., 1.]], &Device::Cpu)?;
+    let truncated = truncate(&embeddings, 2)?.to_vec2::<f32>()?;
+    assert_eq!(truncated, [[0.6, 0.8], [0., 1.]]);
+    assert!(truncate(&embeddings, 0).is_err());
+    assert!(truncate(&embeddings, 4).is_err());
+    Ok(())
+}
+
+#[test]
+fn quantization() -> Result<()> {
+    let embeddings = Tensor::new(
+        &[[1f32, -0.5, 0., 0.25, 1., -1., 0.5, 0.1, -0.2]],
+        &Device::Cpu,
+    )?;
+    let int8 = quantize(&embeddings, Quantization::Int8)?.remove(0);
+    let QuantizedEmbedding::Int8 { values, .. } = &int8 else {
+        panic!("expected int8");
+    };
+    assert_eq!(values[..3], [127, -64, 0]);
+    for (a, b) in int8
+        .dequantize()
+        .iter()
+        .zip(embeddings.to_vec2::<f32>()?[0].iter())
+    {
+        assert!((a - b).abs() < 0.01);
+    }
+
+    let binary = quantize(&embeddings, Quantization::Binary)?.remove(0);
+    assert_eq!(
+        binary,
+        QuantizedEmbedding::Binary {
+            bits: vec![0b1001_1011, 0],
+            dims: 9
+        }
+    );
+    assert_eq!(binary.dims(), 9);
+    assert_eq!(binary.dequantize()[..3], [1., -1., -1.]);
+    assert_eq!(hamming_distance(&[0b1011], &[0b0110]), 3);
+    Ok(())
+}
+
+#[test]
+fn instruction_prompts() -> Result<()> {
+    let e5 = EmbeddingPrompts::guess("intfloat/multilingual-e5-large").unwrap();
+    assert_eq!(e5.queries(&["how"]), ["query: how"]);
+    assert_eq!(e5.documents(&["because"]), ["passage: because"]);
+    assert_eq!(
+        EmbeddingPrompts::guess("BAAI/bge-large-en-v1.5")
+            .unwrap()
+            .document("doc"),
+        "doc"
+    );
+    assert_eq!(
+        EmbeddingPrompts::guess("Qwen/Qwen3-Embedding-0.6B").unwrap(),
+        EmbeddingPrompts::instruct(DEFAULT_TASK)
+    );
+    assert_eq!(
+        EmbeddingPrompts::instruct("Find code").query("sort a vec"),
+        "Instruct: Find code\nQuery:sort a vec"
+    );
+    assert_eq!(EmbeddingPrompts::guess("BAAI/bge-m3"), None);
+
+    let repo = MemRepo::new("someone/custom").with_file(
+        "config_sentence_transformers.json",
+        br#"{ "prompts": { "query": "q: ", "document": "d: " } }"#.as_slice(),
+    );
+    assert_eq!(
+        EmbeddingPrompts::from_repo(&repo)?,
+        EmbeddingPrompts::new("q: ", "d: ")
+    );
+    let repo = MemRepo::new("intfloat/e5-base-v2");
+    assert_eq!(EmbeddingPrompts::from_repo(&repo)?.query, "query: ");
+    assert_eq!(
+        EmbeddingPrompts::from_repo(&MemRepo::new("someone/custom"))?,
+        EmbeddingPrompts::default()
+    );
+
+    // LocalRepo 對不存在的檔案也會回傳路徑
+    let dir = std::env::temp_dir().join(format!("mospeada-prompts-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let repo = LocalRepo::new("intfloat/e5-base-v2", &dir);
+    assert_eq!(EmbeddingPrompts::from_repo(&repo)?.query, "query: ");
+    std::fs::write(
+        dir.join("config_sentence_transformers.json"),
+        r#"{ "prompts": { "query": "q: " } }"#,
+    )?;
+    assert_eq!(
+        EmbeddingPrompts::from_repo(&repo)?,
+        EmbeddingPrompts::new("q: ", "")
+    );
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[test]
+fn sparse_embedding() -> Result<()> {
+    let e = std::f32::consts::E - 1.;
+    // (batch 1, seq 2, vocab 4)，第二個位置被 mask
+    let logits = Tensor::new(&[[[e, -1., 0., 0.], [0., 0., e, 9.]]], &Device::Cpu)?;
+    let mask = Tensor::new(&[[1u32, 0]], &Device::Cpu)?;
+    let embeddings = splade(&logits, Some(&mask))?;
+    assert_eq!(embeddings[0].indices, [0]);
+    assert!((embeddings[0].values[0] - 1.).abs() < 1e-6);
+    let embeddings = splade(&logits, None)?;
+    assert_eq!(embeddings[0].indices, [0, 2, 3]);
+
+    let query = SparseEmbedding::new(vec![(5, 1.), (2, 2.)]);
+    let a = SparseEmbedding::new(vec![(2, 1.), (3, 4.)]);
+    let b = SparseEmbedding::new(vec![(5, 3.), (2, 1.), (9, 0.)]);
+    assert_eq!(b.len(), 2);
+    assert_eq!(query.dot(&a), 2.);
+    assert_eq!(query.dot(&b), 5.);
+    assert_eq!(a.top_k(1), SparseEmbedding::new(vec![(3, 4.)]));
+
+    let mut index = SparseIndex::new();
+    index.add(&a);
+    index.add(&b);
+    index.add(&SparseEmbedding::new(vec![(7, 1.)]));
+    assert_eq!(index.len(), 3);
+    assert_eq!(index.search(&query, 5), [(1, 5.), (0, 2.)]);
+    assert_eq!(index.search(&query, 1), [(1, 5.)]);
+    Ok(())
+}
+
+#[test]
+fn late_interaction() -> Result<()> {
+    let hidden = Tensor::new(
+        &[
+            [[2f32, 0.], [0., 3.], [5., 5.]],
+            [[0., 1.], [1., 0.], [0., 0.]],
+        ],
+        &Device::Cpu,
+    )?;
+    let mask = Tensor::new(&[[1u32, 1, 1], [1, 1, 0]], &Device::Cpu)?;
+    let vectors = multi_vector(&hidden, Some(&mask))?;
+    assert_eq!(vectors[0].dims(), [3, 2]);
+    assert_eq!(vectors[1].to_vec2::<f32>()?, [[0., 1.], [1., 0.]]);
+
+    let query = Tensor::new(&[[1f32, 0.], [0., 1.]], &Device::Cpu)?;
+    let docs = [
+        Tensor::new(&[[1f32, 0.]], &Device::Cpu)?,
+        vectors[1].clone(),
+    ];
+    assert_eq!(maxsim(&query, &docs[0])?, 1.);
+    assert_eq!(maxsim_batch(&query, &docs)?, [1., 2.]);
+    assert!(maxsim_batch(&query, &[]).is_ok_and(|s| s.is_empty()));
+    Ok(())
+}
+
+// 每個字都是一個 token
+fn tokenizer() -> Result<Tokenizer> {
+    let tokenizer = tokenizers::Tokenizer::from_str(
+        r#"{
+            "version": "1.0",
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": null,
+            "decoder": null,
+            "model": { "type": "WordLevel", "vocab": { "<unk>": 0 }, "unk_token": "<unk>" }
+        }"#,
+    )
+    .map_err(anyhow::Error::msg)?;
+    Ok(Tokenizer::from_hf(tokenizer))
+}
+
+#[test]
+fn length_sorted_batches() -> Result<()> {
+    assert_eq!(
+        micro_batches(&[5, 1, 3, 1, 9], 6),
+        [vec![1, 3], vec![2], vec![0], vec![4]]
+    );
+    assert!(micro_batches(&[], 6).is_empty());
+
+    let texts = ["a b c", "a", "a b c d e f", "a b", "a b c d", "a"];
+    let mut batches = vec![];
+    let embeddings = embed_batched(&texts, &tokenizer()?, 6, |batch| {
+        let lengths: Vec<_> = batch.iter().map(|t| t.split(' ').count()).collect();
+        // pad 後的 token 數不超過上限，除非只有一筆
+        let padded = lengths.len() * lengths.iter().max().unwrap();
+        assert!(padded <= 6 || lengths.len() == 1, "{batch:?}");
+        batches.push(lengths.clone());
+        Ok(lengths.iter().map(|&n| vec![n as f32]).collect())
+    })?;
+    assert_eq!(embeddings, [[3.], [1.], [6.], [2.], [4.], [1.]]);
+    assert_eq!(batches, [vec![1, 1, 2], vec![3], vec![4], vec![6]]);
+
+    assert!(embed_batched(&texts, &tokenizer()?, 6, |_| Ok(vec![])).is_err());
+    Ok(())
+}
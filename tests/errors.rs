@@ -0,0 +1,118 @@
+use anyhow::Result;
+use candle_core::{DType, Device};
+use candle_nn::VarBuilder;
+use mospeada::Error;
+use mospeada::repo::{LocalRepo, MemRepo, Repo};
+use std::path::PathBuf;
+
+fn load(_: &serde_json::Value, _: VarBuilder) -> candle_core::Result<()> {
+    Ok(())
+}
+
+// tokenizer、chat template 與權重檔案都是網路錯誤
+struct Offline(MemRepo);
+
+impl Offline {
+    fn unreachable(&self) -> mospeada::Result<PathBuf> {
+        Err(Error::msg("connection refused"))
+    }
+}
+
+impl Repo for Offline {
+    fn model_id(&self) -> &str {
+        self.0.model_id()
+    }
+
+    fn get(&self, filename: &str) -> mospeada::Result<PathBuf> {
+        self.0.get(filename)
+    }
+
+    fn tokenizer_config_file(&self) -> mospeada::Result<PathBuf> {
+        self.unreachable()
+    }
+
+    fn tokenizer_file(&self) -> mospeada::Result<PathBuf> {
+        self.unreachable()
+    }
+
+    fn config_file(&self) -> mospeada::Result<PathBuf> {
+        self.0.config_file()
+    }
+
+    fn safetensors_files(&self) -> mospeada::Result<Vec<PathBuf>> {
+        Ok(vec![self.unreachable()?])
+    }
+
+    fn pytorch_model_file(&self) -> mospeada::Result<PathBuf> {
+        self.unreachable()
+    }
+
+    fn generate_config_file(&self) -> mospeada::Result<PathBuf> {
+        self.unreachable()
+    }
+}
+
+#[test]
+fn missing_artifacts() -> Result<()> {
+    let repo = MemRepo::new("test/empty")
+        .with_file("config.json", b"{}".as_slice())
+        .with_file("tokenizer_config.json", b"{}".as_slice());
+
+    assert!(matches!(
+        mospeada::tokenizers::from_pretrained(&repo),
+        Err(Error::TokenizerNotFound { model_id }) if model_id == "test/empty"
+    ));
+    #[cfg(feature = "chat-template")]
+    assert!(matches!(
+        mospeada::chat_template::from_pretrained(&repo),
+        Err(Error::ChatTemplateMissing { .. })
+    ));
+
+    match repo.load_model(DType::F32, &Device::Cpu, load) {
+        Err(Error::WeightsNotFound { tried, .. }) => {
+            assert!(tried.contains(&"pytorch_model.bin".to_string()))
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+
+    let dir = std::env::temp_dir().join(format!("mospeada-errors-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join("config.json"), "{}")?;
+    let repo = LocalRepo::new("test/local", &dir);
+    assert!(matches!(
+        repo.load_model(DType::F32, &Device::Cpu, load),
+        Err(Error::WeightsNotFound { .. })
+    ));
+    #[cfg(feature = "chat-template")]
+    assert!(matches!(
+        mospeada::chat_template::from_pretrained(&repo),
+        Err(Error::ChatTemplateMissing { .. })
+    ));
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[test]
+fn network_errors_are_not_missing() -> Result<()> {
+    let repo = Offline(MemRepo::new("test/offline").with_file("config.json", b"{}".as_slice()));
+
+    match mospeada::tokenizers::from_pretrained(&repo) {
+        Err(Error::TokenizerNotFound { .. }) => panic!("network error reported as missing"),
+        Err(err) => assert!(err.to_string().contains("connection refused"), "{err}"),
+        Ok(_) => panic!("expected an error"),
+    }
+    match repo.load_model(DType::F32, &Device::Cpu, load) {
+        Err(err) => {
+            assert!(!err.is_not_found());
+            assert!(err.to_string().contains("connection refused"), "{err}");
+        }
+        Ok(_) => panic!("expected an error"),
+    }
+    #[cfg(feature = "chat-template")]
+    match mospeada::chat_template::from_pretrained(&repo) {
+        Err(Error::ChatTemplateMissing { .. }) => panic!("network error reported as missing"),
+        Err(err) => assert!(err.to_string().contains("connection refused"), "{err}"),
+        Ok(_) => panic!("expected an error"),
+    }
+    Ok(())
+}
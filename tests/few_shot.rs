@@ -0,0 +1,69 @@
+use anyhow::Result;
+#[cfg(feature = "chat-template")]
+use mospeada::chat_template::{ChatTemplate, ChatTemplateKind};
+use mospeada::few_shot::FewShot;
+use mospeada::tokenizers::Tokenizer;
+use std::str::FromStr;
+
+// 每個字與標點都是一個 token
+fn tokenizer() -> Result<Tokenizer> {
+    let tokenizer = tokenizers::Tokenizer::from_str(
+        r#"{
+            "version": "1.0",
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": null,
+            "decoder": null,
+            "model": { "type": "WordLevel", "vocab": { "<unk>": 0 }, "unk_token": "<unk>" }
+        }"#,
+    )
+    .map_err(anyhow::Error::msg)?;
+    Ok(Tokenizer::from_hf(tokenizer))
+}
+
+#[test]
+fn render_plain() -> Result<()> {
+    let few_shot = FewShot::new()
+        .with_instruction("Classify")
+        .with_example("good", "positive")
+        .with_examples([("bad", "negative")]);
+    assert_eq!(
+        few_shot.render("fine")?,
+        "Classify\n\nInput: good\nOutput: positive\n\nInput: bad\nOutput: negative\n\nInput: fine\nOutput:"
+    );
+
+    let few_shot = few_shot
+        .with_labels("Q:", "A:")
+        .with_separator("\n")
+        .with_budget(&tokenizer()?, 12)?;
+    // 超過上限時刪除最舊的範例
+    assert_eq!(
+        few_shot.render("fine")?,
+        "Classify\nQ: bad\nA: negative\nQ: fine\nA:"
+    );
+
+    let few_shot = few_shot.with_budget(&tokenizer()?, 5)?;
+    assert!(few_shot.render("fine").is_err());
+    Ok(())
+}
+
+#[cfg(feature = "chat-template")]
+#[test]
+fn render_chat() -> Result<()> {
+    let template = ChatTemplate::from_kind(ChatTemplateKind::ChatML)?;
+    let few_shot = FewShot::new()
+        .with_instruction("Classify")
+        .with_example("good", "positive");
+    assert_eq!(few_shot.messages("fine").len(), 4);
+    assert_eq!(
+        few_shot.apply(&template, "fine")?,
+        template.test_render(&[
+            ("system", "Classify"),
+            ("user", "good"),
+            ("assistant", "positive"),
+            ("user", "fine"),
+        ])?
+    );
+    Ok(())
+}
@@ -0,0 +1,647 @@
+use anyhow::Result;
+use candle_core::{DType, Device, Tensor};
+use mospeada::audit::{AuditLog, AuditRecord};
+use mospeada::determinism::verify_determinism;
+use mospeada::generation::{
+    ContextOverflow, GeneratedSequence, GenerationConfig, GenerationPool, Model, Step, StopReason,
+    TextGeneration, Usage, spawn_blocking,
+};
+use mospeada::logits::TokenHealingConstraint;
+use mospeada::stopping::{ConfidentStop, MaxTime, StopTokens, StoppingContext};
+use mospeada::testing::MockModel;
+use mospeada::tokenizers::Tokenizer;
+use mospeada::trace::{LogitRecord, LogitRecorder, replay};
+use std::str::FromStr;
+use std::time::Duration;
+
+const VOCAB: usize = 8;
+
+// 永遠預測下一個 token 為 (最後一個 token + 1) % VOCAB，並記錄每次 forward 的輸入
+#[derive(Default)]
+struct Counter {
+    calls: Vec<(usize, usize)>,
+}
+
+impl Model for Counter {
+    fn forward(&mut self, x: &Tensor, start_pos: usize) -> mospeada::Result<Tensor> {
+        let ids = x.squeeze(0)?.to_vec1::<u32>()?;
+        self.calls.push((ids.len(), start_pos));
+        let next = (*ids.last().unwrap() as usize + 1) % VOCAB;
+        let mut logits = vec![0f32; VOCAB];
+        logits[next] = 10.;
+        Ok(Tensor::new(logits.as_slice(), &Device::Cpu)?
+            .unsqueeze(0)?
+            .unsqueeze(0)?)
+    }
+
+    fn reset(&mut self) {}
+}
+
+fn config() -> Result<GenerationConfig> {
+    Ok(serde_json::from_str(r#"{ "eos_token_id": 100 }"#)?)
+}
+
+#[test]
+fn context_overflow_error() -> Result<()> {
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    generation.set_context_limit(4, ContextOverflow::Error)?;
+
+    assert_eq!(generation.apply(&[0, 1, 2], 10)?, Step::Token(3));
+    assert_eq!(generation.next()?, Step::Token(4));
+    assert!(matches!(
+        generation.next(),
+        Err(mospeada::Error::ContextOverflow {
+            max_context: 4,
+            tokens: 5
+        })
+    ));
+    Ok(())
+}
+
+#[test]
+fn context_overflow_sliding_window() -> Result<()> {
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    generation.set_context_limit(6, ContextOverflow::SlidingWindow { sink_tokens: 2 })?;
+
+    let mut tokens = vec![generation.apply(&[0, 1, 2, 3, 4, 5, 6], 10)?];
+    for _ in 0..4 {
+        tokens.push(generation.next()?);
+    }
+    assert_eq!(tokens, [7, 0, 1, 2, 3].map(Step::Token));
+    assert_eq!(generation.tokens().len(), 12);
+
+    // 上限很小時至少保留最後一個 token
+    for (max_context, overflow) in [
+        (1, ContextOverflow::TruncateLeft),
+        (2, ContextOverflow::SlidingWindow { sink_tokens: 4 }),
+    ] {
+        generation.set_context_limit(max_context, overflow)?;
+        assert_eq!(generation.apply(&[0, 1, 2], 10)?, Step::Token(3));
+        assert_eq!(generation.next()?, Step::Token(4));
+    }
+    assert!(
+        generation
+            .set_context_limit(0, ContextOverflow::TruncateLeft)
+            .is_err()
+    );
+    Ok(())
+}
+
+#[test]
+fn warmup_keeps_state() -> Result<()> {
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    generation.set_context_limit(16, ContextOverflow::Error)?;
+    generation.warmup(32, 4)?;
+
+    assert_eq!(generation.apply(&[5], 10)?, Step::Token(6));
+    assert_eq!(generation.tokens(), &[5, 6]);
+    Ok(())
+}
+
+#[test]
+fn stop_reasons() -> Result<()> {
+    let config: GenerationConfig = serde_json::from_str(r#"{ "eos_token_id": 3 }"#)?;
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config, 0, 64);
+
+    assert_eq!(generation.apply(&[1], 10)?, Step::Token(2));
+    let eos = Step::Finished(StopReason::Eos { token_id: 3 });
+    assert_eq!(generation.next()?, eos);
+    assert_eq!(generation.next()?, eos);
+    assert_eq!(generation.generated_tokens(), 2);
+    assert_eq!(generation.usage(), Usage::new(1, 2));
+    assert_eq!(generation.usage().total_tokens, 3);
+
+    assert_eq!(generation.apply(&[4], 2)?, Step::Token(5));
+    assert_eq!(generation.next()?, Step::Token(6));
+    assert_eq!(generation.next()?, Step::Finished(StopReason::MaxNewTokens));
+    Ok(())
+}
+
+#[test]
+fn stopping_criteria() -> Result<()> {
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    generation.add_stopping_criteria(StopTokens(vec![6]));
+    generation.add_stopping_criteria(|ctx: &StoppingContext| Ok(ctx.generated_tokens().len() >= 3));
+
+    assert_eq!(generation.apply(&[1], 10)?, Step::Token(2));
+    assert_eq!(generation.next()?, Step::Token(3));
+    assert_eq!(
+        generation.next()?,
+        Step::Finished(StopReason::Criteria { index: 1 })
+    );
+    assert_eq!(generation.tokens(), &[1, 2, 3, 4]);
+
+    assert_eq!(generation.apply(&[4], 10)?, Step::Token(5));
+    assert_eq!(
+        generation.next()?,
+        Step::Finished(StopReason::Criteria { index: 0 })
+    );
+
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    generation.add_stopping_criteria(MaxTime::new(Duration::ZERO));
+    assert_eq!(
+        generation.apply(&[1], 10)?,
+        Step::Finished(StopReason::Criteria { index: 0 })
+    );
+    Ok(())
+}
+
+#[test]
+fn confident_stop() -> Result<()> {
+    // Counter 的下一個 token 機率約 0.9997，entropy 約 0.003
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    generation.add_stopping_criteria(
+        ConfidentStop::new(vec![])
+            .with_entropy_threshold(0.1, 2)
+            .with_patience(2),
+    );
+    assert_eq!(generation.apply(&[1], 10)?, Step::Token(2));
+    assert_eq!(generation.next()?, Step::Token(3));
+    assert_eq!(
+        generation.next()?,
+        Step::Finished(StopReason::Criteria { index: 0 })
+    );
+
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    generation.add_stopping_criteria(ConfidentStop::new(vec![5]).with_patience(1));
+    assert_eq!(generation.apply(&[3], 10)?, Step::Token(4));
+    assert_eq!(
+        generation.next()?,
+        Step::Finished(StopReason::Criteria { index: 0 })
+    );
+    Ok(())
+}
+
+#[test]
+fn logit_bias() -> Result<()> {
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    generation.set_logit_bias([(2, f32::NEG_INFINITY), (5, 20.)].into_iter().collect());
+
+    assert_eq!(generation.apply(&[1], 10)?, Step::Token(5));
+    assert_eq!(generation.next()?, Step::Token(5));
+    Ok(())
+}
+
+#[test]
+fn suppress_tokens() -> Result<()> {
+    let config: GenerationConfig = serde_json::from_str(
+        r#"{ "eos_token_id": 100, "suppress_tokens": [3], "begin_suppress_tokens": [2] }"#,
+    )?;
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config, 0, 64);
+
+    // 第一個 token 不能是 2，之後的 token 都不能是 3
+    let Step::Token(first) = generation.apply(&[1], 10)? else {
+        panic!("unexpected finish")
+    };
+    assert!(first != 2 && first != 3);
+    assert_eq!(generation.apply(&[0], 10)?, Step::Token(1));
+    assert_eq!(generation.next()?, Step::Token(2));
+    assert!(generation.next()? != Step::Token(3));
+    Ok(())
+}
+
+#[test]
+fn forced_tokens() -> Result<()> {
+    let config: GenerationConfig = serde_json::from_str(
+        r#"{
+            "eos_token_id": 100,
+            "forced_decoder_ids": [[2, 7], [3, 5]],
+            "forced_bos_token_id": 4,
+            "forced_eos_token_id": 0
+        }"#,
+    )?;
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config, 0, 64);
+
+    // 長度 1 時 forced bos，長度 2、3 時 forced_decoder_ids，最後一個 token 為 forced eos
+    assert_eq!(generation.apply(&[1], 6)?, Step::Token(4));
+    let mut tokens = vec![];
+    while let Step::Token(token) = generation.next()? {
+        tokens.push(token);
+    }
+    assert_eq!(tokens, [7, 5, 6, 7, 0]);
+    assert_eq!(generation.tokens(), &[1, 4, 7, 5, 6, 7, 0]);
+    Ok(())
+}
+
+#[test]
+fn exponential_decay_length_penalty() -> Result<()> {
+    let config: GenerationConfig = serde_json::from_str(
+        r#"{ "eos_token_id": 7, "exponential_decay_length_penalty": [2, 100.0] }"#,
+    )?;
+    let mut logits = vec![1f32; VOCAB];
+    logits[0] = 2.;
+    logits[7] = 0.5;
+    let model = MockModel::new(vec![logits; 10]);
+    let mut generation = TextGeneration::new(model, Device::Cpu, &config, 0, 64);
+
+    // 生成超過 2 個 token 後，eos 的 logits 變成 0.5 + 0.5 * 99
+    assert_eq!(generation.apply(&[1], 10)?, Step::Token(0));
+    assert_eq!(generation.next()?, Step::Token(0));
+    assert_eq!(generation.next()?, Step::Token(0));
+    assert_eq!(
+        generation.next()?,
+        Step::Finished(StopReason::Eos { token_id: 7 })
+    );
+
+    let sequence = GeneratedSequence {
+        tokens: vec![1, 2, 3, 4],
+        logprob: -4.,
+        stop_reason: StopReason::MaxNewTokens,
+    };
+    assert_eq!(sequence.score(0.), -4.);
+    assert_eq!(sequence.score(1.), -1.);
+    Ok(())
+}
+
+#[test]
+fn memory_usage() -> Result<()> {
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    generation.record_memory();
+    generation.apply(&[1], 3)?;
+    while let Step::Token(_) = generation.next()? {}
+
+    let memory = generation.memory_usage().unwrap();
+    if cfg!(target_os = "linux") {
+        assert!(memory.before_prefill.allocated.unwrap() > 0);
+        assert!(memory.after_prefill.peak.unwrap() > 0);
+        assert!(memory.peak_decode.unwrap() >= memory.after_prefill.allocated.unwrap());
+    }
+    Ok(())
+}
+
+// 隨機 sampling: logits 全部相同，由亂數決定下一個 token
+struct Uniform;
+
+impl Model for Uniform {
+    fn forward(&mut self, _x: &Tensor, _start_pos: usize) -> mospeada::Result<Tensor> {
+        Ok(Tensor::zeros(
+            (1, 1, 64),
+            candle_core::DType::F32,
+            &Device::Cpu,
+        )?)
+    }
+
+    fn reset(&mut self) {}
+}
+
+#[test]
+fn checkpoint_resume() -> Result<()> {
+    let config: GenerationConfig =
+        serde_json::from_str(r#"{ "eos_token_id": 100, "temperature": 1.0 }"#)?;
+    let path =
+        std::env::temp_dir().join(format!("mospeada-checkpoint-{}.json", std::process::id()));
+
+    let mut generation = TextGeneration::new(Uniform, Device::Cpu, &config, 42, 64);
+    generation.apply(&[1, 2, 3], 20)?;
+    for _ in 0..5 {
+        generation.next()?;
+    }
+    generation.checkpoint(&path)?;
+    let mut expected = vec![];
+    for _ in 0..5 {
+        expected.push(generation.next()?);
+    }
+
+    let mut resumed = TextGeneration::resume(&path, Uniform, Device::Cpu)?;
+    let mut got = vec![];
+    for _ in 0..5 {
+        got.push(resumed.next()?);
+    }
+    assert_eq!(got, expected);
+    assert_eq!(resumed.tokens(), generation.tokens());
+    assert_eq!(resumed.usage(), generation.usage());
+
+    // sampler chain 中 xtc 的亂數狀態也會恢復
+    let chain = "xtc=0.01,0.5".parse()?;
+    let mut generation = TextGeneration::new(Uniform, Device::Cpu, &config, 42, 64);
+    generation.set_sampler_chain(&chain)?;
+    generation.apply(&[1, 2, 3], 40)?;
+    for _ in 0..10 {
+        generation.next()?;
+    }
+    generation.checkpoint(&path)?;
+    let expected: Vec<_> = (0..20)
+        .map(|_| generation.next())
+        .collect::<Result<_, _>>()?;
+
+    let mut resumed = TextGeneration::resume(&path, Uniform, Device::Cpu)?;
+    resumed.set_sampler_chain(&chain)?;
+    let got: Vec<_> = (0..20).map(|_| resumed.next()).collect::<Result<_, _>>()?;
+    assert_eq!(got, expected);
+
+    // 中途切換成 greedy 不消耗亂數，切回 sampling 後接著原本的亂數
+    let sampling = "temp=1".parse()?;
+    let mut reference = TextGeneration::new(Uniform, Device::Cpu, &config, 42, 64);
+    reference.set_sampler_chain(&sampling)?;
+    reference.apply(&[1, 2, 3], 40)?;
+    let reference: Vec<_> = (0..10)
+        .map(|_| reference.next())
+        .collect::<Result<_, _>>()?;
+
+    let mut generation = TextGeneration::new(Uniform, Device::Cpu, &config, 42, 64);
+    generation.set_sampler_chain(&sampling)?;
+    generation.apply(&[1, 2, 3], 40)?;
+    for _ in 0..5 {
+        generation.next()?;
+    }
+    generation.set_sampler_chain(&"greedy".parse()?)?;
+    let greedy: Vec<_> = (0..5)
+        .map(|_| generation.next())
+        .collect::<Result<_, _>>()?;
+    assert!(greedy.windows(2).all(|w| w[0] == w[1]));
+    generation.set_sampler_chain(&sampling)?;
+    generation.checkpoint(&path)?;
+    let expected: Vec<_> = (0..5)
+        .map(|_| generation.next())
+        .collect::<Result<_, _>>()?;
+    assert_eq!(expected, reference[5..]);
+
+    let mut resumed = TextGeneration::resume(&path, Uniform, Device::Cpu)?;
+    resumed.set_sampler_chain(&sampling)?;
+    let got: Vec<_> = (0..5).map(|_| resumed.next()).collect::<Result<_, _>>()?;
+    assert_eq!(got, expected);
+
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+#[test]
+fn num_return_sequences() -> Result<()> {
+    let config: GenerationConfig = serde_json::from_str(
+        r#"{ "eos_token_id": 100, "temperature": 1.0, "num_return_sequences": 3 }"#,
+    )?;
+    let n = config.get_num_return_sequences_or(1);
+
+    let mut generation = TextGeneration::new(Uniform, Device::Cpu, &config, 7, 64);
+    let sequences = generation.generate_n(&[1, 2], 4, n)?;
+    assert_eq!(sequences.len(), 3);
+    for sequence in &sequences {
+        assert_eq!(sequence.tokens.len(), 4);
+        assert_eq!(sequence.stop_reason, StopReason::MaxNewTokens);
+        // 64 個 token 機率相同
+        assert!((sequence.logprob - 4. * (1f64 / 64.).ln()).abs() < 1e-4);
+    }
+    assert_ne!(sequences[0].tokens, sequences[1].tokens);
+    Ok(())
+}
+
+#[test]
+fn token_healing_constraint() -> Result<()> {
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    generation.add_transform(TokenHealingConstraint::new(vec![5]));
+
+    assert_eq!(generation.apply(&[1], 10)?, Step::Token(5));
+    assert_eq!(generation.next()?, Step::Token(6));
+    Ok(())
+}
+
+#[test]
+fn trace_replay() -> Result<()> {
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    generation.record_trace(3);
+    generation.apply(&[1, 2], 4)?;
+    while let Step::Token(_) = generation.next()? {}
+
+    let trace = generation.take_trace().unwrap();
+    assert_eq!(trace.input_ids, vec![1, 2]);
+    assert_eq!(trace.steps.len(), 4);
+    assert_eq!(trace.steps[0].token, 3);
+    assert_eq!(trace.steps[0].top_logprobs.len(), 3);
+    assert_eq!(trace.steps[0].top_logprobs[0].0, 3);
+
+    let report = replay(&trace, &mut Counter::default(), &Device::Cpu, 1e-6)?;
+    assert!(report.is_match());
+
+    // 模型改變時會找到第一個不同的步驟
+    let mut changed = trace.clone();
+    changed.steps[2].top_logprobs[0].1 -= 0.5;
+    let report = replay(&changed, &mut Counter::default(), &Device::Cpu, 1e-3)?;
+    assert_eq!(report.first_mismatch, Some(2));
+    Ok(())
+}
+
+// Counter 的 logits 以 F16 輸出
+struct HalfCounter(Counter);
+
+impl Model for HalfCounter {
+    fn forward(&mut self, x: &Tensor, start_pos: usize) -> mospeada::Result<Tensor> {
+        Ok(self.0.forward(x, start_pos)?.to_dtype(DType::F16)?)
+    }
+
+    fn reset(&mut self) {}
+}
+
+#[test]
+fn native_sampling() -> Result<()> {
+    let run = |config: &GenerationConfig, native: bool, device: bool| -> Result<(Vec<u32>, f64)> {
+        let mut generation =
+            TextGeneration::new(HalfCounter(Counter::default()), Device::Cpu, config, 0, 64);
+        generation.set_native_sampling(native);
+        generation.set_device_sampling(device);
+        let mut tokens = vec![];
+        let mut step = generation.apply(&[0], 4)?;
+        while let Step::Token(token) = step {
+            tokens.push(token);
+            step = generation.next()?;
+        }
+        Ok((tokens, generation.logprob()))
+    };
+
+    let config = config()?;
+    let (expected, logprob) = run(&config, false, false)?;
+    let (tokens, native_logprob) = run(&config, true, false)?;
+    assert_eq!(expected, [1, 2, 3, 4]);
+    assert_eq!(tokens, expected);
+    assert!((logprob - native_logprob).abs() < 1e-4);
+    let (tokens, device_logprob) = run(&config, false, true)?;
+    assert_eq!(tokens, expected);
+    assert!((logprob - device_logprob).abs() < 1e-4);
+
+    // top-k 只在候選中 sampling
+    let config: GenerationConfig =
+        serde_json::from_str(r#"{ "eos_token_id": 100, "temperature": 1.0, "top_k": 1 }"#)?;
+    assert_eq!(run(&config, true, false)?.0, expected);
+    assert_eq!(run(&config, false, true)?.0, expected);
+    let config: GenerationConfig =
+        serde_json::from_str(r#"{ "eos_token_id": 100, "temperature": 0.01, "top_k": 3 }"#)?;
+    assert_eq!(run(&config, false, true)?.0, expected);
+    Ok(())
+}
+
+#[test]
+fn deterministic() -> Result<()> {
+    let config: GenerationConfig =
+        serde_json::from_str(r#"{ "eos_token_id": 100, "temperature": 1.0 }"#)?;
+    let mut generation = TextGeneration::new(Uniform, Device::Cpu, &config, 42, 64);
+
+    let tokens = verify_determinism(&mut generation, &[1, 2, 3], 10)?;
+    assert_eq!(tokens.len(), 10);
+
+    // 沒有重設亂數時，同一個 prompt 的結果不同
+    let first = generation.generate_n(&[1, 2, 3], 10, 2)?;
+    assert_ne!(first[0].tokens, first[1].tokens);
+
+    generation.set_deterministic(true);
+    let sequences = generation.generate_n(&[1, 2, 3], 10, 2)?;
+    assert_eq!(sequences[0].tokens, tokens);
+    assert_eq!(sequences[1].tokens, tokens);
+
+    // xtc 有自己的亂數，也需要在每次生成前重設
+    let mut generation = TextGeneration::new(Uniform, Device::Cpu, &config, 42, 64);
+    generation.set_sampler_chain(&"xtc=0.01,0.5".parse()?)?;
+    let tokens = verify_determinism(&mut generation, &[1, 2, 3], 20)?;
+    generation.set_deterministic(true);
+    let sequences = generation.generate_n(&[1, 2, 3], 20, 2)?;
+    assert_eq!(sequences[0].tokens, tokens);
+    assert_eq!(sequences[1].tokens, tokens);
+    Ok(())
+}
+
+#[test]
+fn spawn_generation() -> Result<()> {
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    let handle = spawn_blocking(move || {
+        let sequence = generation.generate_n(&[0], 3, 1)?.remove(0);
+        Ok((generation, sequence))
+    })?;
+    let (generation, sequence) = handle.join()?;
+    assert_eq!(sequence.tokens, [1, 2, 3]);
+    assert_eq!(generation.tokens(), [0, 1, 2, 3]);
+
+    let handle = spawn_blocking(|| -> mospeada::Result<()> { panic!("boom") })?;
+    assert!(matches!(handle.join(), Err(mospeada::Error::Panicked(msg)) if msg == "boom"));
+
+    let handle = spawn_blocking(|| {
+        std::thread::sleep(Duration::from_millis(200));
+        Ok(())
+    })?;
+    assert!(matches!(
+        handle.join_timeout(Duration::from_millis(10)),
+        Err(mospeada::Error::Timeout(_))
+    ));
+
+    let pool = GenerationPool::new(2)?;
+    let config = config()?;
+    let handles: Vec<_> = (0..4u32)
+        .map(|i| {
+            let config = config.clone();
+            pool.spawn(move || {
+                let mut generation =
+                    TextGeneration::new(Counter::default(), Device::Cpu, &config, 0, 64);
+                Ok(generation.generate_n(&[i], 2, 1)?.remove(0).tokens)
+            })
+        })
+        .collect();
+    for (i, handle) in handles.into_iter().enumerate() {
+        assert_eq!(handle.join()?, [i as u32 + 1, i as u32 + 2]);
+    }
+    Ok(())
+}
+
+#[test]
+fn logit_recorder() -> Result<()> {
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    let mut recorder = LogitRecorder::new(vec![]);
+    assert!(recorder.generate(&mut generation, &[1], 2).is_err());
+
+    generation.record_trace(2);
+    recorder.generate(&mut generation, &[1], 2)?;
+    let sequence = recorder.generate(&mut generation, &[5], 3)?;
+    assert_eq!(sequence.tokens, [6, 7, 0]);
+    assert_eq!(recorder.records(), 2);
+
+    let output = String::from_utf8(recorder.into_inner())?;
+    let records = output
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<std::result::Result<Vec<LogitRecord>, _>>()?;
+    assert_eq!(records[0].input_ids, [1]);
+    assert_eq!(records[0].tokens, [2, 3]);
+    assert_eq!(records[1].tokens, [6, 7, 0]);
+    assert_eq!(records[1].top_logprobs.len(), 3);
+    assert_eq!(records[1].top_logprobs[0].len(), 2);
+    assert_eq!(records[1].top_logprobs[0][0].0, 6);
+    Ok(())
+}
+
+#[test]
+fn audit_log() -> Result<()> {
+    let tokenizer = tokenizers::Tokenizer::from_str(
+        r#"{
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": { "t0": 0, "t1": 1, "t2": 2, "t3": 3, "t4": 4, "t5": 5, "t6": 6, "t7": 7 },
+                "unk_token": "t0"
+            }
+        }"#,
+    )
+    .map_err(anyhow::Error::msg)?;
+    let tokenizer = Tokenizer::from_hf(tokenizer);
+
+    let mut generation = TextGeneration::new(Counter::default(), Device::Cpu, &config()?, 0, 64);
+    let mut audit = AuditLog::new(vec![])
+        .with_model_id("test/counter")
+        .with_redaction(|text| text.replace("t1", "[REDACTED]"));
+    let (response, sequence) = audit.generate(&mut generation, &tokenizer, "t1", 2)?;
+    assert_eq!(response, "t2 t3");
+    assert_eq!(sequence.tokens, [2, 3]);
+
+    audit.set_enabled(false);
+    audit.generate(&mut generation, &tokenizer, "t5", 2)?;
+    assert_eq!(audit.records(), 1);
+
+    let output = String::from_utf8(audit.into_inner())?;
+    let records = output
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<std::result::Result<Vec<AuditRecord>, _>>()?;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].model_id.as_deref(), Some("test/counter"));
+    assert_eq!(records[0].prompt, "[REDACTED]");
+    assert_eq!(records[0].response, "t2 t3");
+    assert_eq!(records[0].usage, Usage::new(1, 2));
+    assert_eq!(records[0].stop_reason, Some(StopReason::MaxNewTokens));
+    assert!(records[0].timestamp > 0);
+    Ok(())
+}
+
+#[test]
+fn incremental_prompt() -> Result<()> {
+    let mut model = MockModel::from_tokens(10, &[5, 6, 7, 5, 6]);
+    let mut generation = TextGeneration::new(&mut model, Device::Cpu, &config()?, 0, 64);
+    let mut generate = |ids: &[u32], max_new_tokens| -> Result<Vec<u32>> {
+        let mut step = generation.apply_incremental(ids, max_new_tokens)?;
+        while let Step::Token(_) = step {
+            step = generation.next()?;
+        }
+        Ok(generation.tokens()[ids.len()..].to_vec())
+    };
+    assert_eq!(generate(&[1, 2, 3], 2)?, [5, 6]);
+    // 上一輪的對話加上新的輸入，只 forward 最後生成的 token 與新的 token
+    assert_eq!(generate(&[1, 2, 3, 5, 6, 4], 1)?, [7]);
+    // template 修改了前面的文字，從不同的位置開始
+    assert_eq!(generate(&[1, 9, 3], 1)?, [5]);
+    // 沒有相同的前綴，重新開始
+    assert_eq!(generate(&[8, 8], 1)?, [5]);
+    drop(generation);
+
+    assert_eq!(
+        model.calls(),
+        [
+            (vec![1, 2, 3], 0),
+            (vec![5], 3),
+            (vec![6, 4], 4),
+            (vec![9, 3], 1),
+            (vec![8, 8], 0),
+        ]
+    );
+    Ok(())
+}
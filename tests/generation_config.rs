@@ -1,6 +1,12 @@
 use anyhow::Result;
+use candle_core::{Device, Tensor};
 use candle_transformers::generation::Sampling;
-use mospeada::generation::GenerationConfig;
+use mospeada::generation::{GenerationConfig, Step, StopReason, TextGeneration};
+use mospeada::logits::{
+    Dry, FrequencyPresencePenalty, LogitsContext, LogitsTransform, MinP, TopK, TopNSigma, TopP, Xtc,
+};
+use mospeada::sampler::{SamplerChain, SamplerSpec};
+use mospeada::testing::MockModel;
 
 #[test]
 fn load_generation_config() -> Result<()> {
@@ -39,3 +45,271 @@ fn load_generation_config() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn full_schema_and_merge() -> Result<()> {
+    let mut config = serde_json::from_str::<GenerationConfig>(
+        r#"{
+            "bos_token_id": 1,
+            "pad_token_id": 0,
+            "eos_token_id": 2,
+            "do_sample": false,
+            "temperature": 0.7,
+            "num_beams": 1,
+            "min_new_tokens": 2,
+            "max_length": 4096,
+            "no_repeat_ngram_size": 3,
+            "min_p": 0.05,
+            "stop_strings": "END",
+            "guidance_scale": 1.5,
+            "_from_model_config": true
+        }"#,
+    )?;
+    assert_eq!(config.bos_token_id, Some(1));
+    assert_eq!(config.pad_token_id, Some(0));
+    assert_eq!(config.stop_strings, Some(vec!["END".to_string()]));
+    // do_sample = false 時忽略 temperature
+    assert_eq!(config.sampling(), Sampling::ArgMax);
+
+    let request = serde_json::from_str::<GenerationConfig>(
+        r#"{ "do_sample": true, "top_k": 20, "stop_strings": ["a", "b"] }"#,
+    )?;
+    config.merge(&request);
+    assert_eq!(
+        config.sampling(),
+        Sampling::TopK {
+            k: 20,
+            temperature: 0.7
+        }
+    );
+    assert_eq!(
+        config.stop_strings,
+        Some(vec!["a".to_string(), "b".to_string()])
+    );
+    assert_eq!(config.min_new_tokens, Some(2));
+    assert_eq!(config.get_eos_token_id(), Some(vec![2]));
+
+    let mut default = GenerationConfig::default();
+    default.merge(&config);
+    assert_eq!(default.max_length, Some(4096));
+    assert_eq!(GenerationConfig::default().sampling(), Sampling::ArgMax);
+    Ok(())
+}
+
+#[test]
+fn min_new_tokens() -> Result<()> {
+    let config =
+        serde_json::from_str::<GenerationConfig>(r#"{ "eos_token_id": 1, "min_new_tokens": 2 }"#)?;
+    // eos 的 logits 最高，前兩個 token 改選 2
+    let logits = vec![0., 5., 1., 0.];
+    let model = MockModel::new(vec![logits; 3]);
+    let mut generation = TextGeneration::new(model, Device::Cpu, &config, 0, 64);
+    assert_eq!(generation.apply(&[0], 10)?, Step::Token(2));
+    assert_eq!(generation.next()?, Step::Token(2));
+    assert_eq!(
+        generation.next()?,
+        Step::Finished(StopReason::Eos { token_id: 1 })
+    );
+    Ok(())
+}
+
+#[test]
+fn frequency_presence_penalty() -> Result<()> {
+    let mut config = serde_json::from_str::<GenerationConfig>(
+        r#"{ "frequency_penalty": 1.0, "presence_penalty": 0.5, "repeat_last_n": 32 }"#,
+    )?;
+    assert_eq!(config.logits_transforms().len(), 1);
+    let mut other = GenerationConfig::default();
+    other.set_repeat_last_n(8);
+    config.merge(&other);
+    assert_eq!(config.get_repeat_last_n_or(64), 8);
+    assert_eq!(config.presence_penalty, Some(0.5));
+
+    // config 的 repeat_last_n 優先於 constructor 的參數
+    let generation =
+        TextGeneration::new(MockModel::from_tokens(4, &[0]), Device::Cpu, &config, 0, 64);
+    assert_eq!(generation.snapshot().repeat_last_n, 8);
+
+    let logits = Tensor::zeros(4, candle_core::DType::F32, &Device::Cpu)?;
+    let ctx = LogitsContext {
+        tokens: &[1, 3, 1, 1, 2],
+        prompt_tokens: 2,
+        max_new_tokens: 10,
+    };
+    let mut penalty = FrequencyPresencePenalty {
+        frequency: 1.,
+        presence: 0.5,
+        last_n: None,
+    };
+    // prompt 中的 3 不列入
+    assert_eq!(
+        penalty.apply(&logits, &ctx)?.to_vec1::<f32>()?,
+        [0., -2.5, -1.5, 0.]
+    );
+    penalty.last_n = Some(1);
+    assert_eq!(
+        penalty.apply(&logits, &ctx)?.to_vec1::<f32>()?,
+        [0., 0., -1.5, 0.]
+    );
+    Ok(())
+}
+
+#[test]
+fn dry_penalty() -> Result<()> {
+    // 1 2 3 4 ... 1 2 3 之後接 4 會重複長度 3 的序列
+    let tokens = [1, 2, 3, 4, 5, 1, 2, 3];
+    let dry = Dry::new(1.);
+    assert_eq!(dry.match_lengths(&tokens), [(4, 3)].into_iter().collect());
+    // 2 為 breaker 時比對在 2 中斷；4 為 breaker 時不懲罰
+    assert_eq!(
+        Dry::new(1.)
+            .with_sequence_breakers(vec![2])
+            .match_lengths(&tokens),
+        [(4, 1)].into_iter().collect()
+    );
+    assert!(
+        Dry::new(1.)
+            .with_sequence_breakers(vec![4])
+            .match_lengths(&tokens)
+            .is_empty()
+    );
+    assert!(
+        Dry::new(1.)
+            .with_last_n(4)
+            .match_lengths(&tokens)
+            .is_empty()
+    );
+
+    let logits = Tensor::zeros(6, candle_core::DType::F32, &Device::Cpu)?;
+    let ctx = LogitsContext {
+        tokens: &tokens,
+        prompt_tokens: 0,
+        max_new_tokens: 10,
+    };
+    let mut dry = Dry::new(2.);
+    let penalized = dry.apply(&logits, &ctx)?.to_vec1::<f32>()?;
+    // 2 * 1.75^(3 - 2)
+    assert_eq!(penalized, [0., 0., 0., 0., -3.5, 0.]);
+    dry.allowed_length = 4;
+    assert_eq!(dry.apply(&logits, &ctx)?.to_vec1::<f32>()?, [0.; 6]);
+    Ok(())
+}
+
+#[test]
+fn xtc_and_top_n_sigma() -> Result<()> {
+    let ctx = LogitsContext {
+        tokens: &[0],
+        prompt_tokens: 1,
+        max_new_tokens: 10,
+    };
+    // 機率約為 0.42、0.42、0.16、0.00
+    let logits = Tensor::new(&[2f32, 2., 1., -10.], &Device::Cpu)?;
+
+    let mut xtc = Xtc::new(0.1, 1., 0);
+    let excluded = xtc.apply(&logits, &ctx)?.to_vec1::<f32>()?;
+    assert_eq!(
+        excluded.iter().filter(|v| v.is_infinite()).count(),
+        2,
+        "{excluded:?}"
+    );
+    assert_eq!(excluded[2..], [1., -10.]);
+    // 超過 threshold 的 token 少於 2 個時不作用
+    let mut xtc = Xtc::new(0.5, 1., 0);
+    assert_eq!(
+        xtc.apply(&logits, &ctx)?.to_vec1::<f32>()?,
+        [2., 2., 1., -10.]
+    );
+    let mut xtc = Xtc::new(0.1, 0., 0);
+    assert_eq!(
+        xtc.apply(&logits, &ctx)?.to_vec1::<f32>()?,
+        [2., 2., 1., -10.]
+    );
+
+    // mean -1.25、std 約 5.07，max - 0.2 * std 約 0.99
+    let mut sigma = TopNSigma(0.2);
+    assert_eq!(
+        sigma.apply(&logits, &ctx)?.to_vec1::<f32>()?,
+        [2., 2., 1., f32::NEG_INFINITY]
+    );
+    let mut sigma = TopNSigma(0.1);
+    assert_eq!(
+        sigma.apply(&logits, &ctx)?.to_vec1::<f32>()?,
+        [2., 2., f32::NEG_INFINITY, f32::NEG_INFINITY]
+    );
+    Ok(())
+}
+
+#[test]
+fn sampler_chain() -> Result<()> {
+    let chain: SamplerChain = "top_k=40 -> top_p=0.9 -> temp=0.8 -> dry -> xtc=0.2".parse()?;
+    assert_eq!(
+        chain.0,
+        [
+            SamplerSpec::TopK(40),
+            SamplerSpec::TopP(0.9),
+            SamplerSpec::Temperature(0.8),
+            SamplerSpec::Dry(0.8),
+            SamplerSpec::Xtc(0.2, 0.5),
+        ]
+    );
+    assert_eq!(
+        chain.to_string(),
+        "top_k=40 -> top_p=0.9 -> temp=0.8 -> dry=0.8 -> xtc=0.2,0.5"
+    );
+    assert_eq!(chain.to_string().parse::<SamplerChain>()?, chain);
+    assert_eq!(chain.transforms(0).len(), 5);
+
+    let json = SamplerChain::from_json(
+        r#"["min_p=0.05", { "top_n_sigma": 1.5 }, { "xtc": [0.1, 0.3] }, { "greedy": null }]"#,
+    )?;
+    assert_eq!(
+        json.0,
+        [
+            SamplerSpec::MinP(0.05),
+            SamplerSpec::TopNSigma(1.5),
+            SamplerSpec::Xtc(0.1, 0.3),
+            SamplerSpec::Greedy,
+        ]
+    );
+    assert!(json.is_greedy());
+    assert!("top_k".parse::<SamplerChain>().is_err());
+    assert!("top_k=a".parse::<SamplerChain>().is_err());
+    assert!("mirostat=2".parse::<SamplerChain>().is_err());
+    assert!(SamplerChain::from_json(r#"{ "top_k": 1 }"#).is_err());
+    assert_eq!(
+        SamplerChain::from_json(r#"[{ "top_k": "40" }, { "xtc": ["0.1", 0.3] }]"#)?.0,
+        [SamplerSpec::TopK(40), SamplerSpec::Xtc(0.1, 0.3)]
+    );
+
+    let ctx = LogitsContext {
+        tokens: &[0],
+        prompt_tokens: 1,
+        max_new_tokens: 10,
+    };
+    let logits = Tensor::new(&[3f32, 2., 1., 0.], &Device::Cpu)?;
+    let masked = |mut t: Box<dyn LogitsTransform>| -> Result<usize> {
+        let logits = t.apply(&logits, &ctx)?.to_vec1::<f32>()?;
+        Ok(logits.iter().filter(|v| v.is_infinite()).count())
+    };
+    assert_eq!(masked(Box::new(TopK(2)))?, 2);
+    // 機率約為 0.64、0.24、0.09、0.03
+    assert_eq!(masked(Box::new(TopP(0.8)))?, 2);
+    assert_eq!(masked(Box::new(MinP(0.2)))?, 2);
+
+    // top_k=1 之後 sampling 等同 greedy
+    let mut config = GenerationConfig::default();
+    config.set_eos_token_id(mospeada::generation::Eos::Single(3));
+    let model = MockModel::from_tokens(4, &[2, 1, 3]);
+    let mut generation = TextGeneration::new(model, Device::Cpu, &config, 42, 64);
+    generation.set_sampler_chain(&"top_k=1 -> temp=1.5".parse()?)?;
+    assert_eq!(generation.generate_n(&[0], 10, 1)?[0].tokens, [2, 1, 3]);
+
+    // 再次設定時取代前一個 chain，xtc=0,1 只留下機率最低的 token
+    let model = MockModel::new(vec![vec![3., 2., 1., 0.]; 3]);
+    let mut generation = TextGeneration::new(model, Device::Cpu, &config, 42, 64);
+    generation.set_sampler_chain(&"xtc=0,1".parse()?)?;
+    assert_eq!(generation.generate_n(&[0], 3, 1)?[0].tokens, [3]);
+    generation.set_sampler_chain(&"top_k=1".parse()?)?;
+    assert_eq!(generation.generate_n(&[0], 3, 1)?[0].tokens, [0, 0, 0]);
+    Ok(())
+}
@@ -0,0 +1,99 @@
+#![cfg(all(feature = "http", unix))]
+
+use anyhow::Result;
+use mospeada::hf_hub::{PrunePolicy, prune, scan_cache};
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+// 建立 snapshot，檔案為指向 blobs 的 symlink，同 HF 的快取格式
+fn snapshot(repo: &Path, commit: &str, files: &[(&str, &str)], age: u64) -> Result<()> {
+    let dir = repo.join("snapshots").join(commit);
+    std::fs::create_dir_all(&dir)?;
+    for (name, blob) in files {
+        std::os::unix::fs::symlink(format!("../../blobs/{blob}"), dir.join(name))?;
+    }
+    let modified = SystemTime::now() - Duration::from_secs(age);
+    File::open(&dir)?.set_modified(modified)?;
+    Ok(())
+}
+
+#[test]
+fn scan_and_prune() -> Result<()> {
+    let cache = std::env::temp_dir().join(format!("mospeada-hf-cache-{}", std::process::id()));
+    let repo = cache.join("models--org--model");
+    std::fs::create_dir_all(repo.join("blobs"))?;
+    std::fs::create_dir_all(repo.join("refs"))?;
+    std::fs::write(repo.join("blobs/config-v1"), "1")?;
+    std::fs::write(repo.join("blobs/config-v2"), "22")?;
+    std::fs::write(repo.join("blobs/weights"), "4444")?;
+    std::fs::write(repo.join("refs/main"), "ccc")?;
+    std::fs::create_dir_all(repo.join("refs/refs/pr"))?;
+    std::fs::write(repo.join("refs/refs/pr/1"), "bbb")?;
+    snapshot(
+        &repo,
+        "aaa",
+        &[
+            ("config.json", "config-v1"),
+            ("model.safetensors", "weights"),
+        ],
+        3000,
+    )?;
+    snapshot(&repo, "bbb", &[("config.json", "config-v1")], 2000)?;
+    snapshot(
+        &repo,
+        "ccc",
+        &[
+            ("config.json", "config-v2"),
+            ("model.safetensors", "weights"),
+        ],
+        10,
+    )?;
+
+    let repos = scan_cache(&cache)?;
+    assert_eq!(repos.len(), 1);
+    assert_eq!(repos[0].repo_id, "org/model");
+    assert_eq!(repos[0].repo_type, "model");
+    assert_eq!(repos[0].size, 7);
+    let commits: Vec<_> = repos[0]
+        .revisions
+        .iter()
+        .map(|r| r.commit.as_str())
+        .collect();
+    assert_eq!(commits, ["ccc", "bbb", "aaa"]);
+    assert_eq!(repos[0].revisions[0].refs, ["main"]);
+    assert_eq!(repos[0].revisions[0].size, 6);
+    assert_eq!(repos[0].revisions[1].refs, ["refs/pr/1"]);
+
+    // dry run 不會刪除檔案
+    let report = prune(&cache, PrunePolicy::KeepLastN(1), true)?;
+    assert_eq!(report.removed.len(), 2);
+    assert_eq!(report.freed, 1);
+    assert_eq!(scan_cache(&cache)?[0].revisions.len(), 3);
+
+    let report = prune(
+        &cache,
+        PrunePolicy::OlderThan(Duration::from_secs(2500)),
+        false,
+    )?;
+    assert_eq!(
+        report.removed,
+        [("org/model".to_string(), "aaa".to_string())]
+    );
+    assert_eq!(report.freed, 0);
+    assert_eq!(scan_cache(&cache)?[0].revisions.len(), 2);
+
+    let report = prune(&cache, PrunePolicy::KeepLastN(1), false)?;
+    assert_eq!(report.freed, 1);
+    assert!(!repo.join("blobs/config-v1").exists());
+    assert!(!repo.join("refs/refs").exists());
+    assert!(repo.join("refs/main").exists());
+    assert_eq!(scan_cache(&cache)?[0].size, 6);
+
+    let report = prune(&cache, PrunePolicy::KeepLastN(0), false)?;
+    assert_eq!(report.freed, 6);
+    assert!(scan_cache(&cache)?.is_empty());
+
+    std::fs::remove_dir_all(cache)?;
+    Ok(())
+}
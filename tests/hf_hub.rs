@@ -0,0 +1,130 @@
+#![cfg(feature = "http")]
+
+use anyhow::Result;
+use mospeada::Error;
+use mospeada::hf_hub::{ApiRepo, RetryPolicy};
+use mospeada::repo::Repo;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+const CONFIG: &str = r#"{ "vocab_size": 32 }"#;
+
+// 模擬 HF 的 resolve API: config.json 前兩次回傳 503，gated 回傳 401，slow 不回應
+fn serve(requests: Arc<AtomicUsize>) -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let path = line.split_whitespace().nth(1).unwrap_or("").to_string();
+            let mut start = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if let Some(range) = header.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                    start = range.split('-').next().unwrap().parse().unwrap();
+                }
+                if header.trim().is_empty() {
+                    break;
+                }
+            }
+
+            let count = requests.fetch_add(1, Ordering::SeqCst);
+            let response = match path.as_str() {
+                "/org/model/resolve/main/config.json" if count < 2 => {
+                    "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n".to_string()
+                }
+                "/org/model/resolve/main/config.json" => {
+                    let body = &CONFIG[start..];
+                    format!(
+                        "HTTP/1.1 206 Partial Content\r\nx-repo-commit: abc123\r\netag: \"config-etag\"\r\n\
+                         Content-Range: bytes {start}-{}/{}\r\nContent-Length: {}\r\n\r\n{body}",
+                        CONFIG.len() - 1,
+                        CONFIG.len(),
+                        body.len()
+                    )
+                }
+                "/org/gated/resolve/main/config.json" => {
+                    "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n".to_string()
+                }
+                "/org/slow/resolve/main/config.json" => {
+                    std::thread::sleep(Duration::from_secs(3));
+                    continue;
+                }
+                _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string(),
+            };
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    Ok(format!("http://{addr}"))
+}
+
+#[test]
+fn retry_and_cache() -> Result<()> {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let endpoint = serve(requests.clone())?;
+    let cache_dir = std::env::temp_dir().join(format!("mospeada-hf-hub-{}", std::process::id()));
+    let retry = RetryPolicy {
+        max_retries: 3,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(50),
+    };
+    assert_eq!(retry.backoff(0), Duration::from_millis(10));
+    assert_eq!(retry.backoff(1), Duration::from_millis(20));
+    assert_eq!(retry.backoff(10), Duration::from_millis(50));
+
+    let repo = ApiRepo::builder("org/model")
+        .with_endpoint(&endpoint)
+        .with_cache_dir(&cache_dir)
+        .with_retry(retry)
+        .build()?;
+    assert_eq!(repo.model_config()?.vocab_size(), Some(32));
+    // 兩次 503、一次 metadata、一次下載
+    assert_eq!(requests.load(Ordering::SeqCst), 4);
+    let config = cache_dir.join("models--org--model/snapshots/abc123/config.json");
+    assert_eq!(repo.config_file()?, config);
+    assert_eq!(
+        std::fs::read_to_string(cache_dir.join("models--org--model/refs/main"))?,
+        "abc123"
+    );
+    // 404 不重試，回傳可辨識的 FileNotFound
+    let before = requests.load(Ordering::SeqCst);
+    let err = repo.tokenizer_file().unwrap_err();
+    assert!(err.is_not_found());
+    assert!(matches!(err, Error::FileNotFound { ref file, .. } if file == "tokenizer.json"));
+    assert_eq!(requests.load(Ordering::SeqCst), before + 1);
+
+    // 已快取的檔案不需要再下載
+    let offline = ApiRepo::builder("org/model")
+        .with_endpoint("http://127.0.0.1:1")
+        .with_cache_dir(&cache_dir)
+        .with_retry(RetryPolicy::none())
+        .build()?;
+    assert_eq!(offline.config_file()?, config);
+
+    let gated = ApiRepo::builder("org/gated")
+        .with_endpoint(&endpoint)
+        .with_cache_dir(&cache_dir)
+        .build()?;
+    assert!(matches!(gated.config_file(), Err(Error::GatedRepo { .. })));
+
+    let slow = ApiRepo::builder("org/slow")
+        .with_endpoint(&endpoint)
+        .with_cache_dir(&cache_dir)
+        .with_retry(RetryPolicy::none())
+        .with_read_timeout(Some(Duration::from_millis(100)))
+        .build()?;
+    let start = std::time::Instant::now();
+    let err = slow.config_file().unwrap_err();
+    assert!(!err.is_not_found());
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    std::fs::remove_dir_all(cache_dir)?;
+    Ok(())
+}
@@ -0,0 +1,110 @@
+#![cfg(feature = "http")]
+
+use anyhow::Result;
+use mospeada::http_repo::HttpRepo;
+use mospeada::repo::Repo;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+
+// 只回應 GET 的簡易 HTTP server，需要 bearer token，回傳 base url
+fn serve(files: &'static [(&'static str, &'static str)]) -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let path = line.split_whitespace().nth(1).unwrap_or("").to_string();
+            let mut auth = false;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header
+                    .to_ascii_lowercase()
+                    .starts_with("authorization: bearer secret")
+                {
+                    auth = true;
+                }
+                if header.trim().is_empty() {
+                    break;
+                }
+            }
+            let body = files
+                .iter()
+                .find(|(p, _)| *p == path)
+                .map(|(_, body)| *body)
+                .filter(|_| auth);
+            let response = match body {
+                Some(body) => format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                ),
+                None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string(),
+            };
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+    Ok(format!("http://{addr}"))
+}
+
+#[test]
+fn download_and_cache() -> Result<()> {
+    let url = serve(&[
+        ("/models/test/config.json", r#"{ "vocab_size": 32 }"#),
+        (
+            "/models/test/model.safetensors.index.json",
+            r#"{ "weight_map": { "a": "model-1.safetensors", "b": "model-2.safetensors" } }"#,
+        ),
+        ("/models/test/model-1.safetensors", "1"),
+        ("/models/test/model-2.safetensors", "2"),
+    ])?;
+    let cache_dir = std::env::temp_dir().join(format!("mospeada-http-{}", std::process::id()));
+    let repo = HttpRepo::s3("test/model", &url, "models", "/test/")
+        .with_cache_dir(&cache_dir)
+        .with_header("Authorization", "Bearer secret");
+
+    assert_eq!(repo.model_config()?.vocab_size(), Some(32));
+    assert_eq!(
+        repo.safetensors_files()?,
+        [
+            cache_dir.join("model-1.safetensors"),
+            cache_dir.join("model-2.safetensors")
+        ]
+    );
+    assert!(repo.tokenizer_file().unwrap_err().is_not_found());
+    for filename in ["../../.bashrc", "/etc/passwd", "a/../../b", ""] {
+        assert!(repo.get(filename).is_err(), "{filename}");
+    }
+
+    // 已快取的檔案不需要再下載
+    let offline = HttpRepo::new("test/model", "http://127.0.0.1:1").with_cache_dir(&cache_dir);
+    assert_eq!(offline.model_config()?.vocab_size(), Some(32));
+    // 連線失敗不是檔案不存在，不能改用快取中的 index
+    assert!(!offline.safetensors_files().unwrap_err().is_not_found());
+
+    std::fs::remove_dir_all(cache_dir)?;
+    Ok(())
+}
+
+#[test]
+fn modelscope_urls() -> Result<()> {
+    let repo = mospeada::modelscope::from_pretrained(
+        "Qwen/Qwen2.5-0.5B-Instruct",
+        None,
+        Some("/tmp/modelscope"),
+        None,
+    )?;
+    assert_eq!(repo.model_id(), "Qwen/Qwen2.5-0.5B-Instruct");
+    assert_eq!(
+        repo.base_url(),
+        "https://www.modelscope.cn/models/Qwen/Qwen2.5-0.5B-Instruct/resolve/master"
+    );
+    assert_eq!(
+        repo.cache_dir(),
+        &std::path::PathBuf::from("/tmp/modelscope/Qwen/Qwen2.5-0.5B-Instruct/master")
+    );
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use anyhow::Result;
+use candle_core::{DType, Device, Tensor};
+use mospeada::repo::{DtypePolicy, LoadOptions, MemRepo, Repo};
+use mospeada::testing::FakeRepo;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Config {
+    hidden_size: usize,
+}
+
+#[test]
+fn load_from_memory() -> Result<()> {
+    let path =
+        std::env::temp_dir().join(format!("mospeada-mem-{}.safetensors", std::process::id()));
+    let weight = Tensor::arange(0f32, 6., &Device::Cpu)?.reshape((2, 3))?;
+    candle_core::safetensors::save(&[("w".to_string(), weight.clone())].into(), &path)?;
+    let safetensors = std::fs::read(&path)?;
+    std::fs::remove_file(&path)?;
+
+    let repo = MemRepo::new("test/mem")
+        .with_file("config.json", br#"{ "hidden_size": 3 }"#.as_slice())
+        .with_file("model.safetensors", safetensors);
+
+    assert_eq!(repo.model_config()?.hidden_size(), Some(3));
+    let loaded = repo.load_model(DType::F32, &Device::Cpu, |config: &Config, vb| {
+        vb.get((2, config.hidden_size), "w")
+    })?;
+    assert_eq!(loaded.to_vec2::<f32>()?, weight.to_vec2::<f32>()?);
+
+    // 需要路徑時才寫到暫存目錄
+    let config_file = repo.config_file()?;
+    assert_eq!(
+        std::fs::read_to_string(&config_file)?,
+        r#"{ "hidden_size": 3 }"#
+    );
+    assert!(repo.tokenizer_file().is_err());
+    drop(repo);
+    assert!(!config_file.exists());
+    Ok(())
+}
+
+#[test]
+fn load_options() -> Result<()> {
+    let repo = FakeRepo::new("test/load")?.with_file("config.json", r#"{ "hidden_size": 2 }"#)?;
+    let weight = Tensor::new(&[[1f32, 2.], [3., 4.]], &Device::Cpu)?;
+    candle_core::safetensors::save(
+        &[("w".to_string(), weight.clone())].into(),
+        repo.path().join("model.safetensors"),
+    )?;
+
+    for options in [
+        LoadOptions::default(),
+        LoadOptions::eager(),
+        LoadOptions::prefetch(),
+    ] {
+        let loaded =
+            repo.load_model_with(DType::F16, &Device::Cpu, &options, |config: &Config, vb| {
+                vb.get((2, config.hidden_size), "w")
+            })?;
+        assert_eq!(loaded.dtype(), DType::F16);
+        assert_eq!(
+            loaded.to_dtype(DType::F32)?.to_vec2::<f32>()?,
+            weight.to_vec2::<f32>()?
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn dtype_policy() -> Result<()> {
+    let policy = DtypePolicy::new(DType::BF16)
+        .with_override("lm_head", DType::F32)
+        .with_override("model.layers", DType::F16)
+        .with_override("model.layers.1", DType::F32);
+    assert_eq!(policy.dtype_of("lm_head.weight"), DType::F32);
+    assert_eq!(policy.dtype_of("lm_head_extra.weight"), DType::BF16);
+    assert_eq!(policy.dtype_of("model.layers.0.weight"), DType::F16);
+    assert_eq!(policy.dtype_of("model.layers.1.weight"), DType::F32);
+    assert_eq!(policy.dtype_of("model.embed_tokens.weight"), DType::BF16);
+
+    let repo = FakeRepo::new("test/policy")?.with_file("config.json", r#"{ "hidden_size": 2 }"#)?;
+    let weight = Tensor::new(&[[1f32, 2.], [3., 4.]], &Device::Cpu)?;
+    candle_core::safetensors::save(
+        &[
+            ("model.w".to_string(), weight.clone()),
+            ("lm_head.weight".to_string(), weight.clone()),
+        ]
+        .into(),
+        repo.path().join("model.safetensors"),
+    )?;
+
+    let (w, lm_head) = repo.load_model_with_policy(
+        &DtypePolicy::new(DType::F16).with_override("lm_head", DType::F32),
+        &Device::Cpu,
+        &LoadOptions::default(),
+        |config: &Config, vb| {
+            let shape = (2, config.hidden_size);
+            Ok((
+                vb.pp("model").get(shape, "w")?,
+                vb.pp("lm_head").get(shape, "weight")?,
+            ))
+        },
+    )?;
+    assert_eq!(w.dtype(), DType::F16);
+    assert_eq!(lm_head.dtype(), DType::F32);
+    assert_eq!(lm_head.to_vec2::<f32>()?, weight.to_vec2::<f32>()?);
+    Ok(())
+}
@@ -0,0 +1,163 @@
+use anyhow::Result;
+use candle_core::{DType, Device, Tensor};
+use mospeada::classification::{labels_above, scores_from_config};
+use mospeada::config::{ModelConfig, ProblemType, RopeScaling, rope_inv_freq};
+use mospeada::info::{EngineInfo, ModelInfo};
+use mospeada::repo::MemRepo;
+
+#[test]
+fn tolerant_model_config() -> Result<()> {
+    let config = ModelConfig::new(serde_json::from_str(
+        r#"{
+            "architectures": ["Qwen2ForCausalLM"],
+            "hidden_size": 896,
+            "max_position_embeddings": "32768",
+            "model_type": "qwen2",
+            "num_attention_heads": 14,
+            "num_hidden_layers": 24.0,
+            "num_key_value_heads": 2,
+            "rope_theta": 1000000.0,
+            "vocab_size": 151936
+        }"#,
+    )?);
+
+    assert_eq!(config.model_type(), Some("qwen2"));
+    assert_eq!(config.architectures(), vec!["Qwen2ForCausalLM"]);
+    assert_eq!(config.hidden_size(), Some(896));
+    assert_eq!(config.num_layers(), Some(24));
+    assert_eq!(config.head_dim(), Some(64));
+    assert_eq!(config.max_position_embeddings(), Some(32768));
+    assert_eq!(config.rope_theta(), Some(1e6));
+    assert_eq!(config.vocab_size(), Some(151936));
+
+    let config = ModelConfig::new(serde_json::from_str(
+        r#"{
+            "model_type": "gpt2",
+            "n_embd": 768,
+            "n_layer": 12,
+            "n_head": 12,
+            "n_positions": 1024,
+            "text_config": { "vocab_size": 50257 }
+        }"#,
+    )?);
+
+    assert_eq!(config.hidden_size(), Some(768));
+    assert_eq!(config.num_layers(), Some(12));
+    assert_eq!(config.num_key_value_heads(), Some(12));
+    assert_eq!(config.max_position_embeddings(), Some(1024));
+    assert_eq!(config.vocab_size(), Some(50257));
+    assert_eq!(config.rope_theta(), None);
+
+    Ok(())
+}
+
+#[test]
+fn rope_scaling() -> Result<()> {
+    let config = ModelConfig::new(serde_json::from_str(
+        r#"{
+            "hidden_size": 4096,
+            "num_attention_heads": 32,
+            "max_position_embeddings": 131072,
+            "rope_theta": 500000.0,
+            "rope_scaling": {
+                "factor": 8.0,
+                "low_freq_factor": 1.0,
+                "high_freq_factor": 4.0,
+                "original_max_position_embeddings": 8192,
+                "rope_type": "llama3"
+            }
+        }"#,
+    )?);
+
+    let scaling = config.rope_scaling()?.unwrap();
+    assert_eq!(
+        scaling,
+        RopeScaling::Llama3 {
+            factor: 8.,
+            low_freq_factor: 1.,
+            high_freq_factor: 4.,
+            original_max_position_embeddings: 8192,
+        }
+    );
+
+    let base = rope_inv_freq(128, 500000.);
+    let scaled = config.rope_inv_freq(131072)?;
+    assert_eq!(scaled.len(), 64);
+    // 高頻不變，低頻除以 factor
+    assert_eq!(scaled[0], base[0]);
+    assert!((scaled[63] - base[63] / 8.).abs() < 1e-12);
+
+    let config = ModelConfig::new(serde_json::from_str(
+        r#"{ "rope_scaling": { "type": "linear", "factor": 2.0 } }"#,
+    )?);
+    assert_eq!(
+        config.rope_scaling()?,
+        Some(RopeScaling::Linear { factor: 2. })
+    );
+
+    let config = ModelConfig::new(serde_json::from_str(
+        r#"{ "rope_scaling": { "rope_type": "default" } }"#,
+    )?);
+    assert_eq!(config.rope_scaling()?, None);
+
+    Ok(())
+}
+
+#[test]
+fn classification_scores() -> Result<()> {
+    let config = ModelConfig::new(serde_json::from_str(
+        r#"{
+            "id2label": { "1": "toxic", "0": "clean", "2": "insult" },
+            "problem_type": "multi_label_classification"
+        }"#,
+    )?);
+    assert_eq!(config.id2label(), ["clean", "toxic", "insult"]);
+    assert_eq!(config.problem_type(), ProblemType::MultiLabel);
+
+    let logits = Tensor::new(&[[-2f32, 3., 1.]], &Device::Cpu)?;
+    let scores = scores_from_config(&logits, &config)?;
+    assert_eq!(scores[0].label, "toxic");
+    assert!((scores[0].score - 0.9526).abs() < 1e-4);
+    assert_eq!(labels_above(&scores, 0.5), ["toxic", "insult"]);
+
+    // 沒有 problem_type 時為 single label，分數總和為 1
+    let config = ModelConfig::new(serde_json::from_str(r#"{ "num_labels": 3 }"#)?);
+    assert_eq!(config.problem_type(), ProblemType::SingleLabel);
+    let scores = scores_from_config(&logits, &config)?;
+    assert_eq!(scores[0].label, "LABEL_1");
+    assert!((scores.iter().map(|s| s.score).sum::<f32>() - 1.).abs() < 1e-5);
+
+    let config = ModelConfig::new(serde_json::from_str(r#"{ "num_labels": 1 }"#)?);
+    assert_eq!(config.problem_type(), ProblemType::Regression);
+    Ok(())
+}
+
+#[test]
+fn model_info() -> Result<()> {
+    let repo = MemRepo::new("test/info").with_file(
+        "config.json",
+        br#"{
+            "max_position_embeddings": 4096,
+            "quantization_config": { "quant_method": "awq", "bits": 4 }
+        }"#
+        .as_slice(),
+    );
+    let info = ModelInfo::from_repo(&repo, DType::BF16, &Device::Cpu)?
+        .with_revision("main")
+        .with_adapter("lora-a");
+    assert_eq!(info.model_id, "test/info");
+    assert_eq!(info.revision.as_deref(), Some("main"));
+    assert_eq!(info.dtype, "bf16");
+    assert_eq!(info.device, "cpu");
+    assert_eq!(info.context_length, Some(4096));
+    assert_eq!(info.quantization.as_deref(), Some("awq"));
+    assert_eq!(info.adapters, ["lora-a"]);
+
+    assert!(!EngineInfo::new(vec![]).ready);
+    let engine = EngineInfo::new(vec![info.clone()]);
+    assert!(engine.ready);
+    let json: serde_json::Value = serde_json::from_str(&engine.to_json()?)?;
+    assert_eq!(json["models"][0]["model_id"], "test/info");
+    assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use anyhow::Result;
+use candle_core::Device;
+use mospeada::generation::{GenerationConfig, Step, StopReason, TextGeneration};
+use mospeada::moderation::{KeywordFilter, Moderation, ModerationStop, Verdict};
+use mospeada::testing::MockModel;
+use mospeada::tokenizers::Tokenizer;
+use std::str::FromStr;
+
+#[test]
+fn keyword_filter() -> Result<()> {
+    let mut filter = KeywordFilter::new()
+        .block(["Forbidden"])
+        .redact(["secret", "password"])
+        .with_replacement("[x]");
+
+    assert_eq!(filter.check_prompt("hello")?, Verdict::Allow);
+    assert!(matches!(
+        filter.check_prompt("a FORBIDDEN word")?,
+        Verdict::Block(_)
+    ));
+    assert_eq!(
+        filter.check_prompt("my Secret password is secret")?,
+        Verdict::Redact("my [x] [x] is [x]".to_string())
+    );
+
+    // 只遮蔽新的片段，封鎖的關鍵字檢查全部輸出
+    assert_eq!(
+        filter.check_output("secret", "the secret")?,
+        Verdict::Redact("[x]".to_string())
+    );
+    assert!(filter.check_output("dden", "forbidden")?.is_stop());
+
+    let mut filter = KeywordFilter::new()
+        .block_regex([r"\b\d{4}-\d{4}-\d{4}-\d{4}\b"])?
+        .redact_regex([r"[\w.+-]+@[\w-]+\.\w+", r"(?i)token=\S+"])?
+        .redact(["secret"]);
+    assert!(matches!(
+        filter.check_prompt("card 1234-5678-9012-3456")?,
+        Verdict::Block(_)
+    ));
+    assert_eq!(
+        filter.check_prompt("mail a.b@example.com, TOKEN=abc secret")?,
+        Verdict::Redact("mail ***, *** ***".to_string())
+    );
+    assert!(KeywordFilter::new().block_regex(["("]).is_err());
+    Ok(())
+}
+
+// 記錄每次 check_output 的參數
+#[derive(Default)]
+struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>>);
+
+impl Moderation for Recorder {
+    fn check_output(&mut self, text: &str, generated: &str) -> mospeada::Result<Verdict> {
+        let mut calls = self.0.lock().unwrap();
+        calls.push((text.to_string(), generated.to_string()));
+        Ok(Verdict::Allow)
+    }
+}
+
+#[test]
+fn stop_generation() -> Result<()> {
+    let tokenizer = tokenizers::Tokenizer::from_str(
+        r#"{
+            "version": "1.0",
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": { "hello": 0, "bad": 1, "world": 2, "<unk>": 3 },
+                "unk_token": "<unk>"
+            }
+        }"#,
+    )
+    .map_err(anyhow::Error::msg)?;
+    let tokenizer = Tokenizer::from_hf(tokenizer);
+
+    let config: GenerationConfig = serde_json::from_str(r#"{ "eos_token_id": 100 }"#)?;
+    let model = MockModel::from_tokens(4, &[0, 2, 1, 0]);
+    let mut generation = TextGeneration::new(model, Device::Cpu, &config, 0, 64);
+    generation.add_stopping_criteria(ModerationStop::new(
+        &tokenizer,
+        KeywordFilter::new().block(["bad"]),
+    ));
+
+    assert_eq!(generation.apply(&[0], 10)?, Step::Token(0));
+    assert_eq!(generation.next()?, Step::Token(2));
+    assert_eq!(
+        generation.next()?,
+        Step::Finished(StopReason::Criteria { index: 0 })
+    );
+
+    // 每一步只傳入新 decode 的文字，重新生成時從頭開始
+    let recorder = Recorder::default();
+    let calls = recorder.0.clone();
+    let model = MockModel::from_tokens(4, &[0, 2, 0, 2]);
+    let mut generation = TextGeneration::new(model, Device::Cpu, &config, 0, 64);
+    generation.add_stopping_criteria(ModerationStop::new(&tokenizer, recorder));
+    for _ in 0..2 {
+        let mut step = generation.apply(&[0], 3)?;
+        while let Step::Token(_) = step {
+            step = generation.next()?;
+        }
+    }
+    let calls = calls.lock().unwrap();
+    assert_eq!(
+        calls[..3],
+        [
+            ("hello".to_string(), "hello".to_string()),
+            (" world".to_string(), "hello world".to_string()),
+            (" hello".to_string(), "hello world hello".to_string()),
+        ]
+    );
+    assert_eq!(calls[3], ("hello".to_string(), "hello".to_string()));
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use anyhow::Result;
+use mospeada::ollama::OllamaRepo;
+use mospeada::repo::{LocalRepo, Repo};
+
+#[test]
+fn ollama_layout() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("mospeada-ollama-{}", std::process::id()));
+    let manifest_dir = dir.join("manifests/registry.ollama.ai/library/qwen2.5");
+    std::fs::create_dir_all(&manifest_dir)?;
+    std::fs::create_dir_all(dir.join("blobs"))?;
+    std::fs::write(
+        manifest_dir.join("0.5b"),
+        r#"{
+            "schemaVersion": 2,
+            "layers": [
+                { "mediaType": "application/vnd.ollama.image.model", "digest": "sha256:aaa", "size": 4 },
+                { "mediaType": "application/vnd.ollama.image.template", "digest": "sha256:bbb", "size": 10 }
+            ]
+        }"#,
+    )?;
+    std::fs::write(dir.join("blobs/sha256-aaa"), "GGUF")?;
+    std::fs::write(dir.join("blobs/sha256-bbb"), "{{ .Prompt }}")?;
+    std::fs::write(dir.join("model.gguf"), "GGUF")?;
+
+    let repo = OllamaRepo::with_models_dir(&dir, "qwen2.5:0.5b")?;
+    assert_eq!(repo.gguf_file()?, dir.join("blobs/sha256-aaa"));
+    assert_eq!(repo.get("model.gguf")?, dir.join("blobs/sha256-aaa"));
+    assert_eq!(repo.template()?, "{{ .Prompt }}");
+    assert!(repo.tokenizer_file().is_err());
+    assert!(OllamaRepo::with_models_dir(&dir, "qwen2.5").is_err());
+
+    // llama.cpp 的 models 目錄直接使用 LocalRepo
+    assert_eq!(
+        LocalRepo::new("local", &dir).gguf_files()?,
+        [dir.join("model.gguf")]
+    );
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use mospeada::postprocess::{CollapseWhitespace, PostProcess, PostProcessor, StripCodeFences};
+
+#[test]
+fn processors() {
+    assert_eq!(
+        StripCodeFences.process("Here:\n```json\n{\"a\": 1}\n```"),
+        "Here:\n{\"a\": 1}"
+    );
+    assert_eq!(
+        CollapseWhitespace.process("  a   b\t c\n\n\n\nd \n e  "),
+        "a b c\n\nd\ne"
+    );
+}
+
+#[test]
+fn chain() {
+    let post = PostProcess::new()
+        .strip_prefix("Assistant:")
+        .trim_stop_strings(["User:", "END"])
+        .strip_code_fences()
+        .collapse_whitespace()
+        .with(|text: &str| text.to_uppercase());
+    assert_eq!(
+        post.apply("Assistant: ```\nhello   world\n```\nUser: next"),
+        "HELLO WORLD"
+    );
+    assert!(PostProcess::new().is_empty());
+    assert_eq!(PostProcess::new().apply(" x "), " x ");
+}
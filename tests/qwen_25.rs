@@ -1,7 +1,8 @@
 use candle_core::DType;
 
 use candle_transformers::models::qwen2::ModelForCausalLM;
-use mospeada::{Result, chat_template, error, repo::Repo};
+use mospeada::generation::{Step, StopReason};
+use mospeada::{Result, chat_template, repo::Repo};
 
 use minijinja::context;
 
@@ -84,28 +85,24 @@ fn generate_with_qwen_25() -> Result<()> {
 
     //pipeline.run(prompt, 1024, cb)?;
 
-    if let Ok(next_token) = pipeline.apply(prompt.get_ids(), 1024) {
-        cb(next_token);
-    }
-
+    let mut step = pipeline.apply(prompt.get_ids(), 1024)?;
     loop {
-        match pipeline.next() {
-            Ok(next_token) => {
-                cb(next_token);
-            }
-            Err(error::Error::Eos {
-                eos_token_id,
-                generated,
-            }) => {
-                cb(eos_token_id);
-                println!("\n\nEos token: {eos_token_id}, generated: {generated}");
+        match step {
+            Step::Token(next_token) => cb(next_token),
+            Step::Finished(StopReason::Eos { token_id }) => {
+                cb(token_id);
+                println!(
+                    "\n\nEos token: {token_id}, generated: {}",
+                    pipeline.generated_tokens()
+                );
                 break;
             }
-            Err(e) => {
-                println!("{:?}", e);
+            Step::Finished(reason) => {
+                println!("{:?}", reason);
                 break;
             }
         }
+        step = pipeline.next()?;
     }
 
     println!("got {} tokens", tokens.len());
@@ -0,0 +1,83 @@
+#![cfg(all(feature = "vectorstore", feature = "chat-template"))]
+
+use anyhow::Result;
+use mospeada::chat_template::ChatTemplate;
+use mospeada::rag::Rag;
+use mospeada::tokenizers::Tokenizer;
+use std::str::FromStr;
+
+// 每個字與標點都是一個 token
+fn tokenizer() -> Result<Tokenizer> {
+    let tokenizer = tokenizers::Tokenizer::from_str(
+        r#"{
+            "version": "1.0",
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": null,
+            "decoder": null,
+            "model": { "type": "WordLevel", "vocab": { "<unk>": 0 }, "unk_token": "<unk>" }
+        }"#,
+    )
+    .map_err(anyhow::Error::msg)?;
+    Ok(Tokenizer::from_hf(tokenizer))
+}
+
+// 以關鍵字出現的次數當作向量
+fn embed(texts: &[String]) -> mospeada::Result<Vec<Vec<f32>>> {
+    Ok(texts
+        .iter()
+        .map(|text| {
+            let text = text.to_lowercase();
+            ["cat", "dog", "fish"]
+                .iter()
+                .map(|word| text.matches(word).count() as f32)
+                .collect()
+        })
+        .collect())
+}
+
+#[test]
+fn retrieve_and_cite() -> Result<()> {
+    let mut rag = Rag::new(&tokenizer()?, embed)
+        .with_chunking(4, 0)
+        .with_top_k(2);
+    assert_eq!(
+        rag.index("pets", "cats purr softly . dogs bark loudly .")?,
+        [0, 1]
+    );
+    assert_eq!(rag.index("sea", "fish swim in water")?, [2]);
+
+    let retrieved = rag.retrieve("why do dogs bark")?;
+    assert_eq!(retrieved[0].id, 1);
+    assert_eq!(retrieved[0].doc, "pets");
+    assert_eq!(retrieved[0].text, "dogs bark loudly .");
+
+    // template 支援 documents
+    let template = ChatTemplate::new(
+        "{% for d in documents %}<doc id={{ d.id }}>{{ d.text }}</doc>{% endfor %}\
+         {% for m in messages %}{{ m.role }}: {{ m.content }}\n{% endfor %}",
+    )?;
+    let answer = rag.answer(&template, "why do dogs bark", |prompt| {
+        assert!(
+            prompt.starts_with("<doc id=1>dogs bark loudly .</doc>"),
+            "{prompt}"
+        );
+        Ok("To warn you [1]. Not about fish [2] or [9] [1].".to_string())
+    })?;
+    assert_eq!(answer.retrieved.len(), 2);
+    // 2 沒有被檢索到，9 不存在
+    assert_eq!(answer.citations, [1]);
+
+    // template 沒有使用 documents 時放在 system 訊息
+    let template =
+        ChatTemplate::new("{% for m in messages %}{{ m.role }}: {{ m.content }}\n{% endfor %}")?;
+    let prompt = rag.render(&template, "why do dogs bark", &retrieved)?;
+    assert!(
+        prompt.starts_with("system: Answer the question"),
+        "{prompt}"
+    );
+    assert!(prompt.contains("\n\n[1] dogs bark loudly ."), "{prompt}");
+    assert!(prompt.ends_with("user: why do dogs bark\n"), "{prompt}");
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use anyhow::Result;
+use candle_core::Device;
+use mospeada::generation::{GenerationConfig, Step, TextGeneration};
+use mospeada::reasoning::{Reasoning, ReasoningDelta, ReasoningParser, ThinkingBudget};
+use mospeada::testing::MockModel;
+
+fn delta(reasoning: &str, content: &str) -> ReasoningDelta {
+    ReasoningDelta {
+        reasoning: reasoning.to_string(),
+        content: content.to_string(),
+    }
+}
+
+#[test]
+fn parse_reasoning() {
+    assert_eq!(
+        Reasoning::parse("<think>\nlet me see\n</think>\n\nThe answer is 42."),
+        Reasoning {
+            reasoning: Some("let me see".to_string()),
+            content: "The answer is 42.".to_string(),
+        }
+    );
+    assert_eq!(
+        Reasoning::parse("The answer is <think> 42."),
+        Reasoning {
+            reasoning: None,
+            content: "The answer is <think> 42.".to_string(),
+        }
+    );
+    // DeepSeek-R1 的 prompt 以 <think> 結尾
+    assert_eq!(
+        Reasoning::parse_in_reasoning("hmm</think>42"),
+        Reasoning {
+            reasoning: Some("hmm".to_string()),
+            content: "42".to_string(),
+        }
+    );
+    // 還沒結束的推理
+    assert_eq!(
+        Reasoning::parse("<think>hmm"),
+        Reasoning {
+            reasoning: Some("hmm".to_string()),
+            content: String::new(),
+        }
+    );
+}
+
+#[test]
+fn stream_reasoning() {
+    let mut parser = ReasoningParser::new();
+    let deltas = ["<th", "ink>a", "b</th", "in", "k>\n", "\nc", "d"]
+        .into_iter()
+        .map(|text| parser.push(text))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        deltas,
+        [
+            delta("", ""),
+            delta("a", ""),
+            delta("b", ""),
+            delta("", ""),
+            delta("", ""),
+            delta("", "c"),
+            delta("", "d"),
+        ]
+    );
+    assert!(!parser.in_reasoning());
+    assert!(parser.finish().is_empty());
+
+    let mut parser = ReasoningParser::new();
+    assert_eq!(parser.push("<"), delta("", ""));
+    assert_eq!(parser.push("b>"), delta("", "<b>"));
+}
+
+#[test]
+fn thinking_budget() -> Result<()> {
+    let config: GenerationConfig = serde_json::from_str(r#"{ "eos_token_id": 100 }"#)?;
+    let model = MockModel::from_tokens(5, &[2, 2, 2, 2, 2]);
+    let mut generation = TextGeneration::new(model, Device::Cpu, &config, 0, 64);
+    generation.add_transform(ThinkingBudget {
+        start_token: 1,
+        end_tokens: vec![3, 4],
+        budget: 2,
+    });
+
+    assert_eq!(generation.apply(&[0, 1], 5)?, Step::Token(2));
+    for token in [2, 3, 4, 2] {
+        assert_eq!(generation.next()?, Step::Token(token));
+    }
+    Ok(())
+}
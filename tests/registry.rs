@@ -0,0 +1,38 @@
+use anyhow::Result;
+use mospeada::registry::ModelRegistry;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[test]
+fn lazy_load_and_lru() -> Result<()> {
+    let loads = Arc::new(AtomicUsize::new(0));
+    let registry = ModelRegistry::new(100);
+    for (name, bytes) in [("a", 40), ("b", 40), ("c", 40), ("huge", 200)] {
+        let loads = loads.clone();
+        registry.register(name, bytes, move || {
+            loads.fetch_add(1, Ordering::SeqCst);
+            Ok(name.to_string())
+        })?;
+    }
+    assert_eq!(registry.names()?, ["a", "b", "c", "huge"]);
+    assert_eq!(loads.load(Ordering::SeqCst), 0);
+
+    assert_eq!(*registry.get("a")?, "a");
+    let b = registry.get("b")?;
+    registry.get("a")?;
+    assert_eq!(loads.load(Ordering::SeqCst), 2);
+    assert_eq!(registry.used_bytes()?, 80);
+
+    // 載入 c 時卸載最久沒用到的 b，使用中的 b 仍然可用
+    registry.get("c")?;
+    assert!(!registry.is_loaded("b")?);
+    assert!(registry.is_loaded("a")?);
+    assert_eq!(*b, "b");
+    assert_eq!(registry.used_bytes()?, 80);
+
+    assert!(registry.get("huge").is_err());
+    assert!(registry.get("unknown").is_err());
+    assert!(registry.unload("a")?);
+    assert_eq!(registry.used_bytes()?, 40);
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use anyhow::Result;
+use mospeada::generation::GenerationConfig;
+use mospeada::response_cache::ResponseCache;
+use serde_json::{Value, json};
+
+#[test]
+fn lru_and_disk() -> Result<()> {
+    let greedy = GenerationConfig::default();
+    let key = |prompt: &str| ResponseCache::key("test/model", prompt, &greedy, 0, &Value::Null);
+    assert_eq!(key("a")?, key("a")?);
+    assert_ne!(key("a")?, key("b")?);
+    assert_ne!(
+        key("a")?,
+        ResponseCache::key("test/other", "a", &greedy, 0, &Value::Null)?
+    );
+
+    let mut cache = ResponseCache::new(2);
+    cache.insert(&key("a")?, "A")?;
+    cache.insert(&key("b")?, "B")?;
+    assert_eq!(cache.get(&key("a")?)?.as_deref(), Some("A"));
+    // b 最久沒有使用，被移除
+    cache.insert(&key("c")?, "C")?;
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get(&key("b")?)?, None);
+    assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+    let dir = std::env::temp_dir().join(format!("mospeada-cache-{}", std::process::id()));
+    let mut cache = ResponseCache::new(1).with_dir(&dir)?;
+    let mut calls = 0;
+    for _ in 0..2 {
+        let response =
+            cache.get_or_generate("test/model", "a", &greedy, 0, &Value::Null, || {
+                calls += 1;
+                Ok("A".to_string())
+            })?;
+        assert_eq!(response, "A");
+    }
+    assert_eq!(calls, 1);
+
+    let mut reopened = ResponseCache::new(1).with_dir(&dir)?;
+    assert_eq!(reopened.get(&key("a")?)?.as_deref(), Some("A"));
+
+    // 不在 config 中的設定也會改變 key
+    let extra = |max_new_tokens: usize| {
+        ResponseCache::key(
+            "test/model",
+            "a",
+            &greedy,
+            0,
+            &json!({ "max_new_tokens": max_new_tokens }),
+        )
+    };
+    assert_ne!(extra(16)?.hash(), extra(64)?.hash());
+    assert_eq!(reopened.get(&extra(64)?)?, None);
+
+    // hash 相同但內容不同時不回傳，同 hash 碰撞
+    let other = key("b")?;
+    std::fs::copy(
+        dir.join(format!("{}.json", key("a")?.hash())),
+        dir.join(format!("{}.json", other.hash())),
+    )?;
+    assert_eq!(reopened.get(&other)?, None);
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn sampling_opt_in() -> Result<()> {
+    let mut sampling = GenerationConfig::default();
+    sampling.set_do_sample(true);
+    sampling.set_temperature(0.8);
+
+    let mut cache = ResponseCache::new(4);
+    assert!(!cache.is_cacheable(&sampling));
+    let mut calls = 0;
+    for _ in 0..2 {
+        cache.get_or_generate("test/model", "a", &sampling, 0, &Value::Null, || {
+            calls += 1;
+            Ok(calls.to_string())
+        })?;
+    }
+    assert_eq!(calls, 2);
+    assert!(cache.is_empty());
+
+    let mut cache = ResponseCache::new(4).with_sampling(true);
+    assert!(cache.is_cacheable(&sampling));
+    cache.get_or_generate("test/model", "a", &sampling, 0, &Value::Null, || {
+        Ok("x".to_string())
+    })?;
+    assert_eq!(
+        cache.get_or_generate("test/model", "a", &sampling, 0, &Value::Null, || Ok(
+            "y".to_string()
+        ))?,
+        "x"
+    );
+    assert_eq!(
+        cache.get_or_generate("test/model", "a", &sampling, 1, &Value::Null, || Ok(
+            "z".to_string()
+        ))?,
+        "z"
+    );
+    Ok(())
+}
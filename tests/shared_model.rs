@@ -0,0 +1,55 @@
+use anyhow::Result;
+use candle_core::{Device, Tensor};
+use mospeada::generation::{GenerationConfig, Model, SharedModel, Step, TextGeneration};
+use mospeada::tokenizers::Tokenizer;
+
+struct Echo;
+
+impl Model for Echo {
+    fn forward(&mut self, x: &Tensor, _start_pos: usize) -> mospeada::Result<Tensor> {
+        let last = *x.squeeze(0)?.to_vec1::<u32>()?.last().unwrap() as usize;
+        let mut logits = vec![0f32; 4];
+        logits[last % 4] = 1.;
+        Ok(Tensor::new(logits.as_slice(), &Device::Cpu)?
+            .unsqueeze(0)?
+            .unsqueeze(0)?)
+    }
+
+    fn reset(&mut self) {}
+}
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn public_types_are_send_sync() {
+    assert_send_sync::<SharedModel<Echo>>();
+    assert_send_sync::<GenerationConfig>();
+    assert_send_sync::<Tokenizer>();
+    #[cfg(feature = "chat-template")]
+    assert_send_sync::<mospeada::chat_template::ChatTemplate>();
+    assert_send_sync::<mospeada::Error>();
+}
+
+#[test]
+fn shared_model_across_threads() -> Result<()> {
+    let shared = SharedModel::new(Echo);
+    let config: GenerationConfig = serde_json::from_str(r#"{ "eos_token_id": 100 }"#)?;
+
+    let handles: Vec<_> = (0..4u32)
+        .map(|i| {
+            let shared = shared.clone();
+            let config = config.clone();
+            std::thread::spawn(move || {
+                shared.with(|model| {
+                    let mut generation = TextGeneration::new(model, Device::Cpu, &config, 0, 64);
+                    generation.apply(&[i], 4)
+                })
+            })
+        })
+        .collect();
+
+    for (i, handle) in handles.into_iter().enumerate() {
+        assert_eq!(handle.join().unwrap()??, Step::Token(i as u32));
+    }
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use anyhow::Result;
+use candle_core::Device;
+use mospeada::generation::{GenerationConfig, StopReason, TextGeneration};
+use mospeada::sink::{Coalesce, TextSink};
+use mospeada::testing::MockModel;
+use mospeada::tokenizers::Tokenizer;
+use std::str::FromStr;
+use std::time::Duration;
+
+fn tokenizer() -> Result<Tokenizer> {
+    let tokenizer = tokenizers::Tokenizer::from_str(
+        r#"{
+            "version": "1.0",
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": { "hello": 0, "</s>": 1, "world": 2, "<unk>": 3 },
+                "unk_token": "<unk>"
+            }
+        }"#,
+    )
+    .map_err(anyhow::Error::msg)?;
+    Ok(Tokenizer::from_hf(tokenizer))
+}
+
+fn generation() -> Result<TextGeneration<MockModel>> {
+    let config: GenerationConfig = serde_json::from_str(r#"{ "eos_token_id": 1 }"#)?;
+    let model = MockModel::from_tokens(4, &[0, 2, 1]);
+    Ok(TextGeneration::new(model, Device::Cpu, &config, 0, 64))
+}
+
+#[test]
+fn raw_sink() -> Result<()> {
+    let mut sink = TextSink::new(Vec::new()).with_flush(false);
+    let reason = sink.stream(&mut generation()?, &mut tokenizer()?, &[3], 10)?;
+    assert_eq!(reason, StopReason::Eos { token_id: 1 });
+    assert_eq!(String::from_utf8(sink.into_inner())?, "hello world");
+    Ok(())
+}
+
+#[test]
+fn sse_sink() -> Result<()> {
+    let mut sink = TextSink::sse(Vec::new());
+    sink.stream(&mut generation()?, &mut tokenizer()?, &[3], 10)?;
+    assert_eq!(
+        String::from_utf8(sink.into_inner())?,
+        "data: hello\n\ndata:  world\n\ndata: [DONE]\n\n"
+    );
+
+    let mut sink = TextSink::sse(Vec::new());
+    sink.write_event(Some("usage"), "a\nb")?;
+    assert_eq!(
+        String::from_utf8(sink.into_inner())?,
+        "event: usage\ndata: a\ndata: b\n\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn coalesce_sink() -> Result<()> {
+    let mut sink = TextSink::sse(Vec::new()).with_coalesce(Coalesce::new(3));
+    for text in ["a", " b", " c", " d.", " e"] {
+        sink.write_text(text)?;
+    }
+    assert_eq!(
+        String::from_utf8(sink.get_ref().clone())?,
+        "data: a b c\n\ndata:  d.\n\n"
+    );
+    sink.finish()?;
+    assert_eq!(
+        String::from_utf8(sink.into_inner())?,
+        "data: a b c\n\ndata:  d.\n\ndata:  e\n\ndata: [DONE]\n\n"
+    );
+
+    let mut sink =
+        TextSink::new(Vec::new()).with_coalesce(Coalesce::new(10).with_sentence_boundary(false));
+    sink.write_text("a.")?;
+    assert!(sink.get_ref().is_empty());
+    sink.set_coalesce(Some(Coalesce::new(10).with_interval(Duration::ZERO)))?;
+    assert_eq!(sink.get_ref().as_slice(), b"a.");
+    sink.write_text(" b")?;
+    assert_eq!(sink.get_ref().as_slice(), b"a. b");
+
+    let mut sink = TextSink::new(Vec::new()).with_coalesce(Coalesce::new(8));
+    sink.stream(&mut generation()?, &mut tokenizer()?, &[3], 10)?;
+    assert_eq!(String::from_utf8(sink.into_inner())?, "hello world");
+    Ok(())
+}
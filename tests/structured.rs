@@ -0,0 +1,51 @@
+use anyhow::Result;
+use mospeada::structured::{extract_json, generate_json, parse_json, strip_code_fence};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Answer {
+    name: String,
+    score: u32,
+}
+
+#[test]
+fn extract() -> Result<()> {
+    assert_eq!(strip_code_fence("```json\n{\"a\": 1}\n```"), "{\"a\": 1}\n");
+    assert_eq!(strip_code_fence("no fence"), "no fence");
+
+    let text = r#"Sure! {not json} Here it is: {"name": "a}b", "score": 3} hope it helps {"x": 1}"#;
+    assert_eq!(extract_json(text), Some(r#"{"name": "a}b", "score": 3}"#));
+    assert_eq!(extract_json("[1, [2, 3]] tail"), Some("[1, [2, 3]]"));
+    assert_eq!(extract_json("{\"a\": 1"), None);
+
+    let answer: Answer = parse_json("```json\n{\"name\": \"x\", \"score\": 7}\n```")?;
+    assert_eq!(
+        answer,
+        Answer {
+            name: "x".into(),
+            score: 7
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn retry() -> Result<()> {
+    let mut prompts = vec![];
+    let answer: Answer = generate_json("give me json", 1, |prompt| {
+        prompts.push(prompt.to_string());
+        Ok(if prompts.len() == 1 {
+            "{\"name\": \"x\"}".to_string()
+        } else {
+            "{\"name\": \"x\", \"score\": 1}".to_string()
+        })
+    })?;
+    assert_eq!(answer.score, 1);
+    assert_eq!(prompts.len(), 2);
+    assert!(prompts[1].starts_with("give me json\n{\"name\": \"x\"}"));
+    assert!(prompts[1].contains("missing field `score`"));
+
+    let result: mospeada::Result<Answer> = generate_json("p", 2, |_| Ok("nothing".to_string()));
+    assert!(result.is_err());
+    Ok(())
+}
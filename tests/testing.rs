@@ -0,0 +1,44 @@
+use anyhow::Result;
+use candle_core::{Device, Tensor};
+use mospeada::generation::{GenerationConfig, Model, Step, StopReason, TextGeneration};
+use mospeada::repo::Repo;
+use mospeada::testing::{FakeRepo, MockModel};
+
+#[test]
+fn mock_model_and_fake_repo() -> Result<()> {
+    let repo = FakeRepo::new("test/mock")?
+        .with_file("generation_config.json", r#"{ "eos_token_id": 2 }"#)?
+        .with_file("config.json", r#"{ "vocab_size": 4 }"#)?;
+    let config = GenerationConfig::from_pretrained(&repo)?;
+    assert_eq!(repo.model_config()?.vocab_size(), Some(4));
+    assert!(repo.tokenizer_file().is_err());
+
+    let mut model = MockModel::from_tokens(4, &[3, 1, 2]);
+    let mut generation = TextGeneration::new(&mut model, Device::Cpu, &config, 0, 64);
+    assert_eq!(generation.apply(&[0, 1], 10)?, Step::Token(3));
+    assert_eq!(generation.next()?, Step::Token(1));
+    assert_eq!(
+        generation.next()?,
+        Step::Finished(StopReason::Eos { token_id: 2 })
+    );
+    drop(generation);
+
+    assert_eq!(
+        model.calls(),
+        &[(vec![0, 1], 0), (vec![3], 2), (vec![1], 3)]
+    );
+
+    assert_eq!(model.kv_cache_len(), Some(4));
+    assert!(model.trim_to(2)?);
+    assert_eq!(model.cache(), &[0, 1]);
+    assert!(
+        model
+            .forward(&Tensor::new(&[[5u32]], &Device::Cpu)?, 3)
+            .is_err()
+    );
+
+    let path = repo.path().clone();
+    drop(repo);
+    assert!(!path.exists());
+    Ok(())
+}
@@ -0,0 +1,447 @@
+use anyhow::Result;
+use candle_core::Device;
+use mospeada::generation::GenerationConfig;
+use mospeada::repo::LocalRepo;
+use mospeada::tokenizers::{SpecialTokens, Tokenizer, TokenizerConfig, pad_batch};
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokenizers::{PaddingDirection, TruncationDirection};
+
+fn temp_repo(name: &str, files: &[(&str, &str)]) -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("mospeada-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    for (filename, content) in files {
+        std::fs::write(dir.join(filename), content)?;
+    }
+    Ok(dir)
+}
+
+#[test]
+fn bpe_fallback() -> Result<()> {
+    let dir = temp_repo(
+        "bpe",
+        &[
+            (
+                "vocab.json",
+                r#"{"h":0,"e":1,"l":2,"o":3,"he":4,"ll":5,"hell":6,"hello":7,"Ġ":8,"w":9,"r":10,"d":11}"#,
+            ),
+            ("merges.txt", "#version: 0.2\nh e\nl l\nhe ll\nhell o\n"),
+            (
+                "tokenizer_config.json",
+                r#"{"added_tokens_decoder": {"12": {"content": "<|end|>", "special": true}}, "eos_token": "hello"}"#,
+            ),
+            (
+                "special_tokens_map.json",
+                r#"{"eos_token": {"content": "<|end|>", "lstrip": false}, "pad_token": "<|end|>"}"#,
+            ),
+        ],
+    )?;
+
+    let repo = LocalRepo::new("test/bpe", &dir);
+    let tokenizer = mospeada::tokenizers::from_pretrained(&repo)?;
+    let encoding = tokenizer
+        .tokenizer()
+        .encode("hello<|end|>", false)
+        .map_err(anyhow::Error::msg)?;
+    assert_eq!(encoding.get_ids(), &[7, 12]);
+    assert_eq!(tokenizer.decode(&[7, 8, 7])?, "hello hello");
+
+    // special_tokens_map.json 優先於 tokenizer_config.json
+    assert_eq!(
+        tokenizer.special_tokens().eos_token.as_deref(),
+        Some("<|end|>")
+    );
+    assert_eq!(tokenizer.eos_token_id(), Some(12));
+    assert_eq!(tokenizer.pad_token_id(), Some(12));
+    assert_eq!(tokenizer.bos_token_id(), None);
+
+    // "hell" 後面可以接 "hell" 或 "hello"
+    let healing = tokenizer.token_healing(&[7, 8, 6]).unwrap();
+    assert_eq!(healing.ids, vec![7, 8]);
+    assert_eq!(healing.prefix, "hell");
+    assert_eq!(healing.allowed, vec![6, 7]);
+    assert_eq!(tokenizer.token_healing(&[7, 12]), None);
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[test]
+fn no_tokenizer() -> Result<()> {
+    let dir = temp_repo("spm", &[("tokenizer.model", "")])?;
+    let repo = LocalRepo::new("test/spm", &dir);
+    let err = mospeada::tokenizers::from_pretrained(&repo).unwrap_err();
+    assert!(err.to_string().contains("sentencepiece"));
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+// 以 protobuf wire format 建立 sentencepiece 的 tokenizer.model
+fn sentencepiece(pieces: &[(&str, f32, u64)], model_type: u64, add_dummy_prefix: bool) -> Vec<u8> {
+    fn varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+    fn message(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+        varint(out, field << 3 | 2);
+        varint(out, bytes.len() as u64);
+        out.extend_from_slice(bytes);
+    }
+
+    let mut model = vec![];
+    for (piece, score, kind) in pieces {
+        let mut bytes = vec![];
+        message(&mut bytes, 1, piece.as_bytes());
+        varint(&mut bytes, 2 << 3 | 5);
+        bytes.extend_from_slice(&score.to_le_bytes());
+        varint(&mut bytes, 3 << 3);
+        varint(&mut bytes, *kind);
+        message(&mut model, 1, &bytes);
+    }
+    let mut trainer = vec![];
+    varint(&mut trainer, 3 << 3);
+    varint(&mut trainer, model_type);
+    message(&mut model, 2, &trainer);
+    let mut normalizer = vec![];
+    message(&mut normalizer, 1, b"identity");
+    varint(&mut normalizer, 3 << 3);
+    varint(&mut normalizer, add_dummy_prefix as u64);
+    message(&mut model, 3, &normalizer);
+    model
+}
+
+#[test]
+fn sentencepiece_fallback() -> Result<()> {
+    let dir = temp_repo("sentencepiece", &[])?;
+    let repo = LocalRepo::new("test/sentencepiece", &dir);
+    let ids = |tokenizer: &Tokenizer, text: &str| -> Result<Vec<u32>> {
+        Ok(tokenizer
+            .tokenizer()
+            .encode(text, false)
+            .map_err(anyhow::Error::msg)?
+            .get_ids()
+            .to_vec())
+    };
+
+    // Unigram，type: 1 normal、2 unknown、3 control
+    let unigram = sentencepiece(
+        &[
+            ("<unk>", 0., 2),
+            ("<s>", 0., 3),
+            ("</s>", 0., 3),
+            ("▁hello", -1., 1),
+            ("▁world", -2., 1),
+            ("▁", -3., 1),
+            ("h", -5., 1),
+            ("e", -5., 1),
+            ("l", -5., 1),
+            ("o", -5., 1),
+        ],
+        1,
+        true,
+    );
+    std::fs::write(dir.join("tokenizer.model"), unigram)?;
+    let tokenizer = mospeada::tokenizers::from_pretrained(&repo)?;
+    assert_eq!(ids(&tokenizer, "hello  world</s>")?, [3, 4, 2]);
+    assert_eq!(ids(&tokenizer, "hole")?, [5, 6, 9, 8, 7]);
+    assert_eq!(tokenizer.decode(&[1, 3, 4])?, "hello world");
+
+    // BPE，merges 依合併後 piece 的分數排序
+    let bpe = sentencepiece(
+        &[
+            ("<unk>", 0., 2),
+            ("<s>", 0., 3),
+            ("</s>", 0., 3),
+            ("▁h", -1., 1),
+            ("▁hi", -2., 1),
+            ("▁", -3., 1),
+            ("h", -4., 1),
+            ("i", -5., 1),
+        ],
+        2,
+        true,
+    );
+    std::fs::write(dir.join("tokenizer.model"), bpe)?;
+    let tokenizer = mospeada::tokenizers::from_pretrained(&repo)?;
+    assert_eq!(ids(&tokenizer, "hi hi</s>")?, [4, 4, 2]);
+    assert_eq!(ids(&tokenizer, "ih")?, [5, 7, 6]);
+    assert_eq!(tokenizer.decode(&[1, 4, 4])?, "hi hi");
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[test]
+fn truncation_and_padding_from_config() -> Result<()> {
+    let dir = temp_repo(
+        "truncation",
+        &[
+            (
+                "vocab.json",
+                r#"{"h":0,"e":1,"l":2,"o":3,"he":4,"ll":5,"hell":6,"hello":7,"Ġ":8}"#,
+            ),
+            ("merges.txt", "#version: 0.2\nh e\nl l\nhe ll\nhell o\n"),
+            (
+                "tokenizer_config.json",
+                r#"{"model_max_length": 3, "padding_side": "left", "truncation_side": "left", "pad_token": "o"}"#,
+            ),
+        ],
+    )?;
+
+    let repo = LocalRepo::new("test/truncation", &dir);
+    let mut tokenizer = mospeada::tokenizers::from_pretrained(&repo)?;
+    assert_eq!(tokenizer.max_length(), Some(3));
+
+    let encode = |tokenizer: &Tokenizer, text: &str| {
+        tokenizer
+            .tokenizer()
+            .encode(text, false)
+            .map(|e| e.get_ids().to_vec())
+            .map_err(anyhow::Error::msg)
+    };
+    // 從左邊截斷，保留最後的 token
+    assert_eq!(encode(&tokenizer, "hello hello hell")?, [7, 8, 6]);
+
+    let batch = tokenizer
+        .tokenizer()
+        .encode_batch(vec!["hello", "hello hell"], false)
+        .map_err(anyhow::Error::msg)?;
+    assert_eq!(batch[0].get_ids(), [3, 3, 7]);
+    assert_eq!(batch[1].get_ids(), [7, 8, 6]);
+
+    // 不截斷的 encode 不影響原本的設定，改變設定後重新建立
+    let untruncated = |tokenizer: &Tokenizer| -> Result<Vec<u32>> {
+        Ok(tokenizer
+            .encode_untruncated("hello hello hell", false)?
+            .get_ids()
+            .to_vec())
+    };
+    assert_eq!(untruncated(&tokenizer)?, [7, 8, 7, 8, 6]);
+    assert_eq!(encode(&tokenizer, "hello hello hell")?, [7, 8, 6]);
+    tokenizer.set_max_length(Some(2), TruncationDirection::Right)?;
+    assert_eq!(untruncated(&tokenizer)?, [7, 8, 7, 8, 6]);
+    assert_eq!(encode(&tokenizer, "hello hello hell")?, [7, 8]);
+
+    tokenizer.set_max_length(None, TruncationDirection::Right)?;
+    assert_eq!(tokenizer.max_length(), None);
+    assert_eq!(encode(&tokenizer, "hello hello hell")?, [7, 8, 7, 8, 6]);
+
+    // 沒有設定時不限制長度
+    let config = TokenizerConfig::from_file(dir.join("vocab.json"))?;
+    assert_eq!(config.model_max_length, None);
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[test]
+fn stream_byte_fallback() -> Result<()> {
+    // "中" 為 E4 B8 AD，"😀" 為 F0 9F 98 80，都以 byte-fallback token 表示
+    let tokenizer = r#"{
+        "version": "1.0",
+        "added_tokens": [],
+        "normalizer": null,
+        "pre_tokenizer": null,
+        "post_processor": null,
+        "decoder": {
+            "type": "Sequence",
+            "decoders": [{ "type": "ByteFallback" }, { "type": "Fuse" }]
+        },
+        "model": {
+            "type": "BPE",
+            "byte_fallback": true,
+            "vocab": {
+                "<0xE4>": 0, "<0xB8>": 1, "<0xAD>": 2,
+                "<0xF0>": 3, "<0x9F>": 4, "<0x98>": 5, "<0x80>": 6,
+                "a": 7, "!": 8
+            },
+            "merges": []
+        }
+    }"#;
+    let dir = temp_repo("byte-fallback", &[("tokenizer.json", tokenizer)])?;
+    let mut tokenizer = mospeada::tokenizers::from_file(dir.join("tokenizer.json"))?;
+
+    let mut stream = vec![];
+    for token in [7, 0, 1, 2, 3, 4, 5, 6, 8, 0, 1] {
+        stream.push(tokenizer.next_token(token)?);
+    }
+    let some = |s: &str| Some(s.to_string());
+    assert_eq!(
+        stream,
+        [
+            some("a"),
+            None,
+            None,
+            some("中"),
+            None,
+            None,
+            None,
+            some("😀"),
+            some("!"),
+            None,
+            None
+        ]
+    );
+    // 結尾不完整的 UTF-8 以 U+FFFD 輸出
+    assert_eq!(tokenizer.decode_rest()?, some("\u{FFFD}\u{FFFD}"));
+
+    // 組成同一個字元的 token 共用同一個範圍
+    assert_eq!(
+        tokenizer.spans()?,
+        [
+            0..1,
+            1..4,
+            1..4,
+            1..4,
+            4..8,
+            4..8,
+            4..8,
+            4..8,
+            8..9,
+            9..15,
+            9..15
+        ]
+    );
+    assert_eq!(tokenizer.decode_all()?, "a中😀!\u{FFFD}\u{FFFD}");
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[test]
+fn pad_token() -> Result<()> {
+    let tokenizer = tokenizers::Tokenizer::from_str(
+        r#"{
+            "version": "1.0",
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": { "a": 0, "b": 1, "</s>": 2, "<pad>": 3 },
+                "unk_token": "<pad>"
+            }
+        }"#,
+    )
+    .map_err(anyhow::Error::msg)?;
+    let mut tokenizer = Tokenizer::from_hf(tokenizer);
+
+    let config = GenerationConfig::default();
+    assert!(matches!(
+        tokenizer.resolve_pad_token_id(&config),
+        Err(mospeada::Error::PadTokenNotFound)
+    ));
+
+    // 沒有 pad token 時使用 eos
+    tokenizer = tokenizer.with_special_tokens(SpecialTokens {
+        eos_token: Some("</s>".to_string()),
+        ..Default::default()
+    });
+    assert_eq!(tokenizer.resolve_pad_token_id(&config)?, 2);
+
+    tokenizer.set_pad_token("<pad>")?;
+    assert_eq!(tokenizer.resolve_pad_token_id(&config)?, 3);
+    assert!(tokenizer.set_pad_token("<none>").is_err());
+    let batch = tokenizer
+        .tokenizer()
+        .encode_batch(vec!["a", "a b"], false)
+        .map_err(anyhow::Error::msg)?;
+    assert_eq!(batch[0].get_ids(), [0, 3]);
+
+    // generation config 優先
+    let config: GenerationConfig = serde_json::from_str(r#"{ "pad_token_id": 1 }"#)?;
+    assert_eq!(tokenizer.resolve_pad_token_id(&config)?, 1);
+
+    let batch = pad_batch(
+        &[vec![0], vec![0, 1]],
+        3,
+        PaddingDirection::Left,
+        &Device::Cpu,
+    )?;
+    assert_eq!(batch.input_ids.to_vec2::<u32>()?, [[3, 0], [0, 1]]);
+    assert_eq!(batch.attention_mask.to_vec2::<u8>()?, [[0, 1], [1, 1]]);
+    let batch = pad_batch(
+        &[vec![0], vec![0, 1]],
+        3,
+        PaddingDirection::Right,
+        &Device::Cpu,
+    )?;
+    assert_eq!(batch.input_ids.to_vec2::<u32>()?, [[0, 3], [0, 1]]);
+    assert_eq!(batch.attention_mask.to_vec2::<u8>()?, [[1, 0], [1, 1]]);
+    Ok(())
+}
+
+#[test]
+fn tokenize() -> Result<()> {
+    let tokenizer = tokenizers::Tokenizer::from_str(
+        r#"{
+            "version": "1.0",
+            "added_tokens": [
+                { "id": 3, "content": "</s>", "single_word": false, "lstrip": false,
+                  "rstrip": false, "normalized": false, "special": true }
+            ],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": { "<unk>": 0, "hello": 1, "world": 2, "</s>": 3 },
+                "unk_token": "<unk>"
+            }
+        }"#,
+    )
+    .map_err(anyhow::Error::msg)?;
+    let tokenizer = Tokenizer::from_hf(tokenizer);
+
+    let tokens = tokenizer.tokenize("hello  world</s>")?;
+    assert_eq!(tokens.iter().map(|t| t.id).collect::<Vec<_>>(), [1, 2, 3]);
+    assert_eq!(tokens[1].text, "world");
+    assert_eq!(tokens[1].bytes, 7..12);
+    assert_eq!(tokens[2].token, "</s>");
+    assert_eq!(tokenizer.detokenize(&[1, 2, 3])?, "hello world </s>");
+    assert_eq!(tokenizer.decode(&[1, 2, 3])?, "hello world");
+    Ok(())
+}
+
+#[test]
+fn encode_batch() -> Result<()> {
+    let tokenizer = tokenizers::Tokenizer::from_str(
+        r#"{
+            "version": "1.0",
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": { "a": 0, "b": 1, "</s>": 2, "<pad>": 3 },
+                "unk_token": "<pad>"
+            }
+        }"#,
+    )
+    .map_err(anyhow::Error::msg)?;
+    let mut tokenizer = Tokenizer::from_hf(tokenizer);
+
+    let texts: Vec<String> = (0..200).map(|i| "a b ".repeat(i % 5)).collect();
+    let batch = tokenizer.encode_batch(&texts, false)?;
+    assert_eq!(batch.len(), texts.len());
+    for (i, encoding) in batch.iter().enumerate() {
+        assert_eq!(encoding.get_ids().len(), (i % 5) * 2);
+    }
+
+    tokenizer.set_pad_token("<pad>")?;
+    tokenizer.set_padding_side(PaddingDirection::Left);
+    let batch = tokenizer.encode_batch(&["b", "a b a"], false)?;
+    assert_eq!(batch[0].get_ids(), [3, 3, 1]);
+    assert_eq!(batch[0].get_attention_mask(), [0, 0, 1]);
+    assert_eq!(batch[1].get_ids(), [0, 1, 0]);
+    Ok(())
+}
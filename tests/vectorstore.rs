@@ -0,0 +1,30 @@
+#![cfg(feature = "vectorstore")]
+
+use anyhow::Result;
+use mospeada::vectorstore::{Metric, VectorStore};
+use serde_json::json;
+
+#[test]
+fn search_and_persist() -> Result<()> {
+    let mut store = VectorStore::new(2, Metric::Cosine);
+    store.add(vec![1., 0.], json!({ "text": "east" }))?;
+    store.add(vec![0., 1.], json!({ "text": "north" }))?;
+    store.add(vec![-1., 0.], json!({ "text": "west" }))?;
+    assert!(store.add(vec![1., 2., 3.], json!(null)).is_err());
+
+    let results = store.search(&[0.9, 0.1], 2)?;
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].id, 0);
+    assert_eq!(results[0].metadata["text"], "east");
+    assert_eq!(results[1].id, 1);
+
+    let path = std::env::temp_dir().join(format!("mospeada-vectors-{}.json", std::process::id()));
+    store.save(&path)?;
+    let loaded = VectorStore::load(&path)?;
+    std::fs::remove_file(&path)?;
+    assert_eq!(loaded, store);
+
+    let store = VectorStore::new(2, Metric::L2);
+    assert!(store.search(&[0., 0.], 3)?.is_empty());
+    Ok(())
+}